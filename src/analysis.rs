@@ -0,0 +1,596 @@
+//! Working out what a program is for without running it: follow
+//! every path through the code from where it starts, and look at which
+//! instructions it uses and how it uses them.
+
+use std::cmp::Equal;
+use std::collections::TreeMap;
+
+use instr;
+use platform::{Platform, Chip8, Chip8X, SuperChip, XoChip};
+
+/// Instructions beyond the original CHIP-8's, by where they come
+/// from. XO-CHIP includes all of SCHIP's.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Extension {
+    SuperChipOp,
+    XoChipOp,
+    Chip8XOp,
+    MachineCall, // 0NNN: a call into the VIP's own machine code
+}
+
+/// Which extension `ins` belongs to, if any, with the usual way of
+/// writing it.
+pub fn extension(ins: u16) -> Option<(Extension, &'static str)> {
+    let (op, x, y, n) = (ins >> 12, (ins >> 8) & 0xf, (ins >> 4) & 0xf, ins & 0xf);
+    let nn = ins & 0xff;
+    let ext = match (op, x, y, n) {
+        (0x0, 0x0, 0xe, 0x0) | (0x0, 0x0, 0xe, 0xe) => return None,
+        (0x0, 0x0, 0xc, 0x1..0xf) => (SuperChipOp, "00CN"),
+        (0x0, 0x0, 0xf, 0xb) => (SuperChipOp, "00FB"),
+        (0x0, 0x0, 0xf, 0xc) => (SuperChipOp, "00FC"),
+        (0x0, 0x0, 0xf, 0xd) => (SuperChipOp, "00FD"),
+        (0x0, 0x0, 0xf, 0xe) => (SuperChipOp, "00FE"),
+        (0x0, 0x0, 0xf, 0xf) => (SuperChipOp, "00FF"),
+        (0x0, 0x0, 0xd, 0x1..0xf) => (XoChipOp, "00DN"),
+        (0x0, 0x2, 0xa, 0x0) => (Chip8XOp, "02A0"),
+        (0x0, _, _, _) if ins != 0 => (MachineCall, "0NNN"),
+        (0x5, _, _, 0x1) => (Chip8XOp, "5XY1"),
+        (0x5, _, _, 0x2) => (XoChipOp, "5XY2"),
+        (0x5, _, _, 0x3) => (XoChipOp, "5XY3"),
+        (0xd, _, _, 0x0) => (SuperChipOp, "DXY0"),
+        (0xe, _, 0xf, 0x2) => (Chip8XOp, "EXF2"),
+        (0xe, _, 0xf, 0x5) => (Chip8XOp, "EXF5"),
+        (0xf, 0x0, 0x0, 0x0) => (XoChipOp, "F000 NNNN"),
+        (0xf, _, 0x0, 0x1) => (XoChipOp, "FN01"),
+        (0xf, 0x0, 0x0, 0x2) => (XoChipOp, "F002"),
+        (0xf, _, _, _) => match nn {
+            0x30 => (SuperChipOp, "FX30"),
+            0x3a => (XoChipOp, "FX3A"),
+            0x75 => (SuperChipOp, "FX75"),
+            0x85 => (SuperChipOp, "FX85"),
+            0xf8 => (Chip8XOp, "FXF8"),
+            0xfb => (Chip8XOp, "FXFB"),
+            _ => return None
+        },
+        _ => return None
+    };
+    Some(ext)
+}
+
+/// How `ins` is usually written, like `8XY4`.
+pub fn pattern(ins: u16) -> &'static str {
+    match extension(ins) {
+        Some((_, name)) => return name,
+        None => {}
+    }
+    match instr::decode(ins) {
+        instr::Sys(_) => "0NNN",
+        instr::Cls => "00E0",
+        instr::Ret => "00EE",
+        instr::Jp(_) => "1NNN",
+        instr::Call(_) => "2NNN",
+        instr::SeByte(..) => "3XNN",
+        instr::SneByte(..) => "4XNN",
+        instr::SeReg(..) => "5XY0",
+        instr::LdByte(..) => "6XNN",
+        instr::AddByte(..) => "7XNN",
+        instr::LdReg(..) => "8XY0",
+        instr::OrReg(..) => "8XY1",
+        instr::AndReg(..) => "8XY2",
+        instr::XorReg(..) => "8XY3",
+        instr::AddReg(..) => "8XY4",
+        instr::SubReg(..) => "8XY5",
+        instr::ShrReg(..) => "8XY6",
+        instr::SubnReg(..) => "8XY7",
+        instr::ShlReg(..) => "8XYE",
+        instr::SneReg(..) => "9XY0",
+        instr::LdI(_) => "ANNN",
+        instr::JpV0(_) => "BNNN",
+        instr::Rnd(..) => "CXNN",
+        instr::Drw(..) => "DXYN",
+        instr::Skp(_) => "EX9E",
+        instr::Sknp(_) => "EXA1",
+        instr::Audio => "F002",
+        instr::LdVxDt(_) => "FX07",
+        instr::LdVxK(_) => "FX0A",
+        instr::LdDtVx(_) => "FX15",
+        instr::LdStVx(_) => "FX18",
+        instr::AddIVx(_) => "FX1E",
+        instr::LdFVx(_) => "FX29",
+        instr::LdBVx(_) => "FX33",
+        instr::Pitch(_) => "FX3A",
+        instr::LdIVx(_) => "FX55",
+        instr::LdVxI(_) => "FX65",
+        instr::Unknown(_) => "????",
+    }
+}
+
+/// The kinds of instruction `family` sorts them into.
+pub static FAMILIES: [&'static str, ..10] = ["flow", "skip", "arithmetic", "memory", "display",
+                                             "timers", "sound", "keys", "i/o", "machine code"];
+
+/// The kind of instruction written as `pattern`.
+pub fn family(pattern: &str) -> &'static str {
+    match pattern {
+        "1NNN" | "2NNN" | "00EE" | "BNNN" | "00FD" => "flow",
+        "3XNN" | "4XNN" | "5XY0" | "9XY0" => "skip",
+        "6XNN" | "7XNN" | "CXNN" | "5XY1" => "arithmetic",
+        "ANNN" | "FX1E" | "FX29" | "FX30" | "FX33" | "FX55" | "FX65" | "FX75" | "FX85" |
+        "5XY2" | "5XY3" | "F000 NNNN" => "memory",
+        "00E0" | "DXYN" | "DXY0" | "00CN" | "00DN" | "00FB" | "00FC" | "00FE" | "00FF" |
+        "FN01" | "02A0" => "display",
+        "FX07" | "FX15" => "timers",
+        "FX18" | "F002" | "FX3A" => "sound",
+        "EX9E" | "EXA1" | "FX0A" | "EXF2" | "EXF5" => "keys",
+        "FXF8" | "FXFB" => "i/o",
+        "0NNN" => "machine code",
+        p if p.starts_with("8") => "arithmetic",
+        _ => "unknown"
+    }
+}
+
+/// Whether some interpreter would run `ins`. `0000` is taken to be
+/// empty memory rather than a call to machine code at 0.
+pub fn is_instruction(ins: u16) -> bool {
+    match instr::decode(ins) {
+        instr::Sys(0) => false,
+        instr::Unknown(_) => extension(ins).is_some(),
+        _ => true
+    }
+}
+
+/// Bytes an instruction takes up: XO-CHIP's `F000 NNNN` is the only
+/// one with four.
+fn size(ins: u16) -> uint {
+    if ins == 0xf000 { 4 } else { 2 }
+}
+
+/// Instructions that skip the next one.
+fn is_skip(ins: u16) -> bool {
+    match (ins >> 12, ins & 0xf) {
+        (0x3, _) | (0x4, _) | (0x5, 0x0) | (0x9, 0x0) | (0xe, _) => true,
+        _ => false
+    }
+}
+
+/// The code of a program, found by following every path from its
+/// load address.
+pub struct Analysis {
+    pub base: u16,
+    pub size: uint,
+    pub instrs: TreeMap<u16, u16>, // reachable instructions, by address
+    pub computed_jumps: Vec<u16>,  // BNNN, which may go anywhere past NNN
+    pub dead_ends: Vec<u16>,       // reached words that aren't instructions
+    pub pointers: Vec<(u16, u16)>, // where I is set, and to what
+}
+
+/// Analyze a program loaded at `base`, usually `ROM_LOC`.
+pub fn analyze(prgm: &[u8], base: u16) -> Analysis {
+    let start = base as uint;
+    let end = start + prgm.len();
+    let word = |addr: uint| if addr >= start && addr + 1 < end {
+        Some((prgm[addr - start] as u16) << 8 | prgm[addr + 1 - start] as u16)
+    } else {
+        None
+    };
+
+    let mut a = Analysis {
+        base: base,
+        size: prgm.len(),
+        instrs: TreeMap::new(),
+        computed_jumps: vec![],
+        dead_ends: vec![],
+        pointers: vec![],
+    };
+    let mut todo = vec![start];
+    loop {
+        let addr = match todo.pop() {
+            Some(addr) => addr,
+            None => break
+        };
+        if a.instrs.contains_key(&(addr as u16)) || a.dead_ends.contains(&(addr as u16)) {
+            continue;
+        }
+        let ins = match word(addr) {
+            Some(ins) => ins,
+            None => continue
+        };
+        if !is_instruction(ins) {
+            a.dead_ends.push(addr as u16);
+            continue;
+        }
+        a.instrs.insert(addr as u16, ins);
+        if ins >> 12 == 0xa {
+            a.pointers.push((addr as u16, ins & 0xfff));
+        } else if ins == 0xf000 {
+            word(addr + 2).map(|nnnn| a.pointers.push((addr as u16, nnnn)));
+        }
+
+        let next = addr + size(ins);
+        let nnn = (ins & 0xfff) as uint;
+        match ins >> 12 {
+            0x0 if ins == 0x00ee || ins == 0x00fd => {}, // return, exit
+            0x1 => todo.push(nnn),
+            0x2 => {
+                todo.push(next);
+                todo.push(nnn);
+            },
+            0xb => {
+                a.computed_jumps.push(addr as u16);
+                todo.push(nnn);
+            },
+            _ if is_skip(ins) => {
+                todo.push(next);
+                todo.push(next + word(next).map_or(2, size));
+            },
+            _ => todo.push(next)
+        }
+    }
+    a.dead_ends.sort();
+    a
+}
+
+/// A guess at the platform a program was written for.
+pub struct Detection {
+    pub platform: Platform,
+    pub confidence: f64,      // from 0 to 1
+    pub quirks: Vec<String>,  // the platform's preset, then single quirks
+    pub evidence: Vec<String>,
+}
+
+/// One minus a half for each independent sign.
+fn sureness(signs: uint) -> f64 {
+    let mut doubt = 1.0;
+    for _ in range(0, signs) {
+        doubt *= 0.5;
+    }
+    1.0 - doubt
+}
+
+impl Analysis {
+    /// The reachable instructions that belong to `ext`, by how
+    /// they're written, with where each is first used.
+    pub fn uses(&self, ext: Extension) -> Vec<(&'static str, u16)> {
+        let mut uses: Vec<(&'static str, u16)> = vec![];
+        for (&addr, &ins) in self.instrs.iter() {
+            match extension(ins) {
+                Some((e, name)) if e == ext && !uses.iter().any(|&(n, _)| n == name) => {
+                    uses.push((name, addr));
+                },
+                _ => {}
+            }
+        }
+        uses
+    }
+
+    /// How often each kind of instruction appears in the code, most
+    /// used first.
+    pub fn histogram(&self) -> Vec<(&'static str, uint)> {
+        let mut counts: TreeMap<&'static str, uint> = TreeMap::new();
+        for &ins in self.instrs.values() {
+            let p = pattern(ins);
+            let n = counts.find(&p).map_or(0, |&n| n);
+            counts.insert(p, n + 1);
+        }
+        let mut histogram: Vec<(&'static str, uint)> = counts.iter().map(|(&p, &n)| (p, n))
+                                                             .collect();
+        histogram.sort_by(|&(pa, a), &(pb, b)| match b.cmp(&a) {
+            Equal => pa.cmp(&pb),
+            order => order
+        });
+        histogram
+    }
+
+    /// The kinds of instruction the code uses, each with the
+    /// instructions of that kind, in `FAMILIES` order.
+    pub fn families(&self) -> Vec<(&'static str, Vec<&'static str>)> {
+        let histogram = self.histogram();
+        let mut families = vec![];
+        for &name in FAMILIES.iter().chain(["unknown"].iter()) {
+            let mut patterns: Vec<&'static str> = histogram.iter().map(|&(p, _)| p)
+                                                           .filter(|&p| family(p) == name)
+                                                           .collect();
+            if !patterns.is_empty() {
+                patterns.sort();
+                families.push((name, patterns));
+            }
+        }
+        families
+    }
+
+    pub fn uses_family(&self, name: &str) -> bool {
+        self.instrs.values().any(|&ins| family(pattern(ins)) == name)
+    }
+
+    /// Bytes of the program that were reached as code.
+    pub fn code_size(&self) -> uint {
+        self.instrs.values().map(|&ins| size(ins)).fold(0, |a, b| a + b)
+    }
+
+    /// Stretches of the program that aren't code and that I points
+    /// at shortly before drawing: likely sprites. Each runs until
+    /// code, or the next place I points; ones that touch are joined.
+    pub fn sprite_regions(&self) -> Vec<(u16, uint)> {
+        let start = self.base as uint;
+        let mut code = Vec::from_elem(self.size, false);
+        for (&addr, &ins) in self.instrs.iter() {
+            for i in range(addr as uint - start, addr as uint - start + size(ins)) {
+                if i < self.size {
+                    *code.get_mut(i) = true;
+                }
+            }
+        }
+
+        // Every place I points into the data, and which of them are
+        // drawn from.
+        let (mut targets, mut sprites): (Vec<uint>, Vec<uint>) = (vec![], vec![]);
+        for &(addr, target) in self.pointers.iter() {
+            let t = target as uint;
+            if t < start || t >= start + self.size || code[t - start] {
+                continue;
+            }
+            targets.push(t - start);
+            if self.draws_after(addr) {
+                sprites.push(t - start);
+            }
+        }
+        targets.sort();
+        sprites.sort();
+        sprites.dedup();
+
+        let mut regions: Vec<(u16, uint)> = vec![];
+        for &s in sprites.iter() {
+            let limit = targets.iter().map(|&t| t).find(|&t| t > s).unwrap_or(self.size);
+            let mut end = s;
+            while end < limit && !code[end] {
+                end += 1;
+            }
+            if !regions.is_empty() {
+                let last = regions.len() - 1;
+                let (at, len) = regions[last];
+                if at as uint - start + len == s {
+                    *regions.get_mut(last) = (at, len + end - s);
+                    continue;
+                }
+            }
+            regions.push(((s + start) as u16, end - s));
+        }
+        regions
+    }
+
+    /// Whether the code after setting I at `addr` draws with it,
+    /// before setting I again or going elsewhere.
+    fn draws_after(&self, addr: u16) -> bool {
+        let mut at = addr + self.instrs.find(&addr).map_or(2, |&ins| size(ins)) as u16;
+        for _ in range(0u, 8) {
+            let ins = match self.instrs.find(&at) {
+                Some(&ins) => ins,
+                None => return false
+            };
+            at += size(ins) as u16;
+            match family(pattern(ins)) {
+                "display" if ins >> 12 == 0xd => return true,
+                "flow" | "machine code" => return false,
+                _ if ins >> 12 == 0xa || ins == 0xf000 || ins & 0xf0ff == 0xf029 ||
+                     ins & 0xf0ff == 0xf030 => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Recommend a platform and quirks. Each kind of extension
+    /// instruction the code uses is a sign of its platform; with
+    /// none, the program is plain CHIP-8, which is surer the more of
+    /// it was reached.
+    pub fn detect(&self) -> Detection {
+        let mut evidence = vec![];
+        let list = |uses: &Vec<(&'static str, u16)>| {
+            uses.iter().map(|&(name, addr)| format!("{} at #{:03X}", name, addr))
+                .collect::<Vec<String>>().connect(", ")
+        };
+        let (schip, xo, c8x) = (self.uses(SuperChipOp), self.uses(XoChipOp),
+                                self.uses(Chip8XOp));
+        let calls = self.uses(MachineCall);
+        for &(ref uses, what) in [(&xo, "XO-CHIP"), (&schip, "SCHIP"), (&c8x, "CHIP-8X"),
+                                  (&calls, "machine code calls")].iter() {
+            if !uses.is_empty() {
+                evidence.push(format!("{}: {}", what, list(*uses)));
+            }
+        }
+
+        let (platform, mut confidence) = if !xo.is_empty() {
+            (XoChip, sureness(xo.len() + schip.len()))
+        } else if !schip.is_empty() && schip.len() >= c8x.len() {
+            (SuperChip, sureness(schip.len()))
+        } else if !c8x.is_empty() {
+            (Chip8X, sureness(c8x.len()))
+        } else if self.size > Chip8.max_rom_size() {
+            evidence.push(format!("{} bytes is too large for the VIP", self.size));
+            (SuperChip, 0.5)
+        } else {
+            let reached = self.code_size();
+            let covered = if self.size == 0 { 0.0 } else { reached as f64 / self.size as f64 };
+            (Chip8, 0.5 + 0.4 * if covered > 1.0 { 1.0 } else { covered })
+        };
+        if !schip.is_empty() && !c8x.is_empty() {
+            evidence.push("uses both SCHIP and CHIP-8X instructions".to_string());
+            confidence *= 0.5;
+        }
+        if !calls.is_empty() && platform != Chip8 {
+            // Only the VIP has machine code to call.
+            confidence *= 0.75;
+        }
+        if !self.dead_ends.is_empty() {
+            evidence.push(format!("{} path(s) run into words that aren't instructions",
+                                  self.dead_ends.len()));
+            confidence *= 0.8;
+        }
+
+        let mut quirks = vec![platform.quirks_preset().to_string()];
+        match self.shift_quirk() {
+            Some((spec, why)) => {
+                quirks.push(spec.to_string());
+                evidence.push(why);
+            },
+            None => {}
+        }
+        match self.load_store_quirk() {
+            Some((spec, why)) => {
+                quirks.push(spec.to_string());
+                evidence.push(why);
+            },
+            None => {}
+        }
+        Detection { platform: platform, confidence: confidence, quirks: quirks,
+                    evidence: evidence }
+    }
+
+    /// `8XY6` and `8XYE` with VY always V0 were written for shifting
+    /// VX in place; with other VYs, for shifting VY into VX.
+    fn shift_quirk(&self) -> Option<(&'static str, String)> {
+        let (mut in_place, mut from_vy) = (0u, 0u);
+        for &ins in self.instrs.values() {
+            let (x, y) = ((ins >> 8) & 0xf, (ins >> 4) & 0xf);
+            match (ins >> 12, ins & 0xf) {
+                (0x8, 0x6) | (0x8, 0xe) if x != y => {
+                    if y == 0 { in_place += 1 } else { from_vy += 1 }
+                },
+                _ => {}
+            }
+        }
+        match (in_place, from_vy) {
+            (n, 0) if n > 0 => Some(("shift=vx", format!("{} shift(s) ignore VY", n))),
+            (0, n) if n > 0 => Some(("shift=vy", format!("{} shift(s) read VY", n))),
+            _ => None
+        }
+    }
+
+    /// After `FX55` or `FX65`, loading or storing again without
+    /// setting I means walking through memory by the VIP's
+    /// increments; stepping I with `FX1E` means it was left alone.
+    fn load_store_quirk(&self) -> Option<(&'static str, String)> {
+        let (mut increment, mut keep) = (0u, 0u);
+        for (&addr, &ins) in self.instrs.iter() {
+            if ins >> 12 != 0xf || (ins & 0xff != 0x55 && ins & 0xff != 0x65) {
+                continue;
+            }
+            let mut at = addr + 2;
+            loop {
+                let next = match self.instrs.find(&at) {
+                    Some(&next) => next,
+                    None => break
+                };
+                match (next >> 12, next & 0xff) {
+                    (0xf, 0x55) | (0xf, 0x65) => increment += 1,
+                    (0xf, 0x1e) => keep += 1,
+                    (0x0, _) | (0x1, _) | (0x2, _) | (0xa, _) | (0xb, _) | (0xd, _) |
+                    (0xf, 0x00) | (0xf, 0x29) | (0xf, 0x30) | (0xf, 0x33) => break,
+                    _ if is_skip(next) => break,
+                    _ => {
+                        at += size(next) as u16;
+                        continue;
+                    }
+                }
+                break;
+            }
+        }
+        if increment > keep {
+            Some(("load_store=increment",
+                  format!("{} load/store(s) carry on from where I was left", increment)))
+        } else if keep > increment {
+            Some(("load_store=keep", format!("{} load/store(s) step I themselves", keep)))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{analyze, extension, family, pattern, SuperChipOp, XoChipOp};
+    use mem::ROM_LOC;
+    use platform::{Chip8, SuperChip, XoChip};
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension(0x00ff), Some((SuperChipOp, "00FF")));
+        assert_eq!(extension(0xf000), Some((XoChipOp, "F000 NNNN")));
+        assert_eq!(extension(0xd120), Some((SuperChipOp, "DXY0")));
+        assert_eq!(extension(0x00e0), None);
+        assert_eq!(extension(0x6012), None);
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert_eq!(pattern(0x8124), "8XY4");
+        assert_eq!(pattern(0xd12f), "DXYN");
+        assert_eq!(pattern(0xd120), "DXY0");
+        assert_eq!(family(pattern(0xf315)), "timers");
+        assert_eq!(family(pattern(0xe3a1)), "keys");
+        assert_eq!(family(pattern(0x812e)), "arithmetic");
+    }
+
+    #[test]
+    fn test_summary() {
+        let a = analyze([0xa2, 0x0c,   // 200: LD I, #20C
+                         0x60, 0x00,   // 202: LD V0, 0
+                         0xd0, 0x05,   // 204: DRW V0, V0, 5
+                         0xa2, 0x11,   // 206: LD I, #211
+                         0xf0, 0x65,   // 208: LD V0, [I]
+                         0x12, 0x0a,   // 20A: JP 20A
+                         0xf0, 0x90, 0xf0, 0x90, 0x90, // 20C: an `A`
+                         0x2a],        // 211: a table
+                        ROM_LOC);
+        assert_eq!(a.histogram(), vec![("ANNN", 2), ("1NNN", 1), ("6XNN", 1), ("DXYN", 1),
+                                       ("FX65", 1)]);
+        assert_eq!(a.families(), vec![("flow", vec!["1NNN"]), ("arithmetic", vec!["6XNN"]),
+                                      ("memory", vec!["ANNN", "FX65"]),
+                                      ("display", vec!["DXYN"])]);
+        assert!(a.uses_family("display") && !a.uses_family("timers"));
+        assert_eq!(a.code_size(), 12);
+        assert_eq!(a.sprite_regions(), vec![(0x20c, 5)]);
+    }
+
+    #[test]
+    fn test_walk() {
+        // Jumps over data that looks like SCHIP's `00FF`, and skips
+        // over XO-CHIP's long `i :=`.
+        let a = analyze([0x12, 0x04, 0x00, 0xff,   // 200: JP 204; data
+                         0x30, 0x00, 0xf0, 0x00,   // 204: SE V0, 0; i := long
+                         0x03, 0x00, 0x12, 0x0a],  // 208: 0300; 20A: JP 20A
+                        ROM_LOC);
+        assert_eq!(a.instrs.keys().map(|&k| k).collect::<Vec<u16>>(),
+                   vec![0x200, 0x204, 0x206, 0x20a]);
+        assert!(a.dead_ends.is_empty());
+        let d = a.detect();
+        assert_eq!(d.platform, XoChip);
+        assert_eq!(d.quirks, vec!["octo".to_string()]);
+    }
+
+    #[test]
+    fn test_detect_schip() {
+        let d = analyze([0x00, 0xff,   // 200: high-res
+                         0x81, 0x06,   // 202: SHR V1 (VY ignored)
+                         0xf2, 0x65,   // 204: LD V2, [I]
+                         0xf1, 0x1e,   // 206: ADD I, V1
+                         0xf2, 0x65,   // 208: LD V2, [I]
+                         0x00, 0xfd], ROM_LOC).detect();
+        assert_eq!(d.platform, SuperChip);
+        assert_eq!(d.confidence, 0.75);
+        assert_eq!(d.quirks, vec!["schip".to_string(), "shift=vx".to_string(),
+                                  "load_store=keep".to_string()]);
+    }
+
+    #[test]
+    fn test_detect_chip8() {
+        let d = analyze([0x81, 0x26,   // 200: SHR V1, V2
+                         0xf2, 0x55,   // 202: LD [I], V2
+                         0xf2, 0x55,   // 204: LD [I], V2
+                         0x12, 0x06], ROM_LOC).detect();
+        assert_eq!(d.platform, Chip8);
+        assert_eq!(d.confidence, 0.9);
+        assert_eq!(d.quirks, vec!["vip".to_string(), "shift=vy".to_string(),
+                                  "load_store=increment".to_string()]);
+    }
+}
@@ -0,0 +1,248 @@
+use std::ascii::StrAsciiExt;
+use std::collections::HashMap;
+use std::num;
+
+use instr;
+use instr::Instruction;
+
+/// An operand before labels are resolved.
+#[deriving(Clone, PartialEq, Show)]
+enum Operand {
+    Reg(u8),
+    I,
+    IndirectI, // [I]
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Num(u16),
+    Label(String),
+}
+
+struct Line {
+    number: uint,
+    mnemonic: String,
+    operands: Vec<Operand>,
+}
+
+/// Assemble a program in the syntax `instr` disassembles to, for
+/// loading at `base`. Besides instructions, a line may define a
+/// label (`loop:`), or emit data with `DB` (bytes) or `DW` (words).
+/// Numbers may be decimal, or hex with a `#`, `$` or `0x` prefix;
+/// comments start with `;`.
+pub fn assemble(src: &str, base: u16) -> Result<Vec<u8>, String> {
+    let mut labels = HashMap::new();
+    let mut lines = vec![];
+    let mut addr = base as uint;
+
+    for (i, text) in src.lines().enumerate() {
+        let number = i + 1;
+        let mut text = text.splitn(';', 1).next().unwrap().trim();
+        match text.find(':') {
+            Some(colon) => {
+                let label = text.slice_to(colon).trim();
+                if label.is_empty() || label.contains_char(' ') {
+                    return Err(format!("line {}: bad label `{}`", number, label));
+                }
+                if labels.insert(label.to_string(), addr as u16) == false {
+                    return Err(format!("line {}: label `{}` defined twice", number, label));
+                }
+                text = text.slice_from(colon + 1).trim();
+            },
+            None => {}
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = match text.find(|c: char| c.is_whitespace()) {
+            Some(sp) => (text.slice_to(sp), text.slice_from(sp).trim()),
+            None => (text, "")
+        };
+        let mut operands = vec![];
+        if !rest.is_empty() {
+            for op in rest.split(',') {
+                operands.push(try!(parse_operand(op.trim()).map_err(|e| {
+                    format!("line {}: {}", number, e)
+                })));
+            }
+        }
+        let mnemonic = mnemonic.to_ascii_upper();
+        addr += match mnemonic.as_slice() {
+            "DB" => operands.len(),
+            "DW" => operands.len() * 2,
+            _ => 2
+        };
+        lines.push(Line { number: number, mnemonic: mnemonic, operands: operands });
+    }
+
+    let mut out = vec![];
+    for line in lines.iter() {
+        try!(emit(line, &labels, &mut out).map_err(|e| {
+            format!("line {}: {}", line.number, e)
+        }));
+    }
+    Ok(out)
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    let (digits, radix) = if s.starts_with("#") || s.starts_with("$") {
+        (s.slice_from(1), 16)
+    } else if s.starts_with("0x") || s.starts_with("0X") {
+        (s.slice_from(2), 16)
+    } else if s.starts_with("%") {
+        (s.slice_from(1), 2)
+    } else {
+        (s, 10)
+    };
+    num::from_str_radix::<u16>(digits, radix)
+}
+
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    let upper = s.to_ascii_upper();
+    let op = match upper.as_slice() {
+        "I" => I,
+        "[I]" => IndirectI,
+        "DT" => Dt,
+        "ST" => St,
+        "K" => K,
+        "F" => F,
+        "B" => B,
+        "" => return Err("missing operand".to_string()),
+        u if u.len() == 2 && u.starts_with("V") => {
+            match num::from_str_radix::<u8>(u.slice_from(1), 16) {
+                Some(r) => Reg(r),
+                None => return Err(format!("bad register `{}`", s))
+            }
+        },
+        _ => match parse_number(s) {
+            Some(n) => Num(n),
+            None if s.chars().all(|c| c.is_alphanumeric() || c == '_') &&
+                    !s.char_at(0).is_digit() => Label(s.to_string()),
+            None => return Err(format!("bad operand `{}`", s))
+        }
+    };
+    Ok(op)
+}
+
+fn value(op: &Operand, labels: &HashMap<String, u16>, max: u16) -> Result<u16, String> {
+    let n = match *op {
+        Num(n) => n,
+        Label(ref l) => match labels.find(l) {
+            Some(&addr) => addr,
+            None => return Err(format!("undefined label `{}`", l))
+        },
+        _ => return Err(format!("expected a number, found {}", op))
+    };
+    if n > max {
+        return Err(format!("{} is out of range (max {})", n, max));
+    }
+    Ok(n)
+}
+
+fn emit(line: &Line, labels: &HashMap<String, u16>, out: &mut Vec<u8>) -> Result<(), String> {
+    let ops = line.operands.as_slice();
+    let addr = |op: &Operand| value(op, labels, 0xfff);
+    let byte = |op: &Operand| value(op, labels, 0xff).map(|n| n as u8);
+
+    let ins: Instruction = match (line.mnemonic.as_slice(), ops) {
+        ("DB", ops) => {
+            for op in ops.iter() {
+                out.push(try!(byte(op)));
+            }
+            return Ok(());
+        },
+        ("DW", ops) => {
+            for op in ops.iter() {
+                let w = try!(value(op, labels, 0xffff));
+                out.push((w >> 8) as u8);
+                out.push(w as u8);
+            }
+            return Ok(());
+        },
+        ("CLS", []) => instr::Cls,
+        ("RET", []) => instr::Ret,
+        ("AUDIO", []) => instr::Audio,
+        ("SYS", [ref a]) => instr::Sys(try!(addr(a))),
+        ("JP", [Reg(0), ref a]) => instr::JpV0(try!(addr(a))),
+        ("JP", [ref a]) => instr::Jp(try!(addr(a))),
+        ("CALL", [ref a]) => instr::Call(try!(addr(a))),
+        ("SE", [Reg(x), Reg(y)]) => instr::SeReg(x, y),
+        ("SE", [Reg(x), ref n]) => instr::SeByte(x, try!(byte(n))),
+        ("SNE", [Reg(x), Reg(y)]) => instr::SneReg(x, y),
+        ("SNE", [Reg(x), ref n]) => instr::SneByte(x, try!(byte(n))),
+        ("LD", [Reg(x), Reg(y)]) => instr::LdReg(x, y),
+        ("LD", [Reg(x), Dt]) => instr::LdVxDt(x),
+        ("LD", [Reg(x), K]) => instr::LdVxK(x),
+        ("LD", [Reg(x), IndirectI]) => instr::LdVxI(x),
+        ("LD", [Reg(x), ref n]) => instr::LdByte(x, try!(byte(n))),
+        ("LD", [I, ref a]) => instr::LdI(try!(addr(a))),
+        ("LD", [Dt, Reg(x)]) => instr::LdDtVx(x),
+        ("LD", [St, Reg(x)]) => instr::LdStVx(x),
+        ("LD", [F, Reg(x)]) => instr::LdFVx(x),
+        ("LD", [B, Reg(x)]) => instr::LdBVx(x),
+        ("LD", [IndirectI, Reg(x)]) => instr::LdIVx(x),
+        ("ADD", [I, Reg(x)]) => instr::AddIVx(x),
+        ("ADD", [Reg(x), Reg(y)]) => instr::AddReg(x, y),
+        ("ADD", [Reg(x), ref n]) => instr::AddByte(x, try!(byte(n))),
+        ("OR", [Reg(x), Reg(y)]) => instr::OrReg(x, y),
+        ("AND", [Reg(x), Reg(y)]) => instr::AndReg(x, y),
+        ("XOR", [Reg(x), Reg(y)]) => instr::XorReg(x, y),
+        ("SUB", [Reg(x), Reg(y)]) => instr::SubReg(x, y),
+        ("SUBN", [Reg(x), Reg(y)]) => instr::SubnReg(x, y),
+        ("SHR", [Reg(x)]) => instr::ShrReg(x, x),
+        ("SHR", [Reg(x), Reg(y)]) => instr::ShrReg(x, y),
+        ("SHL", [Reg(x)]) => instr::ShlReg(x, x),
+        ("SHL", [Reg(x), Reg(y)]) => instr::ShlReg(x, y),
+        ("RND", [Reg(x), ref n]) => instr::Rnd(x, try!(byte(n))),
+        ("DRW", [Reg(x), Reg(y), ref n]) => instr::Drw(x, y, try!(value(n, labels, 0xf)) as u8),
+        ("SKP", [Reg(x)]) => instr::Skp(x),
+        ("SKNP", [Reg(x)]) => instr::Sknp(x),
+        ("PITCH", [Reg(x)]) => instr::Pitch(x),
+        (m, _) => return Err(format!("bad instruction `{}` with {} operand(s)", m, ops.len()))
+    };
+    let w = ins.encode();
+    out.push((w >> 8) as u8);
+    out.push(w as u8);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::assemble;
+    use instr::decode;
+
+    #[test]
+    fn test_assemble() {
+        let prgm = assemble("
+            ; count V0 up forever
+                    LD V0, 0
+            loop:   ADD V0, #01
+                    LD I, sprite
+                    DRW V0, V1, 1
+                    JP loop
+            sprite: DB %10000001, $7E
+        ", 0x200).unwrap();
+        assert_eq!(prgm, vec![0x60, 0x00, 0x70, 0x01, 0xa2, 0x0a, 0xd0, 0x11,
+                              0x12, 0x02, 0x81, 0x7e]);
+    }
+
+    #[test]
+    fn test_disassembly_roundtrip() {
+        for ins in range(0u, 0x10000) {
+            let text = format!("{}", decode(ins as u16));
+            let prgm = assemble(text.as_slice(), 0x200).unwrap();
+            assert_eq!(prgm, vec![(ins >> 8) as u8, ins as u8]);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(assemble("JP nowhere", 0x200).is_err());
+        assert!(assemble("LD V0, 256", 0x200).is_err());
+        assert!(assemble("FOO V0", 0x200).is_err());
+        assert!(assemble("a: CLS\na: CLS", 0x200).is_err());
+        assert!(assemble("LD VG, 1", 0x200).is_err());
+    }
+}
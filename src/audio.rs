@@ -0,0 +1,219 @@
+use std::io::{IoResult, Seek, SeekSet};
+
+pub static SAMPLE_RATE: uint = 44100;
+pub static PATTERN_SIZE: uint = 16;
+static DEFAULT_PITCH: u8 = 64;
+pub static DEFAULT_BEEP_FREQUENCY: f64 = 440.0;
+pub static DEFAULT_VOLUME: f64 = 0.25;
+
+/// The sound state a program controls: the XO-CHIP audio pattern
+/// buffer and playback pitch. Without a pattern, the VM sounds a
+/// plain square wave beeper.
+#[deriving(Clone)]
+pub struct Voice {
+    pub pattern: Option<[u8, ..PATTERN_SIZE]>,
+    pub pitch: u8,
+}
+
+impl Voice {
+    pub fn new() -> Voice {
+        Voice { pattern: None, pitch: DEFAULT_PITCH }
+    }
+
+    pub fn load_pattern(&mut self, src: &[u8]) {
+        let mut pattern = [0u8, ..PATTERN_SIZE];
+        pattern.copy_from(src);
+        self.pattern = Some(pattern);
+    }
+}
+
+/// Turns the sound timer and voice into samples, one 60 Hz frame
+/// at a time.
+pub struct Synth {
+    rate: uint,
+    phase: f64, // position in the waveform: cycles, or pattern bits
+    amplitude: i16,
+    beep_frequency: f64,
+}
+
+impl Synth {
+    pub fn new(rate: uint) -> Synth {
+        let mut synth = Synth { rate: rate, phase: 0.0, amplitude: 0, beep_frequency: 0.0 };
+        synth.set_volume(DEFAULT_VOLUME);
+        synth.set_beep_frequency(DEFAULT_BEEP_FREQUENCY);
+        synth
+    }
+
+    /// Set the output volume, from 0.0 (silent) to 1.0 (full scale).
+    pub fn set_volume(&mut self, volume: f64) {
+        let volume = volume.max(0.0).min(1.0);
+        self.amplitude = (volume * 32767.0) as i16;
+    }
+
+    /// Set the pitch of the plain beeper, in Hz.
+    pub fn set_beep_frequency(&mut self, freq: f64) {
+        self.beep_frequency = freq;
+    }
+
+    pub fn samples_per_frame(&self) -> uint {
+        self.rate / 60
+    }
+
+    /// Append one frame of samples to `out`. The voice is silent
+    /// unless `active` (the sound timer is nonzero).
+    pub fn render(&mut self, voice: &Voice, active: bool, out: &mut Vec<i16>) {
+        let n = self.samples_per_frame();
+        if !active {
+            self.phase = 0.0;
+            out.grow(n, &0);
+            return;
+        }
+
+        match voice.pattern {
+            None => {
+                let step = self.beep_frequency / self.rate as f64;
+                for _ in range(0, n) {
+                    let high = self.phase < 0.5;
+                    out.push(if high { self.amplitude } else { -self.amplitude });
+                    self.phase = (self.phase + step) % 1.0;
+                }
+            },
+            Some(ref pattern) => {
+                // XO-CHIP plays the 128 pattern bits at 4000 bits/s
+                // when the pitch is 64, an octave per 48 steps.
+                let bits = (PATTERN_SIZE * 8) as f64;
+                let bit_rate = 4000.0 * (2.0f64).powf((voice.pitch as f64 - 64.0) / 48.0);
+                let step = bit_rate / self.rate as f64;
+                for _ in range(0, n) {
+                    let bit = self.phase as uint;
+                    let byte = pattern[bit / 8];
+                    let high = (byte >> (7 - bit % 8)) & 0x1 == 1;
+                    out.push(if high { self.amplitude } else { -self.amplitude });
+                    self.phase = (self.phase + step) % bits;
+                }
+            }
+        }
+    }
+}
+
+/// Writes 16-bit mono PCM to a WAV stream. The header's length
+/// fields are patched in by `finish`, so the stream must be
+/// seekable.
+pub struct WavWriter<W> {
+    w: W,
+    samples: u32,
+}
+
+impl<W: Writer + Seek> WavWriter<W> {
+    pub fn new(mut w: W, rate: uint) -> IoResult<WavWriter<W>> {
+        let rate = rate as u32;
+        try!(w.write_str("RIFF"));
+        try!(w.write_le_u32(36)); // patched by finish
+        try!(w.write_str("WAVE"));
+        try!(w.write_str("fmt "));
+        try!(w.write_le_u32(16));       // fmt chunk size
+        try!(w.write_le_u16(1));        // PCM
+        try!(w.write_le_u16(1));        // mono
+        try!(w.write_le_u32(rate));
+        try!(w.write_le_u32(rate * 2)); // byte rate
+        try!(w.write_le_u16(2));        // block align
+        try!(w.write_le_u16(16));       // bits per sample
+        try!(w.write_str("data"));
+        try!(w.write_le_u32(0));        // patched by finish
+        Ok(WavWriter { w: w, samples: 0 })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> IoResult<()> {
+        for &s in samples.iter() {
+            try!(self.w.write_le_i16(s));
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> IoResult<W> {
+        let data_len = self.samples * 2;
+        try!(self.w.seek(4, SeekSet));
+        try!(self.w.write_le_u32(36 + data_len));
+        try!(self.w.seek(40, SeekSet));
+        try!(self.w.write_le_u32(data_len));
+        try!(self.w.flush());
+        Ok(self.w)
+    }
+}
+
+/// Synthesizes the VM's sound output and records it to a WAV
+/// stream, one frame at a time.
+pub struct Recorder<W> {
+    synth: Synth,
+    wav: WavWriter<W>,
+    buf: Vec<i16>,
+}
+
+impl<W: Writer + Seek> Recorder<W> {
+    pub fn new(w: W, synth: Synth) -> IoResult<Recorder<W>> {
+        let wav = try!(WavWriter::new(w, synth.rate));
+        Ok(Recorder { synth: synth, wav: wav, buf: vec![] })
+    }
+
+    pub fn frame(&mut self, voice: &Voice, active: bool) -> IoResult<()> {
+        self.buf.truncate(0);
+        self.synth.render(voice, active, &mut self.buf);
+        self.wav.write_samples(self.buf.as_slice())
+    }
+
+    pub fn finish(self) -> IoResult<W> {
+        self.wav.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Synth, Voice, WavWriter};
+    use std::io::BufWriter;
+
+    #[test]
+    fn test_silent_when_inactive() {
+        let mut synth = Synth::new(44100);
+        let mut out = vec![];
+        synth.render(&Voice::new(), false, &mut out);
+        assert_eq!(out.len(), 735);
+        assert!(out.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn test_beeper() {
+        let mut synth = Synth::new(44100);
+        let mut out = vec![];
+        synth.render(&Voice::new(), true, &mut out);
+        assert_eq!(out.len(), 735);
+        assert!(out.iter().any(|&s| s > 0));
+        assert!(out.iter().any(|&s| s < 0));
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut voice = Voice::new();
+        voice.load_pattern([0xff, ..16].as_slice());
+        let mut synth = Synth::new(44100);
+        let mut out = vec![];
+        synth.render(&voice, true, &mut out);
+        assert!(out.iter().all(|&s| s > 0));
+    }
+
+    #[test]
+    fn test_wav_header() {
+        let mut buf = [0u8, ..48];
+        {
+            let w = BufWriter::new(buf.as_mut_slice());
+            let mut wav = WavWriter::new(w, 8000).unwrap();
+            wav.write_samples([1i16, -1].as_slice()).unwrap();
+            wav.finish().unwrap();
+        }
+        assert_eq!(buf.slice(0, 4), b"RIFF");
+        assert_eq!(buf[4], 40); // 36 + 4 bytes of data
+        assert_eq!(buf.slice(8, 12), b"WAVE");
+        assert_eq!(buf[40], 4);
+        assert_eq!(buf.slice(44, 48), [0x01, 0x00, 0xff, 0xff].as_slice());
+    }
+}
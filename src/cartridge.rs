@@ -0,0 +1,267 @@
+//! Octo cartridges: GIF images that carry a program, along with the
+//! settings it was written for, hidden in their pixels.
+//!
+//! The low four bits of each pixel's color index hold half a byte,
+//! high half first, reading row by row and frame after frame. The
+//! bytes are a 32-bit big-endian length, and then that much JSON:
+//!
+//!     {"program": "<Octo source>", "options": {"tickrate": 20, ...}}
+
+use std::collections::TreeMap;
+use std::str;
+
+use serialize::json;
+use serialize::json::Json;
+
+use config::Settings;
+use gif;
+use mem;
+use octo;
+use palette;
+use platform::{Chip8, SuperChip, XoChip};
+use timing::PerFrame;
+
+// Larger than any program and its options could need.
+static MAX_PAYLOAD: uint = 1 << 24;
+
+pub struct Cartridge {
+    pub prgm: Vec<u8>,
+    pub settings: Settings,   // from the options
+    pub ignored: Vec<String>, // options fries can't honour
+}
+
+pub fn decode(data: &[u8]) -> Result<Cartridge, String> {
+    let payload = try!(payload(data));
+    let text = match str::from_utf8(payload.as_slice()) {
+        Some(text) => text,
+        None => return Err("the cartridge's data isn't text".to_string())
+    };
+    let root = try!(json::from_str(text).map_err(|e| {
+        format!("the cartridge's data isn't valid JSON: {}", e)
+    }));
+
+    let source = match root.find(&"program".to_string()).and_then(|p| p.as_string()) {
+        Some(source) => source,
+        None => return Err("the cartridge has no program".to_string())
+    };
+    match octo::unsupported(source) {
+        Some((directive, line)) => {
+            return Err(format!("the cartridge's program uses `{}` (line {}), which fries's \
+                                Octo compiler doesn't support", directive, line));
+        },
+        None => {}
+    }
+    let prgm = try!(octo::compile(source, mem::ROM_LOC).map_err(|e| {
+        format!("the cartridge's program doesn't compile: {}", e)
+    }));
+
+    let mut cart = Cartridge { prgm: prgm, settings: Settings::new(), ignored: vec![] };
+    match root.find(&"options".to_string()).and_then(|o| o.as_object()) {
+        Some(options) => try!(apply_options(options, &mut cart)),
+        None => {}
+    }
+    Ok(cart)
+}
+
+fn payload(data: &[u8]) -> Result<Vec<u8>, String> {
+    let frames = try!(gif::read_frames(data));
+    let mut nibbles = frames.iter().flat_map(|f| f.indices.iter()).map(|&i| i & 0xf);
+    let mut byte = || match (nibbles.next(), nibbles.next()) {
+        (Some(hi), Some(lo)) => Some(hi << 4 | lo),
+        _ => None
+    };
+    let not_cartridge = || Err("the GIF isn't an Octo cartridge, or is damaged".to_string());
+
+    let mut len = 0u;
+    for _ in range(0u, 4) {
+        match byte() {
+            Some(b) => len = len << 8 | b as uint,
+            None => return not_cartridge()
+        }
+    }
+    if len == 0 || len > MAX_PAYLOAD {
+        return not_cartridge();
+    }
+    let mut payload = vec![];
+    for _ in range(0, len) {
+        match byte() {
+            Some(b) => payload.push(b),
+            None => return not_cartridge()
+        }
+    }
+    Ok(payload)
+}
+
+/// Turn Octo's options into settings. Colors go to the palette by
+/// plane, and the largest program size picks the platform.
+fn apply_options(options: &TreeMap<String, Json>, cart: &mut Cartridge) -> Result<(), String> {
+    for (key, val) in options.iter() {
+        let bad = |expected: &str| {
+            Err(format!("the cartridge's option `{}` should be {}", key, expected))
+        };
+        let flag = match val.as_boolean() {
+            Some(b) => b,
+            None => false
+        };
+        match key.as_slice() {
+            "tickrate" => cart.settings.speed = match val.as_f64() {
+                Some(n) if n >= 1.0 => Some(PerFrame(n as uint)),
+                _ => return bad("a positive number")
+            },
+            "shiftQuirks" => {
+                cart.settings.quirks.push(if flag { "shift=vx" } else { "shift=vy" }.to_string());
+            },
+            "loadStoreQuirks" => {
+                let spec = if flag { "load_store=keep" } else { "load_store=increment" };
+                cart.settings.quirks.push(spec.to_string());
+            },
+            "backgroundColor" | "fillColor" | "fillColor2" | "blendColor" => {
+                let index = match key.as_slice() {
+                    "backgroundColor" => 0,
+                    "fillColor" => 1,
+                    "fillColor2" => 2,
+                    _ => 3
+                };
+                match val.as_string().and_then(|s| palette::parse_color(s)) {
+                    Some(color) => cart.settings.colors.push((index, color)),
+                    None => return bad("a color like \"#FF6600\"")
+                }
+            },
+            "maxSize" => cart.settings.platform = match val.as_f64() {
+                Some(n) if n <= Chip8.max_rom_size() as f64 => Some(Chip8),
+                Some(n) if n <= SuperChip.max_rom_size() as f64 => Some(SuperChip),
+                Some(_) => Some(XoChip),
+                None => return bad("a number")
+            },
+            "vfOrderQuirks" | "clipQuirks" | "vBlankQuirks" | "jumpQuirks" |
+            "logicQuirks" if flag => cart.ignored.push(key.clone()),
+            "screenRotation" if val.as_f64().map_or(false, |r| r != 0.0) => {
+                cart.ignored.push(key.clone());
+            },
+            _ => {} // display and editor preferences
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::decode;
+    use platform::{Chip8, SuperChip};
+    use quirks::{ShiftVx, ShiftVy, IncrementIndex};
+    use timing::PerFrame;
+
+    /// A one-frame GIF of `indices`, with a 16-color table and LZW
+    /// codes that never grow past 5 bits.
+    fn gif(width: uint, indices: &[u8]) -> Vec<u8> {
+        let height = (indices.len() + width - 1) / width;
+        let mut pixels = indices.to_vec();
+        pixels.grow(width * height - indices.len(), &0);
+
+        let (w, h) = ([width as u8, (width >> 8) as u8], [height as u8, (height >> 8) as u8]);
+        let mut out = b"GIF89a".to_vec();
+        out.push_all(w);
+        out.push_all(h);
+        out.push_all([0xf3, 0, 0]); // a 16-color global table
+        for i in range(0u8, 16) {
+            out.push_all([i * 16, 0, 0]);
+        }
+        out.push_all([0x2c, 0, 0, 0, 0, w[0], w[1], h[0], h[1], 0, 4]);
+
+        let mut codes = vec![];
+        let (mut acc, mut bits) = (0u32, 0u);
+        {
+            let mut put = |code: u8| {
+                acc |= (code as u32) << bits;
+                bits += 5;
+                while bits >= 8 {
+                    codes.push(acc as u8);
+                    acc >>= 8;
+                    bits -= 8;
+                }
+            };
+            for chunk in pixels.as_slice().chunks(10) {
+                put(16); // clear, before the table needs wider codes
+                for &p in chunk.iter() {
+                    put(p);
+                }
+            }
+            put(17);
+        }
+        if bits > 0 {
+            codes.push(acc as u8);
+        }
+        for block in codes.as_slice().chunks(255) {
+            out.push(block.len() as u8);
+            out.push_all(block);
+        }
+        out.push_all([0, 0x3b]);
+        out
+    }
+
+    fn cartridge(json: &str) -> Vec<u8> {
+        let len = json.len();
+        let mut data = vec![(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+        data.push_all(json.as_bytes());
+        let mut indices = vec![];
+        for &b in data.iter() {
+            indices.push(b >> 4);
+            indices.push(b & 0xf);
+        }
+        gif(32, indices.as_slice())
+    }
+
+    #[test]
+    fn test_decode() {
+        let cart = decode(cartridge(r#"{"program": ": main v0 := 1 # set up",
+            "options": {"tickrate": 30, "shiftQuirks": true, "loadStoreQuirks": false,
+                        "fillColor": "#FF6600", "maxSize": 3583, "clipQuirks": true,
+                        "jumpQuirks": false, "fontStyle": "octo"}}"#).as_slice()).unwrap();
+        assert_eq!(cart.prgm, vec![0x12, 0x02, 0x60, 0x01]);
+        assert_eq!(cart.settings.speed, Some(PerFrame(30)));
+        assert_eq!(cart.settings.platform, Some(SuperChip));
+        assert_eq!(cart.settings.colors, vec![(1, [0xff, 0x66, 0x00, 0xff])]);
+        assert_eq!(cart.settings.quirks().unwrap().shift, ShiftVx);
+        assert_eq!(cart.ignored, vec!["clipQuirks".to_string()]);
+    }
+
+    #[test]
+    fn test_fixture() {
+        // Two 48x24 frames with a 256-color table: cartridge art in the
+        // high nibble of each index, and the program in the low one.
+        let cart = decode(include_bin!("../tests/data/bounce.gif")).unwrap();
+        assert_eq!(cart.prgm, vec![0x12, 0x14,
+                                   0x60, 0xf0, 0xf0, 0x60,  // ball
+                                   0x62, 0x02, 0xf2, 0x15,  // wait
+                                   0xf2, 0x07, 0x42, 0x00, 0x12, 0x12, 0x12, 0x0a,
+                                   0x00, 0xee,
+                                   0x60, 0x1e, 0x61, 0x0e,  // main
+                                   0x63, 0x01, 0xa2, 0x02,
+                                   0xd0, 0x14, 0x22, 0x06, 0xd0, 0x14, 0x80, 0x34,
+                                   0x40, 0x3c, 0x63, 0xff, 0x40, 0x00, 0x63, 0x01,
+                                   0x12, 0x1c]);
+        assert_eq!(cart.settings.speed, Some(PerFrame(20)));
+        assert_eq!(cart.settings.platform, Some(Chip8));
+        assert_eq!(cart.settings.colors, vec![(0, [0x99, 0x66, 0x00, 0xff]),
+                                              (3, [0x66, 0x22, 0x00, 0xff]),
+                                              (1, [0xff, 0xcc, 0x00, 0xff]),
+                                              (2, [0xff, 0x66, 0x00, 0xff])]);
+        let quirks = cart.settings.quirks().unwrap();
+        assert_eq!((quirks.shift, quirks.load_store), (ShiftVy, IncrementIndex));
+        assert!(cart.ignored.is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(decode(gif(4, [0, 0, 0, 0, 0, 0, 0, 0]).as_slice()).is_err());
+        assert!(decode(cartridge("not json").as_slice()).is_err());
+        assert!(decode(cartridge(r#"{"options": {}}"#).as_slice()).is_err());
+        assert!(decode(cartridge(r#"{"program": "clear"}"#).as_slice()).is_err());
+        assert_eq!(decode(cartridge(r#"{"program": ": main\n:macro twice { v0 += 2 }"}"#)
+                          .as_slice()).err(),
+                   Some("the cartridge's program uses `:macro` (line 2), which fries's Octo \
+                         compiler doesn't support".to_string()));
+        assert!(decode(cartridge(r#"{"program": ": main", "options": {"tickrate": "fast"}}"#)
+                       .as_slice()).is_err());
+    }
+}
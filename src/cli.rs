@@ -0,0 +1,908 @@
+use getopts;
+use getopts::{getopts, optflag, optopt, OptGroup};
+
+use std::collections::TreeMap;
+use std::io::File;
+use std::io::stdio;
+use std::os;
+use std::rand::{SeedableRng, StdRng};
+
+use serialize::json::{Json, Null, ToJson};
+
+use analysis;
+use audio;
+use audio::{Recorder, Synth};
+use asm;
+use clock::{RealClock, VirtualClock};
+use config::{Config, Settings};
+use crc32;
+use display;
+use display::Display;
+use flicker;
+use flicker::Flicker;
+use instr;
+use keymap;
+use keymap::Keymap;
+use loader;
+use loader::{Loaded, IntelHex};
+use mem;
+use mem::Rom;
+use palette::Palette;
+use platform::Platform;
+use record::{Format, RawRgba, VideoRecorder};
+use romdb;
+use romdb::{Database, Entry};
+use sha1;
+use timing::{Timing, PerFrame, PerSecond};
+use video::{Filter, Pipeline, Scaling};
+use vm::Vm;
+use {Capture, Recording, Video, run_emulator, run_headless, save_screenshot};
+use {CYCLES_PER_FRAME, SCALE};
+
+static USAGE: &'static str = "Usage: fries COMMAND [OPTIONS] ARGS
+
+Commands:
+    run ROM       run a ROM (the default, so `fries ROM` works too)
+    disasm ROM    disassemble a ROM
+    asm SOURCE    assemble a program into a ROM
+    info ROM      describe a ROM
+    test ROM      run a ROM headless and check the final screen
+    bench ROM     measure emulation speed
+    trace ROM     print every instruction as it runs
+
+Run `fries COMMAND --help` for a command's options.";
+
+static DEFAULT_HEADLESS_FRAMES: uint = 600;
+static DEFAULT_BENCH_FRAMES: uint = 3600;
+static DEFAULT_TRACE_FRAMES: uint = 60;
+
+type OptsFn = fn() -> Vec<OptGroup>;
+type RunFn = fn(&getopts::Matches) -> Result<(), String>;
+
+struct Command {
+    name: String,
+    args: &'static str,
+    about: &'static str,
+    opts: OptsFn,
+    run: RunFn,
+}
+
+fn command(name: &str) -> Option<Command> {
+    let (args, about, opts, run): (_, _, OptsFn, RunFn) = match name {
+        "run" => ("ROM", "Run a ROM.", run_opts, cmd_run),
+        "disasm" => ("ROM", "Disassemble a ROM.", disasm_opts, cmd_disasm),
+        "asm" => ("SOURCE", "Assemble a program into a ROM.", asm_opts, cmd_asm),
+        "info" => ("ROM", "Describe a ROM: its hashes, platform, and what its code uses.",
+                   info_opts, cmd_info),
+        "test" => ("ROM", "Run a ROM headless and print, or check, the final screen.",
+                   test_opts, cmd_test),
+        "bench" => ("ROM", "Measure emulation speed.", bench_opts, cmd_bench),
+        "trace" => ("ROM", "Print every instruction as it runs.", trace_opts, cmd_trace),
+        _ => return None
+    };
+    Some(Command { name: name.to_string(), args: args, about: about, opts: opts, run: run })
+}
+
+/// Options for every command that runs a ROM.
+fn vm_opts() -> Vec<OptGroup> {
+    vec![
+        optopt("", "config", "config file (default ~/.config/fries/config.toml)", "FILE"),
+        optopt("", "database", "ROM database, in the CHIP-8 database's programs.json format \
+                                (default ~/.local/share/fries/programs.json)", "FILE"),
+        optopt("", "platform", "the machine the ROM is for: chip8, chip8x, schip, xochip; \
+                                sets the quirks and largest ROM size", "NAME"),
+        optflag("", "allow-oversize", "load the start of a ROM too large for the platform, \
+                                       instead of failing"),
+        optopt("", "load-address", "where the ROM is loaded and starts: 0x200, or 0x600 \
+                                    for the ETI-660", "ADDR"),
+        optopt("", "font-address", "where the font goes in memory (default 0)", "ADDR"),
+        optopt("", "font", "font set: vip, dream6800, eti660, schip, octo, fishnchips, or a \
+                            font file (default: the platform's)", "NAME"),
+        optopt("", "memory-size", "bytes of memory: 4096, or 65536 (the default for \
+                                   xochip)", "N"),
+        optopt("", "ipf", "instructions per 60 Hz frame (default 100)", "N"),
+        optopt("", "ips", "instructions per second, instead of --ipf", "N"),
+        optopt("", "quirks", "quirks preset (vip, schip, octo) and/or QUIRK=VALUE, \
+                              comma-separated", "LIST"),
+        optopt("", "seed", "seed the random number generator", "N"),
+        optopt("", "load-state", "start from a saved state", "FILE"),
+    ]
+}
+
+fn run_opts() -> Vec<OptGroup> {
+    let mut opts = vm_opts();
+    opts.push_all([
+        optopt("", "scale", "window pixels per CHIP-8 pixel (default 10)", "N"),
+        optopt("", "palette", "palette name (solarized, green, amber, octo, high-contrast, \
+                               colorblind) or colors from index 0, like #002b36,#6c71c4",
+               "PALETTE"),
+        optopt("", "filter", "video filter: nearest (default), scale2x, scale3x, epx, scanlines, \
+                              grid", "FILTER"),
+        optopt("", "scaling", "fitting the display to the window: integer (default), \
+                               aspect, stretch", "MODE"),
+        optopt("", "flicker", "flicker reduction: off (default), blend[:FRAMES], \
+                               stable[:TICKS], decay[:KEEP]", "MODE"),
+        optopt("", "keymap", "keyboard layout: a preset name or keymap file", "NAME"),
+        optflag("", "headless", "run without opening a window"),
+        optopt("", "frames", "number of frames to run headless (default 600)", "N"),
+        optopt("", "record-audio", "record sound output to a WAV file", "FILE"),
+        optopt("", "record", "record video at 60 fps to FILE: .gif, .y4m, or .rgba for \
+                              raw frames", "FILE"),
+        optopt("", "record-scale", "pixels per CHIP-8 pixel in the recording (default 1)",
+               "N"),
+        optflag("", "record-flicker", "apply flicker reduction to the recording"),
+        optopt("", "save-state", "save the state to FILE on exit", "FILE"),
+        optopt("", "screenshot", "save a PNG of the screen to FILE on exit", "FILE"),
+        optopt("", "screenshot-scale", "pixels per CHIP-8 pixel in screenshots, including \
+                                        F12 ones (default 1)", "N"),
+    ]);
+    opts
+}
+
+fn disasm_opts() -> Vec<OptGroup> {
+    vec![optopt("", "load-address", "where the ROM is loaded (default 0x200)", "ADDR")]
+}
+
+fn asm_opts() -> Vec<OptGroup> {
+    vec![
+        optopt("o", "output", "where to write the ROM (default SOURCE.ch8)", "FILE"),
+        optopt("", "load-address", "where the ROM is loaded (default 0x200)", "ADDR"),
+    ]
+}
+
+fn info_opts() -> Vec<OptGroup> {
+    vec![
+        optopt("", "config", "config file (default ~/.config/fries/config.toml)", "FILE"),
+        optopt("", "database", "ROM database, in the CHIP-8 database's programs.json format \
+                                (default ~/.local/share/fries/programs.json)", "FILE"),
+        optflag("", "json", "print the description as JSON"),
+    ]
+}
+
+fn test_opts() -> Vec<OptGroup> {
+    let mut opts = vm_opts();
+    opts.push_all([
+        optopt("", "frames", "number of frames to run (default 600)", "N"),
+        optopt("", "expect", "fail unless the final screen matches FILE", "FILE"),
+        optopt("", "screenshot", "save a PNG of the final screen to FILE", "FILE"),
+        optopt("", "screenshot-scale", "pixels per CHIP-8 pixel in the screenshot \
+                                        (default 1)", "N"),
+    ]);
+    opts
+}
+
+fn bench_opts() -> Vec<OptGroup> {
+    let mut opts = vm_opts();
+    opts.push(optopt("", "frames", "number of frames to run (default 3600)", "N"));
+    opts
+}
+
+fn trace_opts() -> Vec<OptGroup> {
+    let mut opts = vm_opts();
+    opts.push(optopt("", "frames", "number of frames to run (default 60)", "N"));
+    opts
+}
+
+pub fn main() {
+    let mut stderr = stdio::stderr();
+    let args = os::args();
+
+    let (name, rest) = match args.as_slice() {
+        [] | [_] => {
+            let _ = writeln!(stderr, "{}", USAGE);
+            os::set_exit_status(2);
+            return;
+        },
+        [_, ref arg, ..] if arg.as_slice() == "-h" || arg.as_slice() == "--help" ||
+                            arg.as_slice() == "help" => {
+            println!("{}", USAGE);
+            return;
+        },
+        [_, ref arg, ..] if command(arg.as_slice()).is_some() => {
+            (arg.as_slice(), args.slice_from(2))
+        },
+        _ => ("run", args.slice_from(1))
+    };
+    let cmd = command(name).unwrap();
+
+    let mut opts = (cmd.opts)();
+    opts.push(optflag("h", "help", "show this help"));
+    let brief = format!("Usage: fries {} [OPTIONS] {}\n\n{}", cmd.name.as_slice(), cmd.args,
+                        cmd.about);
+
+    let matches = match getopts(rest, opts.as_slice()) {
+        Ok(m) => m,
+        Err(e) => {
+            let _ = writeln!(stderr, "{}\n\n{}", e, getopts::usage(brief.as_slice(),
+                                                                 opts.as_slice()));
+            os::set_exit_status(2);
+            return;
+        }
+    };
+    if matches.opt_present("help") {
+        println!("{}", getopts::usage(brief.as_slice(), opts.as_slice()));
+        return;
+    }
+    if matches.free.len() != 1 {
+        let _ = writeln!(stderr, "Expected one {}.\n\n{}", cmd.args,
+                         getopts::usage(brief.as_slice(), opts.as_slice()));
+        os::set_exit_status(2);
+        return;
+    }
+
+    match (cmd.run)(&matches) {
+        Ok(()) => {},
+        Err(e) => {
+            let _ = writeln!(stderr, "{}", e);
+            os::set_exit_status(1);
+        }
+    }
+}
+
+fn opt_uint(matches: &getopts::Matches, name: &str) -> Result<Option<uint>, String> {
+    match matches.opt_str(name) {
+        None => Ok(None),
+        Some(n) => match from_str::<uint>(n.as_slice()) {
+            Some(n) => Ok(Some(n)),
+            None => Err(format!("Invalid value for --{}: {}", name, n))
+        }
+    }
+}
+
+fn parse_address(name: &str, s: &str) -> Result<u16, String> {
+    match mem::parse_number(s) {
+        Some(n) if n <= 0xffff => Ok(n as u16),
+        _ => Err(format!("Invalid value for --{}: {}", name, s))
+    }
+}
+
+/// The settings given on the command line, which override the
+/// config file.
+fn cli_settings(matches: &getopts::Matches) -> Result<Settings, String> {
+    let mut settings = Settings::new();
+    settings.platform = match matches.opt_str("platform") {
+        None => None,
+        Some(name) => match Platform::from_name(name.as_slice()) {
+            Some(p) => Some(p),
+            None => return Err(format!("Invalid value for --platform: {}", name))
+        }
+    };
+    settings.load_address = match matches.opt_str("load-address") {
+        None => None,
+        Some(a) => Some(try!(parse_address("load-address", a.as_slice())))
+    };
+    settings.font_address = match matches.opt_str("font-address") {
+        None => None,
+        Some(a) => Some(try!(parse_address("font-address", a.as_slice())))
+    };
+    settings.font = matches.opt_str("font");
+    settings.memory_size = match matches.opt_str("memory-size") {
+        None => None,
+        Some(n) => match mem::parse_number(n.as_slice()) {
+            Some(n) => Some(n),
+            None => return Err(format!("Invalid value for --memory-size: {}", n))
+        }
+    };
+    settings.speed = match (try!(opt_uint(matches, "ipf")), try!(opt_uint(matches, "ips"))) {
+        (None, None) => None,
+        (Some(n), None) => Some(PerFrame(n)),
+        (None, Some(n)) => Some(PerSecond(n)),
+        (Some(_), Some(_)) => return Err("--ipf and --ips are mutually exclusive".to_string())
+    };
+    match matches.opt_str("quirks") {
+        None => {},
+        Some(q) => {
+            for spec in q.as_slice().split(',') {
+                settings.quirks.push(spec.trim().to_string());
+            }
+        }
+    }
+    Ok(settings)
+}
+
+/// `cli_settings`, plus the display and keyboard options of `run`.
+fn run_settings(matches: &getopts::Matches) -> Result<Settings, String> {
+    let mut settings = try!(cli_settings(matches));
+    settings.scale = try!(opt_uint(matches, "scale"));
+    settings.keymap = matches.opt_str("keymap");
+    match matches.opt_str("filter") {
+        None => {},
+        Some(f) => settings.filter = match Filter::from_name(f.as_slice()) {
+            Some(f) => Some(f),
+            None => return Err(format!("Invalid value for --filter: {}", f))
+        }
+    }
+    match matches.opt_str("scaling") {
+        None => {},
+        Some(m) => settings.scaling = match Scaling::from_name(m.as_slice()) {
+            Some(m) => Some(m),
+            None => return Err(format!("Invalid value for --scaling: {}", m))
+        }
+    }
+    match matches.opt_str("flicker") {
+        None => {},
+        Some(m) => settings.flicker = Some(try!(flicker::Mode::parse(m.as_slice()).map_err(|e| {
+            format!("Invalid value for --flicker: {}", e)
+        })))
+    }
+    match matches.opt_str("palette") {
+        None => {},
+        Some(p) => {
+            try!(Palette::parse(p.as_slice()).map_err(|e| {
+                format!("Invalid value for --palette: {}", e)
+            }));
+            settings.palette = Some(p);
+        }
+    }
+    Ok(settings)
+}
+
+fn load_config(matches: &getopts::Matches) -> Result<Config, String> {
+    match matches.opt_str("config") {
+        Some(path) => Config::load(&Path::new(path)),
+        None => match Config::default_path() {
+            Some(ref path) if path.exists() => Config::load(path),
+            _ => Ok(Config::new())
+        }
+    }
+}
+
+/// The ROM database, if there is one: from the command line, the
+/// config file, or the usual place, in that order. Only a database
+/// that was asked for has to exist.
+fn load_database(matches: &getopts::Matches, config: &Config) -> Result<Option<Database>, String> {
+    let path = match matches.opt_str("database") {
+        Some(path) => Path::new(path),
+        None => match config.database {
+            Some(ref path) => path.clone(),
+            None => match Database::default_path() {
+                Some(path) => if path.exists() { path } else { return Ok(None) },
+                None => return Ok(None)
+            }
+        }
+    };
+    Database::load(&path).map(|db| Some(db))
+}
+
+/// What the ROM file and the database say about the ROM, to go
+/// under its config section. The file wins where they disagree.
+fn rom_settings(loaded: &Loaded, entry: Option<&romdb::Entry>) -> Settings {
+    let mut settings = Settings::new();
+    match entry {
+        Some(entry) => settings.merge(&entry.settings),
+        None => {}
+    }
+    settings.merge(&loaded.settings);
+    settings
+}
+
+/// Settings from a ROM file or the database, in a line.
+fn describe_settings(settings: &Settings, ignored: &[String]) -> String {
+    let mut parts = vec![];
+    match settings.speed {
+        Some(PerFrame(n)) => parts.push(format!("{} instructions per frame", n)),
+        _ => {}
+    }
+    if !settings.quirks.is_empty() {
+        parts.push(format!("quirks {}", settings.quirks.connect(",")));
+    }
+    if !settings.keys.is_empty() {
+        parts.push(format!("{} keys", settings.keys.len()));
+    }
+    match settings.palette {
+        Some(ref palette) => parts.push(format!("palette {}", palette)),
+        None => {}
+    }
+    if !settings.colors.is_empty() {
+        parts.push(format!("{} colors", settings.colors.len()));
+    }
+    match settings.load_address {
+        Some(addr) => parts.push(format!("loaded at #{:03X}", addr)),
+        None => {}
+    }
+    for option in ignored.iter() {
+        parts.push(format!("{} (not supported)", option));
+    }
+    if parts.is_empty() {
+        "none".to_string()
+    } else {
+        parts.connect("; ")
+    }
+}
+
+fn build_keymap(settings: &Settings, rom_path: &Path) -> Result<Keymap, String> {
+    let name = settings.keymap.clone().unwrap_or(keymap::DEFAULT_PRESET.to_string());
+    let mut keymap = try!(Keymap::load(name.as_slice()));
+    for line in settings.keys.iter() {
+        try!(keymap.apply(line.as_slice()).map_err(|e| {
+            format!("Error in config keys: {}", e)
+        }));
+    }
+    // A ROM may come with its own keymap alongside it, as
+    // ROM.keys, which applies on top of everything else.
+    let rom_keys = rom_path.with_extension("keys");
+    if rom_keys.exists() {
+        try!(keymap.apply_file(&rom_keys));
+    }
+    Ok(keymap)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    File::open(path).read_to_end().map_err(|e| {
+        format!("Error reading {}: {}", path.display(), e.desc)
+    })
+}
+
+/// Read a ROM file and take out its program, whatever form it's in.
+fn read_rom(path: &Path, origin: u16) -> Result<Loaded, String> {
+    let data = try!(read_file(path));
+    decode_rom(path, data.as_slice(), origin)
+}
+
+fn decode_rom(path: &Path, data: &[u8], origin: u16) -> Result<Loaded, String> {
+    loader::decode(data, origin).map_err(|e| {
+        format!("Error loading ROM {}: {}", path.display(), e)
+    })
+}
+
+/// The load address `settings` give, for reading Intel HEX against.
+/// A bad memory map is reported later, so here it's just the default.
+fn origin(settings: &Settings) -> u16 {
+    settings.memory_map().map(|m| m.load_address).unwrap_or(mem::ROM_LOC)
+}
+
+/// Take the program out of a ROM file, then work out its settings,
+/// with `cli` on top. Those can move the load address, which Intel
+/// HEX addresses count from, so a HEX file is read again if they do.
+fn load_rom<'a>(path: &Path, cli: &Settings, config: &Config, db: Option<&'a Database>)
+                -> Result<(Loaded, Settings, Option<&'a Entry>), String> {
+    let mut known = config.global.clone();
+    known.merge(cli);
+    let data = try!(read_file(path));
+    let mut loaded = try!(decode_rom(path, data.as_slice(), origin(&known)));
+    let hash = program_hash(&loaded);
+    let entry = db.and_then(|db| db.find(hash.as_slice()));
+    let mut settings = config.settings_for(hash.as_slice(), &rom_settings(&loaded, entry));
+    settings.merge(cli);
+    if loaded.format == IntelHex && origin(&settings) != origin(&known) {
+        loaded = try!(decode_rom(path, data.as_slice(), origin(&settings)));
+    }
+    Ok((loaded, settings, entry))
+}
+
+fn program_hash(loaded: &Loaded) -> String {
+    sha1::to_hex(sha1::sha1(loaded.prgm.as_slice()).as_slice())
+}
+
+/// A ROM file loaded into a VM, with its settings worked out.
+struct Session {
+    rom_path: Path,
+    settings: Settings,
+    vm: Vm,
+}
+
+fn start(matches: &getopts::Matches, cli: Settings) -> Result<Session, String> {
+    let rom_path = Path::new(matches.free[0].clone());
+    let config = try!(load_config(matches));
+    let db = try!(load_database(matches, &config));
+    let (mut loaded, settings, entry) = try!(load_rom(&rom_path, &cli, &config, db.as_ref()));
+    for option in loaded.ignored.iter() {
+        let _ = writeln!(stdio::stderr(), "Warning: the ROM's `{}` setting isn't supported; \
+                                           ignoring it", option);
+    }
+    match entry {
+        Some(entry) => for option in entry.ignored.iter() {
+            let _ = writeln!(stdio::stderr(), "Warning: the ROM database's {} isn't \
+                                               supported; ignoring it", option);
+        },
+        None => {}
+    }
+
+    let map = try!(settings.memory_map().map_err(|e| format!("Error in memory map: {}", e)));
+    let max = map.max_program_size();
+    let checked = loader::check_size(&mut loaded.prgm, max, matches.opt_present("allow-oversize"));
+    match try!(checked.map_err(|e| {
+        format!("Error loading ROM {}: {} (--allow-oversize loads the start of it anyway)",
+                rom_path.display(), e)
+    })) {
+        Some(warning) => {
+            let _ = writeln!(stdio::stderr(), "Warning: {}", warning);
+        },
+        None => {}
+    }
+    let rom = Rom::with_map(loaded.prgm, map);
+
+    let rng = match try!(opt_uint(matches, "seed")) {
+        Some(seed) => SeedableRng::from_seed([seed].as_slice()),
+        None => try!(StdRng::new().map_err(|e| format!("Error creating RNG: {}", e.desc)))
+    };
+
+    let mut vm = Vm::new(rom, rng);
+    vm.set_quirks(try!(settings.quirks()));
+    vm.set_font(&try!(settings.font()));
+    match matches.opt_str("load-state") {
+        None => {},
+        Some(path) => {
+            let path = Path::new(path);
+            try!(File::open(&path).and_then(|mut f| vm.load_state(&mut f)).map_err(|e| {
+                format!("Error loading state {}: {}", path.display(),
+                        e.detail.unwrap_or(e.desc.to_string()))
+            }));
+        }
+    }
+
+    Ok(Session { rom_path: rom_path, settings: settings, vm: vm })
+}
+
+fn pipeline(settings: &Settings) -> Result<Pipeline, String> {
+    let palette = try!(settings.palette().map_err(|e| format!("Error in palette: {}", e)));
+    let mut pipeline = Pipeline::new(palette);
+    settings.filter.clone().map(|f| pipeline.filter = f);
+    settings.scaling.clone().map(|s| pipeline.scaling = s);
+    Ok(pipeline)
+}
+
+fn timing(settings: &Settings) -> Timing {
+    Timing::new(settings.speed.unwrap_or(PerFrame(CYCLES_PER_FRAME)))
+}
+
+fn recording(matches: &getopts::Matches, settings: &Settings,
+             pipeline: &Pipeline) -> Result<Option<Recording>, String> {
+    let path = match matches.opt_str("record") {
+        Some(path) => Path::new(path),
+        None => return Ok(None)
+    };
+    let format = match Format::from_path(&path) {
+        Some(format) => format,
+        None => return Err(format!("Can't record to {}: the name must end in .gif, .y4m \
+                                    or .rgba", path.display()))
+    };
+    let scale = try!(opt_uint(matches, "record-scale")).unwrap_or(1);
+    let (width, height) = (display::COLS * scale, display::ROWS * scale);
+    let rec = File::create(&path).and_then(|f| VideoRecorder::new(f, format.clone(), width,
+                                                                  height));
+    let rec = try!(rec.map_err(|e| format!("Error creating video recording: {}", e.desc)));
+    if format == RawRgba {
+        println!("Recording raw {}x{} RGBA frames at 60 fps to {}", width, height,
+                 path.display());
+    }
+
+    let flicker = if matches.opt_present("record-flicker") {
+        Some(Flicker::new(settings.flicker.clone().unwrap_or(flicker::Raw)))
+    } else {
+        None
+    };
+    Ok(Some(Recording { rec: rec, pipeline: pipeline.clone(), flicker: flicker, scale: scale }))
+}
+
+fn final_screenshot(matches: &getopts::Matches, vm: &Vm, pipeline: &Pipeline,
+                    scale: uint) -> Result<(), String> {
+    match matches.opt_str("screenshot") {
+        None => Ok(()),
+        Some(path) => save_screenshot(vm.display(), pipeline, scale, &Path::new(path))
+    }
+}
+
+fn cmd_run(matches: &getopts::Matches) -> Result<(), String> {
+    let Session { rom_path, settings, vm } = try!(start(matches, try!(run_settings(matches))));
+
+    let frames = try!(opt_uint(matches, "frames")).unwrap_or(DEFAULT_HEADLESS_FRAMES);
+    let keymap = try!(build_keymap(&settings, &rom_path));
+    let pipeline = try!(pipeline(&settings));
+    let screenshot_scale = try!(opt_uint(matches, "screenshot-scale")).unwrap_or(1);
+    let video = Video {
+        scale: settings.scale.unwrap_or(SCALE),
+        pipeline: pipeline.clone(),
+        flicker: Flicker::new(settings.flicker.clone().unwrap_or(flicker::Raw)),
+        screenshot_scale: screenshot_scale,
+        screenshot_prefix: rom_path.with_extension(""),
+    };
+
+    let audio = match matches.opt_str("record-audio") {
+        None => None,
+        Some(path) => {
+            let mut synth = Synth::new(audio::SAMPLE_RATE);
+            settings.volume.map(|v| synth.set_volume(v));
+            settings.beep_frequency.map(|f| synth.set_beep_frequency(f));
+            let rec = File::create(&Path::new(path)).and_then(|f| Recorder::new(f, synth));
+            Some(try!(rec.map_err(|e| {
+                format!("Error creating audio recording: {}", e.desc)
+            })))
+        }
+    };
+
+    let capture = Capture {
+        audio: audio,
+        video: try!(recording(matches, &settings, &pipeline)),
+    };
+
+    let timing = timing(&settings);
+    let vm = if matches.opt_present("headless") {
+        try!(run_headless(vm, timing, &mut VirtualClock::new(), frames, capture))
+    } else {
+        try!(run_emulator(vm, timing, &mut RealClock, keymap, video, capture))
+    };
+    try!(final_screenshot(matches, &vm, &pipeline, screenshot_scale));
+
+    match matches.opt_str("save-state") {
+        None => Ok(()),
+        Some(path) => {
+            let path = Path::new(path);
+            File::create(&path).and_then(|mut f| vm.save_state(&mut f)).map_err(|e| {
+                format!("Error saving state {}: {}", path.display(), e.desc)
+            })
+        }
+    }
+}
+
+fn cmd_disasm(matches: &getopts::Matches) -> Result<(), String> {
+    let base = match matches.opt_str("load-address") {
+        None => mem::ROM_LOC,
+        Some(a) => try!(parse_address("load-address", a.as_slice()))
+    };
+    let loaded = try!(read_rom(&Path::new(matches.free[0].clone()), base));
+    instr::disassemble(loaded.prgm.as_slice(), base, &mut stdio::stdout()).map_err(|e| {
+        format!("Error writing disassembly: {}", e.desc)
+    })
+}
+
+fn cmd_asm(matches: &getopts::Matches) -> Result<(), String> {
+    let src_path = Path::new(matches.free[0].clone());
+    let src = try!(File::open(&src_path).read_to_string().map_err(|e| {
+        format!("Error reading {}: {}", src_path.display(), e.desc)
+    }));
+    let base = match matches.opt_str("load-address") {
+        None => mem::ROM_LOC,
+        Some(a) => try!(parse_address("load-address", a.as_slice()))
+    };
+    let prgm = try!(asm::assemble(src.as_slice(), base).map_err(|e| {
+        format!("{}: {}", src_path.display(), e)
+    }));
+    let out_path = match matches.opt_str("output") {
+        Some(path) => Path::new(path),
+        None => src_path.with_extension("ch8")
+    };
+    File::create(&out_path).write(prgm.as_slice()).map_err(|e| {
+        format!("Error writing {}: {}", out_path.display(), e.desc)
+    })
+}
+
+fn cmd_info(matches: &getopts::Matches) -> Result<(), String> {
+    let path = Path::new(matches.free[0].clone());
+    let data = try!(read_file(&path));
+    let config = try!(load_config(matches));
+    let db = try!(load_database(matches, &config));
+    let (loaded, settings, entry) = try!(load_rom(&path, &Settings::new(), &config,
+                                                  db.as_ref()));
+    let hash = program_hash(&loaded);
+
+    let crc = crc32::crc32(loaded.prgm.as_slice());
+    let file_hash = sha1::to_hex(sha1::sha1(data.as_slice()).as_slice());
+    let map = try!(settings.memory_map().map_err(|e| format!("Error in memory map: {}", e)));
+    let analysis = analysis::analyze(loaded.prgm.as_slice(), map.load_address);
+    let detected = analysis.detect();
+    let uses: Vec<&'static str> = ["timers", "sound", "keys"].iter().map(|&f| f)
+                                                             .filter(|&f| analysis.uses_family(f))
+                                                             .collect();
+    let sprites = analysis.sprite_regions();
+
+    if matches.opt_present("json") {
+        let mut info = TreeMap::new();
+        {
+            let mut put = |key: &str, val: Json| info.insert(key.to_string(), val);
+            put("file", format!("{}", path.display()).to_json());
+            put("format", loaded.describe().to_json());
+            put("size", loaded.prgm.len().to_json());
+            put("sha1", hash.to_json());
+            put("crc32", format!("{:08x}", crc).to_json());
+            put("file_sha1", file_hash.to_json());
+            put("database", match entry {
+                None => Null,
+                Some(entry) => {
+                    let mut e = TreeMap::new();
+                    e.insert("title".to_string(), entry.title.to_json());
+                    e.insert("authors".to_string(), entry.authors.to_json());
+                    e.insert("release".to_string(), entry.release.to_json());
+                    e.insert("platform".to_string(), entry.platform.to_json());
+                    e.to_json()
+                }
+            });
+            let mut d = TreeMap::new();
+            d.insert("platform".to_string(), detected.platform.name().to_string().to_json());
+            d.insert("confidence".to_string(), detected.confidence.to_json());
+            d.insert("quirks".to_string(), detected.quirks.to_json());
+            d.insert("evidence".to_string(), detected.evidence.to_json());
+            put("detected", d.to_json());
+            put("platform", settings.platform.map(|p| p.name().to_string()).to_json());
+            put("config", config.roms.contains_key(&hash).to_json());
+            let mut memory = TreeMap::new();
+            memory.insert("size".to_string(), map.size);
+            memory.insert("load_address".to_string(), map.load_address as uint);
+            memory.insert("font_address".to_string(), map.font_address as uint);
+            memory.insert("reserved".to_string(), map.reserved);
+            put("memory", memory.to_json());
+
+            let mut code = TreeMap::new();
+            code.insert("reached".to_string(), analysis.code_size().to_json());
+            code.insert("computed_jumps".to_string(), analysis.computed_jumps.len().to_json());
+            code.insert("dead_ends".to_string(), analysis.dead_ends.iter().map(|&a| a as uint)
+                                                         .collect::<Vec<uint>>().to_json());
+            put("code", code.to_json());
+            let mut histogram = TreeMap::new();
+            for &(p, n) in analysis.histogram().iter() {
+                histogram.insert(p.to_string(), n);
+            }
+            put("histogram", histogram.to_json());
+            let mut families = TreeMap::new();
+            for &(f, ref patterns) in analysis.families().iter() {
+                families.insert(f.to_string(), patterns.iter().map(|p| p.to_string())
+                                                       .collect::<Vec<String>>());
+            }
+            put("families", families.to_json());
+            let mut used = TreeMap::new();
+            for &f in ["timers", "sound", "keys"].iter() {
+                used.insert(f.to_string(), uses.contains(&f));
+            }
+            put("uses", used.to_json());
+            put("sprites", sprites.iter().map(|&(at, len)| {
+                let mut s = TreeMap::new();
+                s.insert("address".to_string(), at as uint);
+                s.insert("length".to_string(), len);
+                s
+            }).collect::<Vec<TreeMap<String, uint>>>().to_json());
+        }
+        println!("{}", info.to_json().to_pretty_str());
+        return Ok(());
+    }
+
+    println!("File:     {}", path.display());
+    println!("Format:   {}", loaded.describe());
+    println!("Size:     {} bytes", loaded.prgm.len());
+    if file_hash == hash {
+        println!("SHA-1:    {}", hash);
+    } else {
+        println!("SHA-1:    {} (the file: {})", hash, file_hash);
+    }
+    println!("CRC-32:   {:08x}", crc);
+    if loaded.format == loader::OctoCartridge {
+        println!("Options:  {}", describe_settings(&loaded.settings, loaded.ignored.as_slice()));
+    }
+    match (&db, entry) {
+        (&None, _) => {},
+        (&Some(_), None) => println!("Database: not listed"),
+        (&Some(_), Some(entry)) => {
+            println!("Title:    {}", entry.describe());
+            println!("Database: {}{}", entry.platform.clone().map_or(String::new(), |p| {
+                format!("for {}; ", p)
+            }), describe_settings(&entry.settings, entry.ignored.as_slice()));
+        }
+    }
+    println!("Detected: {}, {:.0f}% sure (quirks {})", detected.platform.name(),
+             detected.confidence * 100.0, detected.quirks.connect(","));
+    for why in detected.evidence.iter() {
+        println!("          {}", why);
+    }
+    match settings.platform {
+        None => {},
+        Some(p) => println!("Platform: {}", p.name())
+    }
+    let max = map.max_program_size();
+    println!("Memory:   {} bytes, loaded at #{:03X}, font at #{:03X}{}", map.size,
+             map.load_address, map.font_address, if loaded.prgm.len() > max {
+                 format!(" (too large: at most {} bytes fit)", max)
+             } else {
+                 String::new()
+             });
+    println!("Config:   {}", if config.roms.contains_key(&hash) {
+        "has ROM-specific settings"
+    } else {
+        "global settings only"
+    });
+
+    println!("Code:     {} of {} bytes reached{}", analysis.code_size(), loaded.prgm.len(),
+             match analysis.computed_jumps.len() {
+                 0 => String::new(),
+                 n => format!(", {} computed jump(s)", n)
+             });
+    println!("Uses:     {}", if uses.is_empty() {
+        "no timers, sound or keys".to_string()
+    } else {
+        uses.connect(", ")
+    });
+    let histogram: Vec<String> = analysis.histogram().iter().map(|&(p, n)| {
+        format!("{} {}", p, n)
+    }).collect();
+    println!("Opcodes:  {}", histogram.connect(", "));
+    for (i, &(f, ref patterns)) in analysis.families().iter().enumerate() {
+        println!("{}{}: {}", if i == 0 { "Families: " } else { "          " }, f,
+                 patterns.connect(" "));
+    }
+    let sprites: Vec<String> = sprites.iter().map(|&(at, len)| {
+        format!("#{:03X} ({} bytes)", at, len)
+    }).collect();
+    println!("Sprites:  {}", if sprites.is_empty() {
+        "none found".to_string()
+    } else {
+        sprites.connect(", ")
+    });
+    Ok(())
+}
+
+fn cmd_test(matches: &getopts::Matches) -> Result<(), String> {
+    let Session { settings, vm, .. } = try!(start(matches, try!(cli_settings(matches))));
+    let frames = try!(opt_uint(matches, "frames")).unwrap_or(DEFAULT_HEADLESS_FRAMES);
+    let vm = try!(run_headless(vm, timing(&settings), &mut VirtualClock::new(), frames,
+                               Capture::none()));
+    let screenshot_scale = try!(opt_uint(matches, "screenshot-scale")).unwrap_or(1);
+    try!(final_screenshot(matches, &vm, &try!(pipeline(&settings)), screenshot_scale));
+    let screen = format!("{}", vm.display());
+
+    match matches.opt_str("expect") {
+        None => {
+            print!("{}", screen);
+            Ok(())
+        },
+        Some(path) => {
+            let path = Path::new(path);
+            let expected = try!(File::open(&path).read_to_string().map_err(|e| {
+                format!("Error reading {}: {}", path.display(), e.desc)
+            }));
+            let expected = try!(Display::from_ascii(expected.as_slice()).map_err(|e| {
+                format!("Error in {}: {}", path.display(), e)
+            }));
+            if expected == *vm.display() {
+                println!("ok: screen matches {} after {} frames", path.display(), frames);
+                Ok(())
+            } else {
+                Err(format!("FAIL: screen doesn't match {} after {} frames; got:\n{}",
+                            path.display(), frames, screen))
+            }
+        }
+    }
+}
+
+fn cmd_bench(matches: &getopts::Matches) -> Result<(), String> {
+    use time;
+
+    let Session { settings, vm, .. } = try!(start(matches, try!(cli_settings(matches))));
+    let frames = try!(opt_uint(matches, "frames")).unwrap_or(DEFAULT_BENCH_FRAMES);
+
+    let start = time::precise_time_ns();
+    let vm = try!(run_headless(vm, timing(&settings), &mut VirtualClock::new(), frames,
+                               Capture::none()));
+    let secs = (time::precise_time_ns() - start) as f64 / 1e9;
+
+    let emulated = frames as f64 / 60.0;
+    println!("{} frames ({:.1} s emulated) in {:.3} s", frames, emulated, secs);
+    println!("{} instructions, {:.0} per second", vm.cycles(), vm.cycles() as f64 / secs);
+    println!("{:.1}x real time", emulated / secs);
+    Ok(())
+}
+
+fn cmd_trace(matches: &getopts::Matches) -> Result<(), String> {
+    let Session { settings, mut vm, .. } = try!(start(matches, try!(cli_settings(matches))));
+    let frames = try!(opt_uint(matches, "frames")).unwrap_or(DEFAULT_TRACE_FRAMES);
+    let mut timing = timing(&settings);
+    let mut out = stdio::stdout();
+
+    for frame in range(0, frames) {
+        for _ in range(0, timing.cycles_for_tick()) {
+            if vm.is_blocked() {
+                break;
+            }
+            let op = vm.next_opcode();
+            try!(writeln!(out, "{:5} {:03X}  {:04X}  {:<18} I={:03X} V={}",
+                          frame, vm.pc(), op, format!("{}", instr::decode(op)),
+                          vm.index(), vm.registers()).map_err(|e| {
+                format!("Error writing trace: {}", e.desc)
+            }));
+            vm.run_cycles(1);
+            match vm.error() {
+                Some(e) => return Err(format!("Error at {:03X}: {}", vm.pc(), e)),
+                None => {}
+            }
+        }
+        vm.tick_timers();
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::io::timer;
+use time;
+
+/// A source of time for pacing emulation, in nanoseconds.
+pub trait Clock {
+    fn now(&self) -> u64;
+
+    /// Wait for `ns` nanoseconds to pass.
+    fn sleep(&mut self, ns: u64);
+}
+
+/// The wall clock.
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> u64 {
+        time::precise_time_ns()
+    }
+
+    fn sleep(&mut self, ns: u64) {
+        timer::sleep(ns / 1000000);
+    }
+}
+
+/// A clock that only moves when told to. Sleeping advances it
+/// instantly, so headless runs go as fast as the host allows and
+/// tests can step emulated time exactly.
+pub struct VirtualClock {
+    now: u64
+}
+
+impl VirtualClock {
+    pub fn new() -> VirtualClock {
+        VirtualClock { now: 0 }
+    }
+
+    pub fn advance(&mut self, ns: u64) {
+        self.now += ns;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> u64 {
+        self.now
+    }
+
+    fn sleep(&mut self, ns: u64) {
+        self.advance(ns);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Clock, VirtualClock};
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut clock = VirtualClock::new();
+        assert_eq!(clock.now(), 0);
+        clock.advance(5);
+        clock.sleep(10);
+        assert_eq!(clock.now(), 15);
+    }
+}
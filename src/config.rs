@@ -0,0 +1,509 @@
+use std::ascii::StrAsciiExt;
+use std::collections::TreeMap;
+use std::default::Default;
+use std::io::File;
+use std::os;
+
+use toml;
+
+use flicker;
+use font;
+use font::Font;
+use mem;
+use mem::MemoryMap;
+use palette;
+use palette::{Color, Palette};
+use platform::Platform;
+use quirks::Quirks;
+use timing::{Speed, PerFrame, PerSecond};
+use video::{Filter, Scaling};
+
+/// Settings that can come from the config file, a ROM's section of
+/// it, or the command line. Unset fields fall through to the layer
+/// below, and finally to the built-in defaults.
+#[deriving(Clone, Show)]
+pub struct Settings {
+    pub platform: Option<Platform>,
+    pub speed: Option<Speed>,
+    pub scale: Option<uint>,
+    pub filter: Option<Filter>,
+    pub scaling: Option<Scaling>,
+    pub flicker: Option<flicker::Mode>,
+    pub palette: Option<String>,     // palette name or color list
+    pub colors: Vec<(uint, Color)>,  // single colors changed on top
+    pub keymap: Option<String>,   // preset name or keymap file
+    pub font: Option<String>,     // preset name or font file
+    pub keys: Vec<String>,        // keymap lines applied on top
+    pub quirks: Vec<String>,      // quirk settings, applied in order
+    pub volume: Option<f64>,
+    pub beep_frequency: Option<f64>,
+    pub load_address: Option<u16>,  // the memory map, over the platform's
+    pub font_address: Option<u16>,
+    pub memory_size: Option<uint>,
+    pub reserved: Option<uint>,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            platform: None,
+            speed: None,
+            scale: None,
+            filter: None,
+            scaling: None,
+            flicker: None,
+            palette: None,
+            colors: vec![],
+            keymap: None,
+            font: None,
+            keys: vec![],
+            quirks: vec![],
+            volume: None,
+            beep_frequency: None,
+            load_address: None,
+            font_address: None,
+            memory_size: None,
+            reserved: None,
+        }
+    }
+
+    /// Layer `top` over these settings.
+    pub fn merge(&mut self, top: &Settings) {
+        fn over<T: Clone>(dst: &mut Option<T>, src: &Option<T>) {
+            if src.is_some() {
+                *dst = src.clone();
+            }
+        }
+        over(&mut self.platform, &top.platform);
+        over(&mut self.speed, &top.speed);
+        over(&mut self.scale, &top.scale);
+        over(&mut self.filter, &top.filter);
+        over(&mut self.scaling, &top.scaling);
+        over(&mut self.flicker, &top.flicker);
+        if top.palette.is_some() {
+            self.colors.clear();
+        }
+        over(&mut self.palette, &top.palette);
+        over(&mut self.keymap, &top.keymap);
+        over(&mut self.font, &top.font);
+        over(&mut self.volume, &top.volume);
+        over(&mut self.beep_frequency, &top.beep_frequency);
+        over(&mut self.load_address, &top.load_address);
+        over(&mut self.font_address, &top.font_address);
+        over(&mut self.memory_size, &top.memory_size);
+        over(&mut self.reserved, &top.reserved);
+        self.keys.push_all(top.keys.as_slice());
+        self.quirks.push_all(top.quirks.as_slice());
+        self.colors.push_all(top.colors.as_slice());
+    }
+
+    /// The platform's quirks, if one is set, with the quirk settings
+    /// applied on top.
+    pub fn quirks(&self) -> Result<Quirks, String> {
+        let mut quirks: Quirks = match self.platform {
+            Some(p) => Quirks::preset(p.quirks_preset()).unwrap(),
+            None => Default::default()
+        };
+        for spec in self.quirks.iter() {
+            try!(quirks.apply(spec.as_slice()));
+        }
+        Ok(quirks)
+    }
+
+    /// The platform's memory map, if one is set, with any parts of
+    /// it that were set on their own.
+    pub fn memory_map(&self) -> Result<MemoryMap, String> {
+        let mut map = match self.platform {
+            Some(p) => p.memory_map(),
+            None => MemoryMap::new()
+        };
+        self.load_address.map(|a| map.load_address = a);
+        self.font_address.map(|a| map.font_address = a);
+        self.memory_size.map(|n| map.size = n);
+        self.reserved.map(|n| map.reserved = n);
+        try!(map.check());
+        Ok(map)
+    }
+
+    /// The font set, or else the platform's own.
+    pub fn font(&self) -> Result<Font, String> {
+        match self.font {
+            Some(ref name) => Font::load(name.as_slice()),
+            None => {
+                let preset = self.platform.map_or(font::DEFAULT_PRESET, |p| p.font_preset());
+                Ok(Font::preset(preset).unwrap())
+            }
+        }
+    }
+
+    pub fn palette(&self) -> Result<Palette, String> {
+        let spec = self.palette.clone().unwrap_or(palette::DEFAULT_PALETTE.to_string());
+        let mut palette = try!(Palette::parse(spec.as_slice()));
+        for &(index, color) in self.colors.iter() {
+            palette.set(index, color);
+        }
+        Ok(palette)
+    }
+}
+
+/// The config file: global settings, plus overrides for particular
+/// ROMs keyed by the SHA-1 of the program (after unpacking it from
+/// hex or gzip, so every copy of a ROM shares its settings).
+///
+///     ipf = 15
+///     keymap = "azerty"
+///
+///     palette = "amber"
+///
+///     [colors]
+///     on = "#ffd780"
+///
+///     [rom.0123456789abcdef0123456789abcdef01234567]
+///     ipf = 1000
+///     platform = "schip"
+///
+/// `quirks` may also be a table of single quirks, like
+/// `key_wait = "press"`. `palette` may also be a list of colors,
+/// and `[colors]` changes single colors of the palette by index,
+/// with `off` and `on` meaning 0 and 1.
+///
+/// `font` is one of the built-in font sets (`vip`, `dream6800`,
+/// `eti660`, `schip`, `octo`, `fishnchips`) or a font file.
+///
+/// `[memory]` changes the platform's memory map: `load_address`,
+/// `font_address`, `size` and `reserved` (bytes at the top), as
+/// numbers or strings like `"0x600"`.
+///
+/// `database = "path/to/programs.json"` names the ROM database, if
+/// it isn't in the usual place.
+pub struct Config {
+    pub global: Settings,
+    pub roms: TreeMap<String, Settings>,
+    pub database: Option<Path>,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config { global: Settings::new(), roms: TreeMap::new(), database: None }
+    }
+
+    /// Where the config file lives if not given on the command line:
+    /// `$XDG_CONFIG_HOME/fries/config.toml`, by default under
+    /// `~/.config`.
+    pub fn default_path() -> Option<Path> {
+        let base = match os::getenv("XDG_CONFIG_HOME") {
+            Some(dir) => Path::new(dir),
+            None => match os::getenv("HOME") {
+                Some(home) => Path::new(home).join(".config"),
+                None => return None
+            }
+        };
+        Some(base.join("fries").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let src = try!(File::open(path).read_to_string().map_err(|e| {
+            format!("Error reading config {}: {}", path.display(), e.desc)
+        }));
+        Config::parse(src.as_slice()).map_err(|e| {
+            format!("Error in config {}: {}", path.display(), e)
+        })
+    }
+
+    pub fn parse(src: &str) -> Result<Config, String> {
+        let mut parser = toml::Parser::new(src);
+        let table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let err = &parser.errors[0];
+                let (line, col) = parser.to_linecol(err.lo);
+                return Err(format!("{}:{}: {}", line + 1, col + 1, err.desc));
+            }
+        };
+
+        let mut config = Config::new();
+        config.global = try!(parse_settings(&table, ""));
+        match table.find(&"database".to_string()) {
+            None => {},
+            Some(path) => config.database = Some(Path::new(try!(str_value("database", path))))
+        }
+        match table.find(&"rom".to_string()) {
+            None => {},
+            Some(roms) => {
+                let roms = try!(table_value("rom", roms));
+                for (hash, rom) in roms.iter() {
+                    let ctx = format!("rom.{}.", hash);
+                    let rom = try!(table_value(ctx.as_slice(), rom));
+                    let settings = try!(parse_settings(rom, ctx.as_slice()));
+                    config.roms.insert(hash.as_slice().to_ascii_lower(), settings);
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// The settings for a ROM with the given SHA-1 (in hex).
+    /// `embedded` holds what the ROM file itself, or the ROM
+    /// database, asks for, which goes over the global settings but
+    /// under the ROM's own section.
+    pub fn settings_for(&self, hash: &str, embedded: &Settings) -> Settings {
+        let mut settings = self.global.clone();
+        settings.merge(embedded);
+        match self.roms.find(&hash.to_ascii_lower()) {
+            Some(rom) => settings.merge(rom),
+            None => {}
+        }
+        settings
+    }
+}
+
+fn parse_settings(table: &toml::Table, ctx: &str) -> Result<Settings, String> {
+    let mut s = Settings::new();
+    for (key, val) in table.iter() {
+        let name = format!("{}{}", ctx, key);
+        let name = name.as_slice();
+        match key.as_slice() {
+            "rom" | "database" if ctx.is_empty() => {}, // handled by Config::parse
+            "platform" => s.platform = match Platform::from_name(try!(str_value(name, val))) {
+                Some(p) => Some(p),
+                None => return Err(format!("{}: expected \"chip8\", \"chip8x\", \"schip\" \
+                                            or \"xochip\"", name))
+            },
+            "ipf" => s.speed = Some(PerFrame(try!(uint_value(name, val)))),
+            "ips" => s.speed = Some(PerSecond(try!(uint_value(name, val)))),
+            "scale" => s.scale = Some(try!(uint_value(name, val))),
+            "filter" => s.filter = match Filter::from_name(try!(str_value(name, val))) {
+                Some(f) => Some(f),
+                None => return Err(format!("{}: unknown filter", name))
+            },
+            "scaling" => s.scaling = match Scaling::from_name(try!(str_value(name, val))) {
+                Some(f) => Some(f),
+                None => return Err(format!("{}: expected \"integer\", \"aspect\" or \"stretch\"",
+                                           name))
+            },
+            "flicker" => {
+                let mode = flicker::Mode::parse(try!(str_value(name, val)));
+                s.flicker = Some(try!(mode.map_err(|e| format!("{}: {}", name, e))));
+            },
+            "keymap" => s.keymap = Some(try!(str_value(name, val)).to_string()),
+            "font" => s.font = Some(try!(str_value(name, val)).to_string()),
+            "keys" => {
+                for (k, v) in try!(table_value(name, val)).iter() {
+                    let keys = try!(str_value(format!("{}.{}", name, k).as_slice(), v));
+                    s.keys.push(format!("{} = {}", k, keys));
+                }
+            },
+            "quirks" => {
+                // Either a preset name, or a table of single quirks.
+                let mut specs = vec![];
+                match val.as_str() {
+                    Some(preset) => specs.push(preset.to_string()),
+                    None => {
+                        for (k, v) in try!(table_value(name, val)).iter() {
+                            let value = try!(str_value(format!("{}.{}", name, k).as_slice(), v));
+                            specs.push(format!("{}={}", k, value));
+                        }
+                    }
+                }
+                let mut scratch: Quirks = Default::default();
+                for spec in specs.iter() {
+                    try!(scratch.apply(spec.as_slice()).map_err(|e| format!("{}: {}", name, e)));
+                }
+                s.quirks.push_all(specs.as_slice());
+            },
+            "palette" => {
+                // Either a name, or a list of colors.
+                let spec = match val.as_slice() {
+                    Some(colors) => {
+                        let mut specs = vec![];
+                        for (i, c) in colors.iter().enumerate() {
+                            let name = format!("{}[{}]", name, i);
+                            specs.push(try!(str_value(name.as_slice(), c)));
+                        }
+                        specs.connect(",")
+                    },
+                    None => try!(str_value(name, val)).to_string()
+                };
+                try!(Palette::parse(spec.as_slice()).map_err(|e| format!("{}: {}", name, e)));
+                s.palette = Some(spec);
+            },
+            "colors" => {
+                for (k, v) in try!(table_value(name, val)).iter() {
+                    let name = format!("{}.{}", name, k);
+                    let name = name.as_slice();
+                    let color = match palette::parse_color(try!(str_value(name, v))) {
+                        Some(color) => color,
+                        None => return Err(format!("{}: expected a color like \"#6c71c4\"",
+                                                   name))
+                    };
+                    let index = match k.as_slice() {
+                        "off" => 0,
+                        "on" => 1,
+                        k => match from_str::<uint>(k) {
+                            Some(i) if i < palette::MAX_COLORS => i,
+                            _ => return Err(format!("unknown setting `{}`", name))
+                        }
+                    };
+                    s.colors.push((index, color));
+                }
+            },
+            "memory" => {
+                for (k, v) in try!(table_value(name, val)).iter() {
+                    let name = format!("{}.{}", name, k);
+                    let name = name.as_slice();
+                    match k.as_slice() {
+                        "load_address" => s.load_address = Some(try!(address_value(name, v))),
+                        "font_address" => s.font_address = Some(try!(address_value(name, v))),
+                        "size" => s.memory_size = Some(try!(number_value(name, v))),
+                        "reserved" => s.reserved = Some(try!(number_value(name, v))),
+                        _ => return Err(format!("unknown setting `{}`", name))
+                    }
+                }
+            },
+            "audio" => {
+                for (k, v) in try!(table_value(name, val)).iter() {
+                    let name = format!("{}.{}", name, k);
+                    let name = name.as_slice();
+                    match k.as_slice() {
+                        "volume" => s.volume = Some(try!(float_value(name, v))),
+                        "beep_frequency" => s.beep_frequency = Some(try!(float_value(name, v))),
+                        _ => return Err(format!("unknown setting `{}`", name))
+                    }
+                }
+            },
+            _ => return Err(format!("unknown setting `{}`", name))
+        }
+    }
+    Ok(s)
+}
+
+fn table_value<'a>(name: &str, val: &'a toml::Value) -> Result<&'a toml::Table, String> {
+    match val.as_table() {
+        Some(t) => Ok(t),
+        None => Err(format!("{}: expected a table", name))
+    }
+}
+
+fn str_value<'a>(name: &str, val: &'a toml::Value) -> Result<&'a str, String> {
+    match val.as_str() {
+        Some(s) => Ok(s),
+        None => Err(format!("{}: expected a string", name))
+    }
+}
+
+fn uint_value(name: &str, val: &toml::Value) -> Result<uint, String> {
+    match val.as_integer() {
+        Some(n) if n >= 0 => Ok(n as uint),
+        _ => Err(format!("{}: expected a non-negative integer", name))
+    }
+}
+
+/// An integer, or a string holding one in hex, like `"0x600"`.
+fn number_value(name: &str, val: &toml::Value) -> Result<uint, String> {
+    match val.as_str() {
+        Some(s) => match mem::parse_number(s) {
+            Some(n) => Ok(n),
+            None => Err(format!("{}: expected a number, like 512 or \"0x200\"", name))
+        },
+        None => uint_value(name, val)
+    }
+}
+
+fn address_value(name: &str, val: &toml::Value) -> Result<u16, String> {
+    match try!(number_value(name, val)) {
+        n if n <= 0xffff => Ok(n as u16),
+        n => Err(format!("{}: {:X} is beyond any CHIP-8 memory", name, n))
+    }
+}
+
+fn float_value(name: &str, val: &toml::Value) -> Result<f64, String> {
+    match (val.as_float(), val.as_integer()) {
+        (Some(f), _) => Ok(f),
+        (None, Some(n)) => Ok(n as f64),
+        _ => Err(format!("{}: expected a number", name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Settings};
+    use font::Font;
+    use flicker::Decay;
+    use platform::SuperChip;
+    use quirks::CompleteOnPress;
+    use timing::{PerFrame, PerSecond};
+    use video::Scale2x;
+
+    static HASH: &'static str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(r#"
+            ipf = 15
+            keymap = "azerty"
+            filter = "scale2x"
+            flicker = "decay:0.6"
+            palette = "amber"
+            database = "/usr/share/chip-8-database/programs.json"
+
+            [colors]
+            on = "#ffd780"
+
+            [keys]
+            a = "Z Numpad0"
+
+            [rom.0123456789ABCDEF0123456789ABCDEF01234567]
+            ips = 1000
+            platform = "schip"
+            font = "fishnchips"
+            palette = ["#000000", "#ffffff", "#ff0000"]
+            [rom.0123456789ABCDEF0123456789ABCDEF01234567.quirks]
+            key_wait = "press"
+            [rom.0123456789ABCDEF0123456789ABCDEF01234567.memory]
+            load_address = "0x600"
+            font_address = 80
+        "#).unwrap();
+        assert_eq!(config.global.speed, Some(PerFrame(15)));
+        assert_eq!(config.global.keymap, Some("azerty".to_string()));
+        assert_eq!(config.global.filter, Some(Scale2x));
+        assert_eq!(config.global.flicker, Some(Decay(0.6)));
+        let palette = config.global.palette().unwrap();
+        assert_eq!(palette.color(0), [0x1a, 0x10, 0x00, 0xff]);
+        assert_eq!(palette.color(1), [0xff, 0xd7, 0x80, 0xff]);
+        assert_eq!(config.global.keys, vec!["a = Z Numpad0".to_string()]);
+        assert_eq!(config.database, Some(Path::new("/usr/share/chip-8-database/programs.json")));
+
+        let rom = config.settings_for(HASH, &Settings::new());
+        assert_eq!(rom.speed, Some(PerSecond(1000)));
+        assert_eq!(rom.keymap, Some("azerty".to_string()));
+        assert_eq!(rom.platform, Some(SuperChip));
+        assert_eq!(rom.quirks().unwrap().key_wait, CompleteOnPress);
+        let map = rom.memory_map().unwrap();
+        assert_eq!((map.load_address, map.font_address, map.size), (0x600, 0x50, 4096));
+        assert_eq!(rom.font().unwrap(), Font::preset("fishnchips").unwrap());
+        let palette = rom.palette().unwrap();
+        assert_eq!(palette.len(), 3);
+        assert_eq!(palette.color(1), [0xff, 0xff, 0xff, 0xff]);
+
+        let other = config.settings_for("da39a3ee5e6b4b0d3255bfef95601890afd80709",
+                                        &Settings::new());
+        assert_eq!(other.speed, Some(PerFrame(15)));
+        assert_eq!(other.font().unwrap(), Font::preset("octo").unwrap());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Config::parse("ipf = ").is_err());
+        assert!(Config::parse("ipf = \"fast\"").is_err());
+        assert!(Config::parse("speed = 10").is_err());
+        assert!(Config::parse("[quirks]\nkey_wait = \"whenever\"").is_err());
+        assert!(Config::parse("quirks = \"chip48\"").is_err());
+        assert!(Config::parse("[colors]\non = \"green\"").is_err());
+        assert!(Config::parse("[colors]\n16 = \"#000000\"").is_err());
+        assert!(Config::parse("palette = \"mauve\"").is_err());
+        assert!(Config::parse("filter = \"blur\"").is_err());
+        assert!(Config::parse("platform = \"hp48\"").is_err());
+        assert!(Config::parse("[memory]\nload_address = \"0x10000\"").is_err());
+        assert!(Config::parse("[memory]\nstack = 16").is_err());
+    }
+}
@@ -0,0 +1,50 @@
+/// The CRC-32 used by PNG, gzip and zip.
+pub struct Crc32 {
+    table: [u32, ..256],
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = [0u32, ..256];
+        for n in range(0u, 256) {
+            let mut c = n as u32;
+            for _ in range(0u, 8) {
+                c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            table[n] = c;
+        }
+        Crc32 { table: table, crc: 0xffffffff }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data.iter() {
+            self.crc = self.table[((self.crc ^ byte as u32) & 0xff) as uint] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.crc ^ 0xffffffff
+    }
+}
+
+/// The CRC-32 of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Crc32, crc32};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf43926);
+    }
+}
@@ -1,9 +1,12 @@
 use std::fmt;
+use std::hash;
+use std::hash::Hash;
 
 pub static COLS: uint = 64;
 pub static ROWS: uint = 32;
 pub static MAX_SPRITE_HEIGHT: uint = 15;
 
+#[deriving(Clone, PartialEq, Eq, Hash)]
 pub enum Pixel {
     On,
     Off
@@ -39,25 +42,111 @@ impl fmt::Show for Pixel {
     }
 }
 
+#[deriving(Clone)]
 pub struct Display {
-    p: [u64, ..ROWS]
+    p: [u64, ..ROWS],
+    generation: u64,          // bumped by every change
+    row_gen: [u64, ..ROWS],   // the generation each row last changed in
 }
 
 impl Display {
     pub fn new() -> Display {
         Display {
-            p: [0, ..ROWS as uint]
+            p: [0, ..ROWS as uint],
+            generation: 0,
+            row_gen: [0, ..ROWS as uint],
+        }
+    }
+
+    /// Build a display from ASCII art in the format `fmt::Show`
+    /// prints. The border is optional, and missing rows and columns
+    /// are unlit; `█`, `#`, `X` or `*` is a lit pixel, and a space
+    /// or `.` is an unlit one.
+    pub fn from_ascii(s: &str) -> Result<Display, String> {
+        let mut d = Display::new();
+        let framed = s.lines().any(|l| l.trim_left().starts_with("|"));
+        let mut lines: Vec<(uint, &str)> = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let trimmed = line.trim_left();
+            if framed {
+                // Only the lines inside the border count.
+                if !trimmed.starts_with("|") {
+                    continue;
+                }
+                let inner = trimmed.slice_from(1);
+                lines.push((i + 1, match inner.rfind('|') {
+                    Some(end) => inner.slice_to(end),
+                    None => inner
+                }));
+            } else if !trimmed.is_empty() || !lines.is_empty() {
+                lines.push((i + 1, line));
+            }
+        }
+        while lines.last().map_or(false, |&(_, l)| l.trim().is_empty()) {
+            lines.pop();
+        }
+
+        if lines.len() > ROWS {
+            return Err(format!("{} rows is more than {}", lines.len(), ROWS));
+        }
+        for (y, &(number, row)) in lines.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let px = match c {
+                    '█' | '#' | 'X' | '*' => On,
+                    ' ' | '.' => Off,
+                    _ => return Err(format!("line {}: unexpected `{}`", number, c))
+                };
+                if x >= COLS {
+                    if px.is_on() {
+                        return Err(format!("line {}: more than {} columns", number, COLS));
+                    }
+                    continue;
+                }
+                d.set(x, y, px);
+            }
+        }
+        Ok(d)
+    }
+
+    #[inline]
+    pub fn get(&self, x: uint, y: uint) -> Pixel {
+        assert!(x < COLS);
+        Pixel::from_bool((self.p[y] >> (63 - x)) & 1 == 1)
+    }
+
+    pub fn set(&mut self, x: uint, y: uint, px: Pixel) {
+        assert!(x < COLS);
+        let bit = 1u64 << (63 - x);
+        let row = if px.is_on() { self.p[y] | bit } else { self.p[y] & !bit };
+        if row != self.p[y] {
+            self.touch(y);
+            self.p[y] = row;
         }
     }
 
     pub fn pixels<'a>(&'a self) -> Pixels<'a> {
         Pixels {
             display: self,
-            row_idx: 0,
-            bit: 63,
+            idx: 0,
+            end: COLS * ROWS,
+        }
+    }
+
+    /// The pixels of row `y`, left to right.
+    pub fn row_pixels<'a>(&'a self, y: uint) -> Pixels<'a> {
+        assert!(y < ROWS);
+        Pixels {
+            display: self,
+            idx: y * COLS,
+            end: (y + 1) * COLS,
         }
     }
 
+    /// Every pixel with its position, as `(x, y, pixel)`.
+    pub fn coords<'a>(&'a self) -> Coords<'a> {
+        Coords { pixels: self.pixels() }
+    }
+
     /// Draw the given sprite onto the display at the given
     /// position. Returns true if drawing the sprite turns off any
     /// previously on pixels.
@@ -67,6 +156,9 @@ impl Display {
         let mut flag = false;
 
         for sprite in sprite.iter() {
+            if *sprite != 0 {
+                self.touch(y);
+            }
             let sprite: u64 = (*sprite as u64) << (64 - 8);
             if self.p[y] & (sprite >> x) != 0 {
                 flag = true;
@@ -84,8 +176,50 @@ impl Display {
     }
 
     pub fn clear(&mut self) {
+        for y in range(0, ROWS) {
+            if self.p[y] != 0 {
+                self.touch(y);
+            }
+        }
         self.p = [0, ..ROWS]
     }
+
+    /// The raw display contents: one row per word, the leftmost
+    /// pixel in the most significant bit.
+    pub fn rows<'a>(&'a self) -> &'a [u64] {
+        self.p.as_slice()
+    }
+
+    pub fn row(&self, y: uint) -> u64 {
+        self.p[y]
+    }
+
+    pub fn set_rows(&mut self, rows: &[u64]) {
+        assert_eq!(rows.len(), ROWS);
+        for y in range(0, ROWS) {
+            if self.p[y] != rows[y] {
+                self.touch(y);
+            }
+        }
+        self.p.copy_from(rows);
+    }
+
+    /// A counter that goes up whenever the display changes, so
+    /// frontends can tell when there's nothing new to show.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Whether row `y` has changed since the display was at
+    /// generation `since`.
+    pub fn row_changed(&self, y: uint, since: u64) -> bool {
+        self.row_gen[y] > since
+    }
+
+    fn touch(&mut self, y: uint) {
+        self.generation += 1;
+        self.row_gen[y] = self.generation;
+    }
 }
 
 impl fmt::Show for Display {
@@ -105,50 +239,129 @@ impl fmt::Show for Display {
     }
 }
 
+// Displays are the same if they show the same thing, whatever
+// their history.
+impl PartialEq for Display {
+    fn eq(&self, other: &Display) -> bool {
+        self.p.as_slice() == other.p.as_slice()
+    }
+}
+
+impl Eq for Display {}
+
+impl<S: hash::Writer> Hash<S> for Display {
+    fn hash(&self, state: &mut S) {
+        self.p.as_slice().hash(state)
+    }
+}
+
+impl FromStr for Display {
+    fn from_str(s: &str) -> Option<Display> {
+        Display::from_ascii(s).ok()
+    }
+}
+
 pub struct Pixels<'a> {
     display: &'a Display,
-    row_idx: uint,
-    bit: uint, // 63, 62, 61, 60, ... 0
+    idx: uint, // y * COLS + x
+    end: uint,
 }
 
 impl<'a> Iterator<Pixel> for Pixels<'a> {
     fn next(&mut self) -> Option<Pixel> {
-        if self.row_idx >= ROWS {
+        if self.idx >= self.end {
             return None;
         }
+        let px = self.display.get(self.idx % COLS, self.idx / COLS);
+        self.idx += 1;
+        Some(px)
+    }
+}
 
-        let row = self.display.p[self.row_idx];
-        let shift = self.bit;
-        let on = (row & (1 << shift)) >> shift == 1;
-
-        if self.bit == 0 {
-            self.row_idx += 1;
-        }
+pub struct Coords<'a> {
+    pixels: Pixels<'a>,
+}
 
-        self.bit = (self.bit - 1) % 64;
-        Some(Pixel::from_bool(on))
+impl<'a> Iterator<(uint, uint, Pixel)> for Coords<'a> {
+    fn next(&mut self) -> Option<(uint, uint, Pixel)> {
+        let idx = self.pixels.idx;
+        self.pixels.next().map(|px| (idx % COLS, idx / COLS, px))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Display;
+    use super::{Display, On, Off};
 
     #[test]
     fn test_pixels() {
+        let d = Display::from_ascii("####\n    ####").unwrap();
+        assert_eq!(d.get(0, 0), On);
+        assert_eq!(d.get(4, 0), Off);
+        assert_eq!(d.get(4, 1), On);
+        assert!(d.row_pixels(0).take(4).all(|x| x.is_on()));
+        assert!(d.row_pixels(0).skip(4).all(|x| x.is_off()));
+        let lit: Vec<(uint, uint)> = d.coords().filter(|&(_, _, px)| px.is_on())
+                                               .map(|(x, y, _)| (x, y)).collect();
+        assert_eq!(lit.len(), 8);
+        assert_eq!(lit[0], (0, 0));
+        assert_eq!(lit[4], (4, 1));
+    }
+
+    #[test]
+    fn test_get_set() {
         let mut d = Display::new();
-        d.p[0] = 0b1111 << 60;
-        d.p[1] = 0b00001111 << 56;
-        let pixels = d.pixels();
-        assert!(pixels.take(4).all(|x| x.is_on()));
-        assert!(pixels.skip(4).take(60).all(|x| x.is_off()));
-        assert!(pixels.skip(64).skip(4).take(4).all(|x| x.is_on()));
+        d.set(63, 31, On);
+        assert_eq!(d.get(63, 31), On);
+        assert_eq!(d.rows()[31], 1);
+        d.set(63, 31, Off);
+        assert_eq!(d, Display::new());
     }
 
     #[test]
-    fn test_clear() {
+    fn test_ascii_roundtrip() {
+        let mut d = Display::new();
+        d.draw([0b00100100, 0b10000001, 0b01111110].as_slice(), 30, 14);
+        let text = format!("{}", d);
+        assert_eq!(Display::from_ascii(text.as_slice()), Ok(d.clone()));
+        assert_eq!(from_str::<Display>(text.as_slice()), Some(d));
+
+        assert!(Display::from_ascii("#?#").is_err());
+        assert!(Display::from_ascii(String::from_char(65, '#').as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_eq_ignores_history() {
         let mut d = Display::new();
-        d.p[0] = 943853945;
+        d.draw([0xff].as_slice(), 0, 0);
+        d.clear();
+        assert!(d.generation() > 0);
+        assert_eq!(d, Display::new());
+    }
+
+    #[test]
+    fn test_generation() {
+        let mut d = Display::new();
+        let gen = d.generation();
+        d.draw([0x00], 0, 5);
+        assert_eq!(d.generation(), gen);
+
+        d.draw([0x80, 0x00, 0x80], 0, 5);
+        assert!(d.generation() > gen);
+        assert!(d.row_changed(5, gen) && d.row_changed(7, gen));
+        assert!(!d.row_changed(6, gen) && !d.row_changed(0, gen));
+
+        let gen = d.generation();
+        d.clear();
+        assert!(d.row_changed(5, gen) && !d.row_changed(6, gen));
+        let gen = d.generation();
+        d.clear();
+        assert_eq!(d.generation(), gen);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut d = Display::from_ascii("# # ## ### #").unwrap();
         d.clear();
         assert!(d.pixels().all(|x| x.is_off()));
     }
@@ -0,0 +1,226 @@
+use std::collections::{Deque, RingBuf};
+
+use display;
+use display::Display;
+
+static PIXELS: uint = display::COLS * display::ROWS;
+
+/// Ways to hide the flicker of sprites being erased and redrawn.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Mode {
+    Raw,           // show each frame as it is
+    Blend(uint),   // average the last N frames
+    Stable(uint),  // a pixel changes once it has held its new state for N ticks
+    Decay(f32),    // lit pixels fade, keeping this much brightness each tick
+}
+
+impl Mode {
+    /// Parse `off`, `blend`, `stable` or `decay`, optionally with a
+    /// parameter after a colon, like `blend:3` or `decay:0.7`.
+    pub fn parse(spec: &str) -> Result<Mode, String> {
+        let spec = spec.trim();
+        let (name, param) = match spec.find(':') {
+            Some(i) => (spec.slice_to(i), Some(spec.slice_from(i + 1))),
+            None => (spec, None)
+        };
+        let frames = |default: uint| match param {
+            None => Ok(default),
+            Some(n) => match from_str::<uint>(n) {
+                Some(n) if n >= 1 => Ok(n),
+                _ => Err(format!("`{}`: expected a number of frames, found `{}`", name, n))
+            }
+        };
+        match name {
+            "off" => Ok(Raw),
+            "blend" => Ok(Blend(try!(frames(2)))),
+            "stable" => Ok(Stable(try!(frames(2)))),
+            "decay" => match param {
+                None => Ok(Decay(0.5)),
+                Some(f) => match from_str::<f32>(f) {
+                    Some(f) if f >= 0.0 && f < 1.0 => Ok(Decay(f)),
+                    _ => Err(format!("`decay`: expected a fraction below 1, found `{}`", f))
+                }
+            },
+            _ => Err(format!("unknown flicker reduction `{}`", name))
+        }
+    }
+}
+
+/// Sits between the display and the renderer, turning the frames
+/// the VM draws into how bright each pixel should look.
+pub struct Flicker {
+    mode: Mode,
+    history: RingBuf<Vec<u64>>,
+    held: Vec<uint>,
+    levels: Vec<f32>,
+    seen: u64,         // the display generation last taken in
+    generation: u64,   // bumped whenever the levels change
+    row_gen: Vec<u64>, // the generation each row last changed in
+}
+
+impl Flicker {
+    pub fn new(mode: Mode) -> Flicker {
+        Flicker {
+            mode: mode,
+            history: RingBuf::new(),
+            held: Vec::from_elem(PIXELS, 0),
+            levels: Vec::from_elem(PIXELS, 0.0),
+            seen: 0,
+            generation: 0,
+            row_gen: Vec::from_elem(display::ROWS, 0),
+        }
+    }
+
+    fn set_level(&mut self, i: uint, level: f32) {
+        if self.levels[i] != level {
+            *self.levels.get_mut(i) = level;
+            self.generation += 1;
+            *self.row_gen.get_mut(i / display::COLS) = self.generation;
+        }
+    }
+
+    /// Take in the display as it is at the end of a 60 Hz tick.
+    pub fn tick(&mut self, display: &Display) {
+        let rows = display.rows();
+        let lit = |i: uint| (rows[i / display::COLS] >> (63 - i % display::COLS)) & 1 == 1;
+
+        match self.mode {
+            Raw => {
+                // Only the rows that changed need looking at.
+                for y in range(0, display::ROWS) {
+                    if display.row_changed(y, self.seen) {
+                        for i in range(y * display::COLS, (y + 1) * display::COLS) {
+                            self.set_level(i, if lit(i) { 1.0 } else { 0.0 });
+                        }
+                    }
+                }
+            },
+            Blend(n) => {
+                if self.history.len() == n {
+                    self.history.pop_front();
+                }
+                self.history.push(rows.to_vec());
+                let frames = self.history.len() as f32;
+                for i in range(0, PIXELS) {
+                    let (row, bit) = (i / display::COLS, 63 - i % display::COLS);
+                    let on = self.history.iter().filter(|r| (r[row] >> bit) & 1 == 1).count();
+                    self.set_level(i, on as f32 / frames);
+                }
+            },
+            Stable(n) => {
+                for i in range(0, PIXELS) {
+                    let shown = self.levels[i] == 1.0;
+                    if lit(i) == shown {
+                        *self.held.get_mut(i) = 0;
+                    } else {
+                        *self.held.get_mut(i) += 1;
+                        if self.held[i] >= n {
+                            self.set_level(i, if lit(i) { 1.0 } else { 0.0 });
+                            *self.held.get_mut(i) = 0;
+                        }
+                    }
+                }
+            },
+            Decay(keep) => {
+                for i in range(0, PIXELS) {
+                    let level = if lit(i) { 1.0 } else { self.levels[i] * keep };
+                    self.set_level(i, level);
+                }
+            }
+        }
+        self.seen = display.generation();
+    }
+
+    /// A counter that goes up whenever the levels change, so there's
+    /// no need to redraw while it stays the same.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Whether any level in row `y` has changed since generation
+    /// `since`.
+    pub fn row_changed(&self, y: uint, since: u64) -> bool {
+        self.row_gen[y] > since
+    }
+
+    /// How brightly each pixel shows, from 0 (off) to 1 (on), row by
+    /// row.
+    pub fn levels<'a>(&'a self) -> &'a [f32] {
+        self.levels.as_slice()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Flicker, Mode, Raw, Blend, Stable, Decay};
+    use display::Display;
+
+    fn lit() -> Display {
+        let mut d = Display::new();
+        d.draw([0x80], 0, 0);
+        d
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Mode::parse("off"), Ok(Raw));
+        assert_eq!(Mode::parse("blend"), Ok(Blend(2)));
+        assert_eq!(Mode::parse("stable:3"), Ok(Stable(3)));
+        assert_eq!(Mode::parse("decay:0.25"), Ok(Decay(0.25)));
+        assert!(Mode::parse("blend:0").is_err());
+        assert!(Mode::parse("decay:2").is_err());
+        assert!(Mode::parse("smear").is_err());
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut f = Flicker::new(Blend(2));
+        f.tick(&lit());
+        assert_eq!(f.levels()[0], 1.0);
+        f.tick(&Display::new());
+        assert_eq!(f.levels()[0], 0.5);
+        f.tick(&Display::new());
+        assert_eq!(f.levels()[0], 0.0);
+    }
+
+    #[test]
+    fn test_stable() {
+        // An erase-and-redraw shorter than N ticks never shows.
+        let mut f = Flicker::new(Stable(2));
+        f.tick(&lit());
+        assert_eq!(f.levels()[0], 0.0);
+        f.tick(&lit());
+        assert_eq!(f.levels()[0], 1.0);
+        f.tick(&Display::new());
+        f.tick(&lit());
+        assert_eq!(f.levels()[0], 1.0);
+    }
+
+    #[test]
+    fn test_generation() {
+        let mut f = Flicker::new(Raw);
+        f.tick(&Display::new());
+        let gen = f.generation();
+        let d = lit();
+        f.tick(&d);
+        assert_eq!(f.levels()[0], 1.0);
+        assert!(f.generation() > gen);
+        assert!(f.row_changed(0, gen) && !f.row_changed(1, gen));
+        let gen = f.generation();
+        f.tick(&d);
+        assert_eq!(f.generation(), gen);
+        assert!(!f.row_changed(0, gen));
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut f = Flicker::new(Decay(0.5));
+        f.tick(&lit());
+        f.tick(&Display::new());
+        assert_eq!(f.levels()[0], 0.5);
+        f.tick(&Display::new());
+        assert_eq!(f.levels()[0], 0.25);
+        f.tick(&lit());
+        assert_eq!(f.levels()[0], 1.0);
+    }
+}
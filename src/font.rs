@@ -0,0 +1,207 @@
+//! The hex digit sprites interpreters keep in memory: a small 4x5
+//! font for `FX29`, and SCHIP's big 8x10 one for `FX30`. Each
+//! interpreter drew its own, and some programs rely on the details.
+
+use std::ascii::StrAsciiExt;
+use std::io::File;
+
+use mem::{FONT_SPRITE_SIZE, FONT_SPRITES};
+
+pub static DEFAULT_PRESET: &'static str = "octo";
+pub static BIG_SPRITE_SIZE: uint = 10;
+pub static SMALL_SIZE: uint = FONT_SPRITE_SIZE * FONT_SPRITES;
+pub static MAX_BIG_SIZE: uint = BIG_SPRITE_SIZE * 16;
+
+pub static PRESETS: [&'static str, ..6] = ["vip", "dream6800", "eti660", "schip", "octo",
+                                           "fishnchips"];
+
+static VIP: [u8, ..SMALL_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x60, 0x20, 0x20, 0x20, 0x70, // 0 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0, 0x10, 0xF0, 0x10, 0xF0, // 2 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, 0xF0, 0x80, 0xF0, 0x10, 0xF0, // 4 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x10, 0x10, 0x10, // 6 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0, 0x10, 0xF0, // 8 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, 0xF0, 0x50, 0x70, 0x50, 0xF0, // A B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, 0xF0, 0x50, 0x50, 0x50, 0xF0, // C D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80, // E F
+];
+
+static DREAM_6800: [u8, ..SMALL_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, 0x40, 0x40, 0x40, 0x40, 0x40,
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, 0xE0, 0x20, 0xE0, 0x20, 0xE0,
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0x20, 0xE0,
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, 0xE0, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, 0xE0, 0xA0, 0xE0, 0x20, 0xE0,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, 0xC0, 0xA0, 0xE0, 0xA0, 0xC0,
+    0xE0, 0x80, 0x80, 0x80, 0xE0, 0xC0, 0xA0, 0xA0, 0xA0, 0xC0,
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80,
+];
+
+static ETI_660: [u8, ..SMALL_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, 0x20, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, 0xE0, 0x20, 0xE0, 0x20, 0xE0,
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, 0xE0, 0x80, 0xE0, 0x20, 0xE0,
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, 0xE0, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, 0xE0, 0xA0, 0xE0, 0x20, 0xE0,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, 0x80, 0x80, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x80, 0x80, 0x80, 0xE0, 0x20, 0x20, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80,
+];
+
+// SCHIP's small font, which Octo uses too, and which fries always had.
+static SCHIP: [u8, ..SMALL_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70,
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0, 0x10, 0xF0, 0x10, 0xF0,
+    0x90, 0x90, 0xF0, 0x10, 0x10, 0xF0, 0x80, 0xF0, 0x10, 0xF0,
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x20, 0x40, 0x40,
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0, 0x10, 0xF0,
+    0xF0, 0x90, 0xF0, 0x90, 0x90, 0xE0, 0x90, 0xE0, 0x90, 0xE0,
+    0xF0, 0x80, 0x80, 0x80, 0xF0, 0xE0, 0x90, 0x90, 0x90, 0xE0,
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80,
+];
+
+static FISH_N_CHIPS: [u8, ..SMALL_SIZE] = [
+    0x60, 0xA0, 0xA0, 0xA0, 0xC0, 0x40, 0xC0, 0x40, 0x40, 0xE0,
+    0xC0, 0x20, 0x40, 0x80, 0xE0, 0xC0, 0x20, 0x40, 0x20, 0xC0,
+    0x20, 0xA0, 0xE0, 0x20, 0x20, 0xE0, 0x80, 0xC0, 0x20, 0xC0,
+    0x40, 0x80, 0xC0, 0xA0, 0x40, 0xE0, 0x20, 0x60, 0x40, 0x40,
+    0x40, 0xA0, 0x40, 0xA0, 0x40, 0x40, 0xA0, 0x60, 0x20, 0x40,
+    0x40, 0xA0, 0xE0, 0xA0, 0xA0, 0xC0, 0xA0, 0xC0, 0xA0, 0xC0,
+    0x60, 0x80, 0x80, 0x80, 0x60, 0xC0, 0xA0, 0xA0, 0xA0, 0xC0,
+    0xE0, 0x80, 0xC0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80,
+];
+
+// SCHIP 1.1's big digits, 0 to 9 only.
+static SCHIP_BIG: [u8, ..BIG_SPRITE_SIZE * 10] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+static OCTO_BIG: [u8, ..MAX_BIG_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+static FISH_N_CHIPS_BIG: [u8, ..MAX_BIG_SIZE] = [
+    0x7C, 0xC6, 0xCE, 0xDE, 0xD6, 0xF6, 0xE6, 0xC6, 0x7C, 0x00,
+    0x10, 0x30, 0xF0, 0x30, 0x30, 0x30, 0x30, 0x30, 0xFC, 0x00,
+    0x78, 0xCC, 0xCC, 0x0C, 0x18, 0x30, 0x60, 0xCC, 0xFC, 0x00,
+    0x78, 0xCC, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0xCC, 0x78, 0x00,
+    0x0C, 0x1C, 0x3C, 0x6C, 0xCC, 0xFE, 0x0C, 0x0C, 0x1E, 0x00,
+    0xFC, 0xC0, 0xC0, 0xC0, 0xF8, 0x0C, 0x0C, 0xCC, 0x78, 0x00,
+    0x38, 0x60, 0xC0, 0xC0, 0xF8, 0xCC, 0xCC, 0xCC, 0x78, 0x00,
+    0xFE, 0xC6, 0xC6, 0x06, 0x0C, 0x18, 0x30, 0x30, 0x30, 0x00,
+    0x78, 0xCC, 0xCC, 0xEC, 0x78, 0xDC, 0xCC, 0xCC, 0x78, 0x00,
+    0x7C, 0xC6, 0xC6, 0xC6, 0x7C, 0x18, 0x18, 0x30, 0x70, 0x00,
+    0x30, 0x78, 0xCC, 0xCC, 0xCC, 0xFC, 0xCC, 0xCC, 0xCC, 0x00,
+    0xFC, 0x66, 0x66, 0x66, 0x7C, 0x66, 0x66, 0x66, 0xFC, 0x00,
+    0x3C, 0x66, 0xC6, 0xC0, 0xC0, 0xC0, 0xC6, 0x66, 0x3C, 0x00,
+    0xF8, 0x6C, 0x66, 0x66, 0x66, 0x66, 0x66, 0x6C, 0xF8, 0x00,
+    0xFE, 0x62, 0x60, 0x64, 0x7C, 0x64, 0x60, 0x62, 0xFE, 0x00,
+    0xFE, 0x66, 0x62, 0x64, 0x7C, 0x64, 0x60, 0x60, 0xF0, 0x00,
+];
+
+/// A small font, and a big one of 10 or 16 digits.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Font {
+    pub small: Vec<u8>,
+    pub big: Vec<u8>,
+}
+
+impl Font {
+    /// Look up an interpreter's font by name. Those from before
+    /// SCHIP had no big font, so they get SCHIP's.
+    pub fn preset(name: &str) -> Option<Font> {
+        let (small, big) = match name.to_ascii_lower().as_slice() {
+            "vip" | "cosmac" => (VIP.as_slice(), SCHIP_BIG.as_slice()),
+            "dream6800" => (DREAM_6800.as_slice(), SCHIP_BIG.as_slice()),
+            "eti660" => (ETI_660.as_slice(), SCHIP_BIG.as_slice()),
+            "schip" => (SCHIP.as_slice(), SCHIP_BIG.as_slice()),
+            "octo" => (SCHIP.as_slice(), OCTO_BIG.as_slice()),
+            "fishnchips" => (FISH_N_CHIPS.as_slice(), FISH_N_CHIPS_BIG.as_slice()),
+            _ => return None
+        };
+        Some(Font { small: small.to_vec(), big: big.to_vec() })
+    }
+
+    /// Load a preset name, or else a font file.
+    pub fn load(name: &str) -> Result<Font, String> {
+        match Font::preset(name) {
+            Some(font) => Ok(font),
+            None => {
+                let path = Path::new(name);
+                let data = try!(File::open(&path).read_to_end().map_err(|e| {
+                    format!("Error reading font {}: {}", path.display(), e.desc)
+                }));
+                Font::parse(data.as_slice()).map_err(|e| {
+                    format!("Error in font {}: {}", path.display(), e)
+                })
+            }
+        }
+    }
+
+    /// A font file is the 80 bytes of the small font, then perhaps
+    /// the 100 or 160 of a big one; without one, it gets SCHIP's.
+    pub fn parse(data: &[u8]) -> Result<Font, String> {
+        let big = match data.len() {
+            SMALL_SIZE => SCHIP_BIG.as_slice(),
+            n if n == SMALL_SIZE + SCHIP_BIG.len() || n == SMALL_SIZE + MAX_BIG_SIZE => {
+                data.slice_from(SMALL_SIZE)
+            },
+            n => return Err(format!("expected {}, {} or {} bytes, found {}", SMALL_SIZE,
+                                    SMALL_SIZE + SCHIP_BIG.len(), SMALL_SIZE + MAX_BIG_SIZE, n))
+        };
+        Ok(Font { small: data.slice_to(SMALL_SIZE).to_vec(), big: big.to_vec() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Font, PRESETS, SMALL_SIZE};
+
+    #[test]
+    fn test_presets() {
+        for name in PRESETS.iter() {
+            let font = Font::preset(*name).unwrap();
+            assert_eq!(font.small.len(), SMALL_SIZE);
+            assert!(font.big.len() == 100 || font.big.len() == 160);
+        }
+        assert_eq!(Font::preset("VIP").unwrap().small.slice(5, 10),
+                   [0x60, 0x20, 0x20, 0x20, 0x70].as_slice());
+        assert!(Font::preset("chip48").is_none());
+    }
+
+    #[test]
+    fn test_parse() {
+        let small = Vec::from_elem(SMALL_SIZE, 0xf0u8);
+        let font = Font::parse(small.as_slice()).unwrap();
+        assert_eq!(font.big, Font::preset("schip").unwrap().big);
+
+        let mut both = small.clone();
+        both.grow(160, &0x18);
+        assert_eq!(Font::parse(both.as_slice()).unwrap().big.len(), 160);
+        assert!(Font::parse(small.slice_to(79)).is_err());
+    }
+}
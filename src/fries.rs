@@ -3,448 +3,380 @@
 
 #[phase(plugin, link)] extern crate log;
 
+extern crate getopts;
 extern crate rsfml;
+extern crate serialize;
+extern crate time;
+extern crate toml;
 
 use rsfml::graphics::{RenderWindow, Texture};
 use rsfml::window::keyboard;
 use rsfml::window::keyboard::Key;
 
-use std::collections::TreeMap;
-use std::default::Default;
-use std::rand::{Rng, StdRng};
+use std::io::File;
 
-use cpu::Registers;
+use audio::Recorder;
+use clock::Clock;
 use display::Display;
-use mem::{ROM_LOC, Memory, Rom};
-
+use flicker::Flicker;
+use input::{InputQueue, KeyDown, KeyUp};
+use keymap::Keymap;
+use record::VideoRecorder;
+use video::Pipeline;
+use timing::Timing;
+use vm::{Vm, Trapped};
+
+mod analysis;
+mod asm;
+mod audio;
+mod cartridge;
+mod cli;
+mod clock;
+mod config;
 mod cpu;
+mod crc32;
 mod display;
+mod flicker;
+mod font;
+mod gif;
+mod inflate;
+mod input;
+mod instr;
+mod keymap;
+mod loader;
 mod mem;
-
-static SCALE: uint         = 10;
-static WINDOW_WIDTH: uint  = display::COLS * SCALE;
-static WINDOW_HEIGHT: uint = display::ROWS * SCALE;
-
-static FONT: [u8, ..mem::FONT_SPRITE_SIZE * mem::FONT_SPRITES] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-];
-
-struct Vm {
-    mem: Memory,
-    reg: Registers,
-    pc: u16,
-    dt: u8, // delay timer
-    st: u8, // sound timer
-    i: u16, // index register
-    ret_stack: Vec<u16>, // return stack
-    display: Display,
-    rng: StdRng,
-    blocked: bool,
-    blocked_reg: u8,
-    keys: u16,
+mod octo;
+mod palette;
+mod platform;
+mod png;
+mod quirks;
+mod record;
+mod romdb;
+mod sha1;
+mod timing;
+mod video;
+mod vm;
+
+static SCALE: uint = 10;
+static CYCLES_PER_FRAME: uint = 100;
+static FAST_FORWARD_RATE: f64 = 4.0;
+static SLOW_MOTION_RATE: f64 = 0.25;
+static INPUT_POLL_NS: u64 = 2000000;
+
+/// How the display is presented in the window.
+struct Video {
+    scale: uint,
+    pipeline: Pipeline,
+    flicker: Flicker,
+    screenshot_scale: uint,
+    screenshot_prefix: Path, // hotkey screenshots go to PREFIX-N.png
 }
 
-impl Vm {
-    fn new(r: Rom, rng: StdRng) -> Vm {
-        let mut mem = Memory::new();
-        mem.load_rom(r);
-        mem.load_font(FONT);
-
-        Vm {
-            mem: mem,
-            reg: Default::default(),
-            pc: mem::ROM_LOC,
-            dt: 0,
-            st: 0,
-            i: 0,
-            ret_stack: vec![],
-            display: Display::new(),
-            rng: rng,
-            blocked: false,
-            blocked_reg: 255,
-            keys: 0
-        }
+// FIXME: real error type I guess?
+fn window(scale: uint) -> Result<RenderWindow, String> {
+    use rsfml::graphics::RenderWindow;
+    use rsfml::window::{ContextSettings, DefaultStyle, VideoMode};
+    let settings = ContextSettings::default();
+    match RenderWindow::new(VideoMode::new_init(display::COLS * scale, display::ROWS * scale, 32),
+                            "CHIP-8",
+                            DefaultStyle,
+                            &settings) {
+        Some(window) => Ok(window),
+        None => Err("Error: Could not create RenderWindow.".to_string())
     }
+}
 
-    fn math_op(&mut self, x: u8, y: u8, op: u8) {
-        let vx = self.reg.get(x);
-        let vy = self.reg.get(y);
-        match op {
-            0x0 => { // VX = VY
-                let dst = self.reg.get_mut(x);
-                *dst = vy;
-            },
-            0x1 => { // VX |= VY
-                let dst = self.reg.get_mut(x);
-                *dst |= vy;
-            },
-            0x2 => { // VX &= VY
-                let dst = self.reg.get_mut(x);
-                *dst &= vy;
-            },
-            0x3 => { // VX ^= VY
-                let dst = self.reg.get_mut(x);
-                *dst ^= vy;
-            },
-            0x4 => { // VX += VY, carry -> VF
-                let res: u8 = {
-                    let dst = self.reg.get_mut(x);
-                    *dst += vy;
-                    *dst
-                };
-                self.reg.set_flag((res < vy) as u8);
-            },
-            0x5 => { // VX -= VY, borrow -> VF
-                self.reg.set_flag((vy > vx) as u8);
-                let dst = self.reg.get_mut(x);
-                *dst -= vy;
-            },
-            0x6 => { // VX = VY >> 1, VF = LSB(VY)
-                // The documentation + implementations of the shift
-                // instructions for CHIP-8 are inconsistent and
-                // contradictory to say the least. We follow Octo
-                // here.
-                let res = vy >> 1;
-                self.reg.set_flag(vy & 0x1);
-                *self.reg.get_mut(x) = res;
-            },
-            0x7 => { // VX = VY - VX, borrow -> VF
-                self.reg.set_flag((vx > vy) as u8);
-                let dst = self.reg.get_mut(x);
-                *dst = vy - *dst;
-            },
-            0xe => { // VX = VY << 1, VF = MSB(VY)
-                // The documentation + implementations of the shift
-                // instructions for CHIP-8 are inconsistent and
-                // contradictory to say the least. We follow Octo
-                // here.
-                let res = vy << 1;
-                self.reg.set_flag((vy >> 7) & 0x1);
-                *self.reg.get_mut(x) = res;
-            },
-            _ => fail!("math op {:01x} unimplemented", op)
-        }
+fn new_texture(width: uint, height: uint) -> Result<Texture, String> {
+    match Texture::new(width, height) {
+        Some(texture) => Ok(texture),
+        None => Err("Error: Could not create texture.".to_string())
     }
+}
 
-    fn misc(&mut self, x: u8, nn: u8) {
-        match nn {
-            0x07 => { // set register from delay timer
-                *self.reg.get_mut(x) = self.dt;
-            },
-            0x0a => { // wait for keypress
-                self.blocked_reg = x;
-                self.blocked = true;
-            },
-            0x15 => {
-                self.dt = self.reg.get(x);
-            },
-            0x18 => {
-                self.st = self.reg.get(x);
-            },
-            0x29 => {
-                self.i = self.mem.font_offset(self.reg.get(x));
-            },
-            0x33 => { // set [I, I+1, I+2] to BCD repr of VX
-                let val = self.reg.get(x);
-                let dst: &mut [u8] = self.mem.mut_slice(self.i, self.i + 3);
-                dst[0] = (val / 100) % 10;
-                dst[1] = (val / 10) % 10;
-                dst[2] = val % 10;
-            },
-            0x1e => {
-                self.i += self.reg.get(x) as u16;
-            },
-            0x55 => { // store registers to memory
-                let new_i = self.i + x as u16 + 1;
-                let dst: &mut [u8] = self.mem.mut_slice(self.i, new_i);
-                let src: &[u8] = self.reg.slice(0, x + 1);
-                dst.copy_from(src);
-                self.i = new_i;
-            },
-            0x65 => { // load registers from memory
-                let new_i = self.i + x as u16 + 1;
-                let dst: &mut [u8] = self.reg.mut_slice(0, x + 1);
-                let src: &[u8] = self.mem.slice(self.i, new_i);
-                dst.copy_from(src);
-                self.i = new_i;
-            },
-            _ => {
-                fail!("f{:01x}{:02x} not implemented", x, nn)
-            }
-        }
-    }
+/// Run the current flicker levels through the pipeline, and upload
+/// the part of it the rows changed since generation `since` reach,
+/// or all of it if there's nothing on the texture yet. The pipeline
+/// still renders the whole frame; only the upload is cut down.
+fn render(video: &Video, texture: &mut Texture, since: Option<u64>, width: uint,
+          height: uint) {
+    let changed: Vec<uint> = range(0, display::ROWS).filter(|&y| {
+        since.map_or(true, |gen| video.flicker.row_changed(y, gen))
+    }).collect();
+    let (first, end) = match (changed.head(), changed.last()) {
+        (Some(&first), Some(&last)) => (first, last + 1),
+        _ => return
+    };
+    let (top, bottom) = video.pipeline.output_rows(first, end, width, height);
+    let img = video.pipeline.render_levels(video.flicker.levels(), width, height);
+    let row = img.width * 4;
+    texture.update_from_pixels(img.data.slice(top * row, bottom * row), img.width,
+                               bottom - top, 0, top);
+}
 
-    fn tick(&mut self) {
-        let (lo, hi) = (self.mem.get(self.pc), self.mem.get(self.pc + 1));
-        let ins: u16 = (lo as u16) << 8 | hi as u16;
-        let op = (lo >> 4) & 0xf;
-        let x = lo & 0xf;
-        let y = (hi >> 4) & 0xf;
-        let n = hi & 0xf;
-        let nn = hi & 0xff;
-        let nnn = ins & 0xfff;
+/// A video recording, rendered on its own terms.
+struct Recording {
+    rec: VideoRecorder<File>,
+    pipeline: Pipeline,
+    flicker: Option<Flicker>, // applied to the recording if set
+    scale: uint,
+}
 
-        debug!("{:04x}", ins);
-        debug!("{}", self.reg);
+/// What gets recorded as the emulator runs, one frame per tick.
+struct Capture {
+    audio: Option<Recorder<File>>,
+    video: Option<Recording>,
+}
 
-        self.pc += 2;
+impl Capture {
+    fn none() -> Capture {
+        Capture { audio: None, video: None }
+    }
 
-        if ins == 0x00e0 { // clear screen
-            self.display.clear();
-            return;
+    fn frame(&mut self, vm: &Vm) -> Result<(), String> {
+        match self.audio {
+            Some(ref mut rec) => {
+                try!(rec.frame(vm.voice(), vm.sound_active())
+                        .map_err(|e| format!("Error recording audio: {}", e.desc)));
+            },
+            None => {}
         }
-
-        if ins == 0x00ee { // return
-            self.pc = self.ret_stack.pop().expect("stack underflow");
-            return;
+        match self.video {
+            Some(ref mut v) => {
+                let (width, height) = (display::COLS * v.scale, display::ROWS * v.scale);
+                let img = match v.flicker {
+                    Some(ref mut flicker) => {
+                        flicker.tick(vm.display());
+                        v.pipeline.render_levels(flicker.levels(), width, height)
+                    },
+                    None => v.pipeline.render(vm.display(), width, height)
+                };
+                try!(v.rec.frame(&img).map_err(|e| {
+                    format!("Error recording video: {}", e.desc)
+                }));
+            },
+            None => {}
         }
+        Ok(())
+    }
 
-        // match_hex! macro ??
-        match op {
-            0x1 => { // jump
-                self.pc = nnn;
-            },
-            0x2 => { // call
-                self.ret_stack.push(self.pc);
-                self.pc = nnn;
-            },
-            0x3 => { // skip if VX eq NN
-                if self.reg.get(x) == nn {
-                    self.pc += 2;
-                }
-            },
-            0x4 => { // skip if VX ne NN
-                if self.reg.get(x) != nn {
-                    self.pc += 2;
-                }
-            },
-            0x5 => { // skip if VX == VY
-                if self.reg.get(x) == self.reg.get(y) {
-                    self.pc += 2;
-                }
-            },
-            0x6 => { // store
-                *self.reg.get_mut(x) = nn;
-            },
-            0x7 => { // add
-                let r = self.reg.get_mut(x);
-                *r = *r + nn;
-            },
-            0x8 => { // math
-                self.math_op(x, y, n);
-            },
-            0x9 => { // skip if VX != VY
-                if self.reg.get(x) != self.reg.get(y) {
-                    self.pc += 2
-                }
-            },
-            0xa => { // set index register
-                self.i = nnn;
+    fn finish(self) -> Result<(), String> {
+        match self.audio {
+            Some(rec) => {
+                try!(rec.finish().map_err(|e| format!("Error recording audio: {}", e.desc)));
             },
-            0xb => { // jump to nnn + v0
-                self.pc = nnn + self.reg.get(0) as u16;
-            },
-            0xc => { // random number
-                *self.reg.get_mut(x) = self.rng.gen::<u8>() & nn;
-            }
-            0xd => { // draw sprite
-                let sprite = self.mem.slice(self.i, self.i + (n as u16));
-                let (vx, vy) = (self.reg.get(x), self.reg.get(y));
-                let flag = if self.display.draw(sprite, vx, vy) { 0x1 } else { 0x0 };
-                self.reg.set_flag(flag);
-            },
-            0xe if nn == 0x9e => { // skip if key in VX is pressed
-                if self.is_key_pressed(self.reg.get(x) as uint) {
-                    self.pc += 2;
-                }
-            },
-            0xe if nn == 0xa1 => { // skip if key in VX is not pressed
-                if !self.is_key_pressed(self.reg.get(x) as uint) {
-                    self.pc += 2;
-                }
-            },
-            0xf => {
-                self.misc(x, nn);
+            None => {}
+        }
+        match self.video {
+            Some(v) => {
+                try!(v.rec.finish().map_err(|e| format!("Error recording video: {}", e.desc)));
             },
-            _ => fail!("{:04x} not yet implemented", ins)
+            None => {}
         }
+        Ok(())
     }
+}
 
-    fn render(&mut self, texture: &mut Texture) {
-        if self.dt > 0 { self.dt -= 1 }
-        if self.st > 0 { self.st -= 1 }
+/// Save the display as a PNG, at `scale` pixels per display pixel.
+fn save_screenshot(display: &Display, pipeline: &Pipeline, scale: uint,
+                   path: &Path) -> Result<(), String> {
+    let img = pipeline.render_scaled(display, scale);
+    File::create(path).and_then(|mut f| png::write_png(&mut f, &img)).map_err(|e| {
+        format!("Error saving screenshot {}: {}", path.display(), e.desc)
+    })
+}
 
-        let on: [u8, ..4]  = [0x6c, 0x71, 0xc4, 0xff];
-        let off: [u8, ..4] = [0x00, 0x2b, 0x36, 0xff];
+/// Take a screenshot for the hotkey, numbering it after any that
+/// are already there.
+fn hotkey_screenshot(display: &Display, video: &Video) {
+    use std::io::stdio;
 
-        let vec: Vec<u8> = self.display.pixels().flat_map(|px| {
-            if px.is_on() { on.iter() } else { off.iter() }
-        }).map(|&x| x).collect();
-        texture.update_from_pixels(vec.as_slice(), display::COLS, display::ROWS, 0, 0);
+    let mut n = 1u;
+    let mut path;
+    loop {
+        path = Path::new(format!("{}-{:03}.png", video.screenshot_prefix.display(), n));
+        if !path.exists() {
+            break;
+        }
+        n += 1;
     }
-
-    fn is_key_pressed(&self, key: uint) -> bool {
-        assert!(key < 16);
-        (self.keys & 1 << key) >> key == 1
+    match save_screenshot(display, &video.pipeline, video.screenshot_scale, &path) {
+        Ok(()) => println!("Saved screenshot {}", path.display()),
+        Err(e) => { let _ = writeln!(stdio::stderr(), "{}", e); }
     }
+}
 
-    fn keydown(&mut self, key: uint) {
-        assert!(key < 16);
-        self.keys |= 1 << key;
-    }
+/// Warn about the instructions the VM couldn't run, past the first
+/// `reported` of them.
+fn report_illegal(vm: &Vm, reported: uint) {
+    use std::io::stdio;
 
-    fn keyup(&mut self, key: uint) {
-        assert!(key < 16);
-        self.keys &= !(1 << key);
-        if self.blocked {
-            self.blocked = false;
-            *self.reg.get_mut(self.blocked_reg) = key as u8;
-            self.blocked_reg = 255;
-        }
+    for &(addr, ins) in vm.illegal_instructions().slice_from(reported).iter() {
+        let what = match analysis::extension(ins) {
+            Some((_, name)) => format!("unsupported instruction {:04X} ({})", ins, name),
+            None => format!("undefined instruction {:04X}", ins)
+        };
+        let _ = writeln!(stdio::stderr(), "Warning: {} at {:03X}", what, addr);
     }
 }
 
-// FIXME: real error type I guess?
-fn window() -> Result<RenderWindow, String> {
-    use rsfml::graphics::RenderWindow;
-    use rsfml::window::{Close, ContextSettings, VideoMode};
-    let settings = ContextSettings::default();
-    match RenderWindow::new(VideoMode::new_init(WINDOW_WIDTH, WINDOW_HEIGHT, 32),
-                            "CHIP-8",
-                            Close,
-                            &settings) {
-        Some(window) => Ok(window),
-        None => Err("Could not create RenderWindow.".to_string())
+/// Emulate one 60 Hz tick, covering real time `start` to `end`: run
+/// the CPU (delivering input as it goes), then the timers. A trap
+/// is left for the caller.
+fn emulate_tick(vm: &mut Vm, timing: &mut Timing, input: &mut InputQueue,
+                start: u64, end: u64,
+                capture: &mut Capture) -> Result<(), String> {
+    let reported = vm.illegal_instructions().len();
+    input.run_tick(vm, timing.cycles_for_tick(), start, end);
+    report_illegal(vm, reported);
+    match vm.error() {
+        Some(&Trapped(_)) | None => {},
+        Some(e) => return Err(format!("Error at {:03X}: {}", vm.pc(), e)),
     }
+    try!(capture.frame(vm));
+    vm.tick_timers();
+    Ok(())
 }
 
-fn texture() -> Result<Texture, String> {
-    match Texture::new(display::COLS as uint, display::ROWS as uint) {
-        Some(texture) => Ok(texture),
-        None => Err("Could not create texture.".to_string())
+fn run_headless<C: Clock>(mut vm: Vm, mut timing: Timing, clock: &mut C, frames: uint,
+                          mut capture: Capture) -> Result<Vm, String> {
+    use std::cmp::min;
+
+    let mut input = InputQueue::new();
+    let mut frame = 0;
+    while frame < frames {
+        let now = clock.now();
+        let ticks = min(timing.update(now), frames - frame);
+        for _ in range(0, ticks) {
+            try!(emulate_tick(&mut vm, &mut timing, &mut input, now, now, &mut capture));
+            match vm.error() {
+                Some(e) => return Err(format!("Error at {:03X}: {}, with no window to pause in",
+                                              vm.pc(), e)),
+                None => {}
+            }
+        }
+        frame += ticks;
+        clock.sleep(timing.until_next_tick());
     }
+    try!(capture.finish());
+    Ok(vm)
 }
 
-fn keymap() -> TreeMap<Key, uint> {
-    let mut map = TreeMap::new();
-    map.insert(keyboard::X,    0x0);
-    map.insert(keyboard::Num1, 0x1);
-    map.insert(keyboard::Num2, 0x2);
-    map.insert(keyboard::Num3, 0x3);
-    map.insert(keyboard::Q,    0x4);
-    map.insert(keyboard::W,    0x5);
-    map.insert(keyboard::E,    0x6);
-    map.insert(keyboard::A,    0x7);
-    map.insert(keyboard::S,    0x8);
-    map.insert(keyboard::D,    0x9);
-    map.insert(keyboard::Z,    0xa);
-    map.insert(keyboard::C,    0xb);
-    map.insert(keyboard::Num4, 0xc);
-    map.insert(keyboard::R,    0xd);
-    map.insert(keyboard::F,    0xe);
-    map.insert(keyboard::V,    0xf);
-    map
-}
-
-fn run_emulator(mut vm: Vm) -> Result<Vm, String> {
-    use std::io::Timer;
-    use rsfml::graphics::Sprite;
+/// Handle the emulator's own hotkeys. Returns false if the key
+/// isn't one of them.
+fn timing_hotkey(timing: &mut Timing, key: Key) -> bool {
+    fn toggle_rate(timing: &mut Timing, rate: f64) {
+        let new = if timing.rate() == rate { 1.0 } else { rate };
+        timing.set_rate(new);
+    }
 
-    static CYCLES_PER_FRAME: u16 = 100;
+    match key {
+        keyboard::F5 => {
+            let paused = timing.is_paused();
+            timing.set_paused(!paused);
+        },
+        keyboard::F6 => timing.frame_advance(),
+        keyboard::F7 => toggle_rate(timing, SLOW_MOTION_RATE),
+        keyboard::F8 => toggle_rate(timing, FAST_FORWARD_RATE),
+        _ => return false
+    }
+    true
+}
 
-    let mut win = try!(window());
-    let mut texture = try!(texture());
-    let keymap = keymap();
+fn run_emulator<C: Clock>(mut vm: Vm, mut timing: Timing, clock: &mut C, keymap: Keymap,
+                          mut video: Video,
+                          mut capture: Capture) -> Result<Vm, String> {
+    use rsfml::graphics::{FloatRect, Sprite, View};
+    use std::cmp::min;
 
-    let mut timer = Timer::new().unwrap();
-    let sixty_hz = timer.periodic(1000 / 60); // not really 60 Hz...
+    let (mut width, mut height) = (display::COLS * video.scale, display::ROWS * video.scale);
+    let mut win = try!(window(video.scale));
+    let mut texture = try!(new_texture(width, height));
+    let mut input = InputQueue::new();
+    let mut shown = None; // the flicker generation on screen
 
     'main: loop {
         use rsfml::window::{event, keyboard};
 
-        for _ in range(0, CYCLES_PER_FRAME) {
-            if vm.blocked {
-                break;
+        // Drain every pending event, stamping each with when we saw
+        // it, so the VM gets them at the right point in the frame.
+        loop {
+            let now = clock.now();
+            match win.poll_event() {
+                event::NoEvent => break,
+                event::Closed => break 'main,
+                event::Resized { width: w, height: h } => {
+                    // Render at the window's own size, so the video
+                    // pipeline does all of the scaling.
+                    width = w as uint;
+                    height = h as uint;
+                    texture = try!(new_texture(width, height));
+                    let rect = FloatRect::new(0., 0., w as f32, h as f32);
+                    win.set_view(&View::new_from_rect(&rect).unwrap()); // FIXME
+                    shown = None;
+                },
+                event::KeyPressed { code: key, .. } => {
+                    if key == keyboard::Escape {
+                        break 'main;
+                    } else if key == keyboard::F12 {
+                        hotkey_screenshot(vm.display(), &video);
+                    } else {
+                        // Unpausing after a trap skips the instruction.
+                        if key == keyboard::F5 && timing.is_paused() {
+                            vm.resume();
+                        }
+                        if !timing_hotkey(&mut timing, key) {
+                            keymap.find(&key).map(|code| input.push(now, KeyDown(code)));
+                        }
+                    }
+                },
+                event::KeyReleased { code: key, .. }=> {
+                    keymap.find(&key).map(|code| input.push(now, KeyUp(code)));
+                },
+                _ => {}
             }
-            vm.tick();
         }
 
-        match win.poll_event() {
-            event::Closed => break 'main,
-            event::KeyPressed { code: key, .. } => {
-                if key == keyboard::Escape {
-                    break 'main;
-                } else {
-                    keymap.find(&key).map(|&code| vm.keydown(code));
-                }
-            },
-            event::KeyReleased { code: key, .. }=> {
-                keymap.find(&key).map(|&code| vm.keyup(code));
-            },
-            _ => {}
-        };
-        sixty_hz.recv();
-        vm.render(&mut texture);
-        let mut sprite = Sprite::new_with_texture(&texture).unwrap(); // FIXME
-        sprite.scale2f(10., 10.);
-        win.draw(&sprite);
-        win.display();
+        // The due ticks cover the real time leading up to now.
+        let now = clock.now();
+        let ticks = timing.update(now);
+        let duration = timing.tick_duration();
+        for i in range(0, ticks) {
+            let end = now - min(now, (ticks - 1 - i) as u64 * duration);
+            let start = end - min(end, duration);
+            try!(emulate_tick(&mut vm, &mut timing, &mut input, start, end, &mut capture));
+            video.flicker.tick(vm.display());
+            match vm.error() {
+                Some(&Trapped(ins)) => {
+                    if !timing.is_paused() {
+                        println!("Trapped on {:04X} at {:03X}: F5 skips it and carries on",
+                                 ins, vm.pc());
+                        timing.set_paused(true);
+                    }
+                    break;
+                },
+                _ => {}
+            }
+        }
+
+        // However many ticks ran, present only the latest frame, and
+        // only if it's changed since the last one shown.
+        let generation = video.flicker.generation();
+        if shown != Some(generation) {
+            render(&video, &mut texture, shown, width, height);
+            shown = Some(generation);
+            let sprite = Sprite::new_with_texture(&texture).unwrap(); // FIXME
+            win.draw(&sprite);
+            win.display();
+        }
+
+        // Wake up often enough to timestamp input precisely.
+        clock.sleep(min(timing.until_next_tick(), INPUT_POLL_NS));
     }
 
+    try!(capture.finish());
     Ok(vm)
 }
 
 pub fn main() {
-    use std::io::stdio;
-    use std::io::File;
-    use std::os;
-
-    let mut stderr = stdio::stderr();
-
-    let rom_path = match os::args().as_slice() {
-        [] => { return; },
-        [_] => {
-            let _ = writeln!(stderr, "Usage: fries ROM");
-            return;
-        },
-        [_, ref rom, ..] => Path::new(rom.clone())
-    };
-
-    let mut rom_file = File::open(&rom_path);
-    let rom = match Rom::from_reader(&mut rom_file) {
-        Ok(r) => r,
-        Err(e) => {
-            let _ = writeln!(stderr, "Error loading ROM: {}", e.desc);
-            return;
-        }
-    };
-
-    let rng = match StdRng::new() {
-        Ok(r) => r,
-        Err(e) => {
-            let _ = writeln!(stderr, "Error creating RNG: {}", e.desc);
-            return;
-        }
-    };
-
-    let vm = Vm::new(rom, rng);
-    match run_emulator(vm) {
-        Err(e) => { let _ = writeln!(stderr, "Error: {}", e); },
-        Ok(_) => {},
-    }
+    cli::main()
 }
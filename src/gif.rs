@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::io::IoResult;
+use std::iter::range_step;
+
+use palette::Color;
+use video::Image;
+
+static MAX_CODE_BITS: uint = 12;
+static MAX_SUB_BLOCK: uint = 255;
+
+/// Writes an animated GIF, frame by frame. Each frame gets its own
+/// color table of up to 256 colors; past that, pixels take the
+/// nearest color already in the table.
+pub struct GifWriter<W> {
+    w: W,
+    width: uint,
+    height: uint,
+}
+
+impl<W: Writer> GifWriter<W> {
+    pub fn new(mut w: W, width: uint, height: uint) -> IoResult<GifWriter<W>> {
+        try!(w.write(b"GIF89a"));
+        try!(w.write_le_u16(width as u16));
+        try!(w.write_le_u16(height as u16));
+        try!(w.write([0,    // no global color table
+                      0,    // background color
+                      0])); // no aspect ratio
+        // Loop forever.
+        try!(w.write(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00"));
+        Ok(GifWriter { w: w, width: width, height: height })
+    }
+
+    /// Add a frame, shown for `delay` hundredths of a second.
+    pub fn frame(&mut self, img: &Image, delay: u16) -> IoResult<()> {
+        assert!(img.width == self.width && img.height == self.height);
+        let (table, indices) = index_colors(img);
+
+        let mut depth = 1;
+        while 1 << depth < table.len() {
+            depth += 1;
+        }
+
+        // Graphic control extension, for the delay.
+        try!(self.w.write([0x21, 0xf9, 4, 0]));
+        try!(self.w.write_le_u16(delay));
+        try!(self.w.write([0, 0]));
+
+        // Image descriptor, with a local color table.
+        try!(self.w.write_u8(0x2c));
+        try!(self.w.write_le_u16(0));
+        try!(self.w.write_le_u16(0));
+        try!(self.w.write_le_u16(self.width as u16));
+        try!(self.w.write_le_u16(self.height as u16));
+        try!(self.w.write_u8(0x80 | (depth - 1) as u8));
+        for i in range(0, 1 << depth) {
+            let c = if i < table.len() { table[i] } else { [0, 0, 0, 0] };
+            try!(self.w.write(c.slice_to(3)));
+        }
+
+        let min_code_size = if depth < 2 { 2 } else { depth };
+        try!(self.w.write_u8(min_code_size as u8));
+        for block in lzw(indices.as_slice(), min_code_size).as_slice().chunks(MAX_SUB_BLOCK) {
+            try!(self.w.write_u8(block.len() as u8));
+            try!(self.w.write(block));
+        }
+        self.w.write_u8(0)
+    }
+
+    pub fn finish(mut self) -> IoResult<W> {
+        try!(self.w.write_u8(0x3b));
+        Ok(self.w)
+    }
+}
+
+/// The colors in `img`, and each pixel's index into them.
+fn index_colors(img: &Image) -> (Vec<Color>, Vec<u8>) {
+    fn distance(x: &Color, y: &Color) -> int {
+        range(0u, 3).map(|c| {
+            let d = x[c] as int - y[c] as int;
+            d * d
+        }).fold(0, |a, b| a + b)
+    }
+
+    let mut table: Vec<Color> = vec![];
+    let mut seen = HashMap::new();
+    let mut indices = Vec::with_capacity(img.width * img.height);
+    for px in img.data.as_slice().chunks(4) {
+        let color = [px[0], px[1], px[2], px[3]];
+        let index = match seen.find_copy(&color) {
+            Some(i) => i,
+            None if table.len() < 256 => {
+                table.push(color);
+                seen.insert(color, (table.len() - 1) as u8);
+                (table.len() - 1) as u8
+            },
+            None => {
+                let mut best = 0;
+                for (i, c) in table.iter().enumerate() {
+                    if distance(c, &color) < distance(&table[best], &color) {
+                        best = i;
+                    }
+                }
+                best as u8
+            }
+        };
+        indices.push(index);
+    }
+    (table, indices)
+}
+
+/// Packs codes into bytes, least significant bit first.
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: uint,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: uint) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// GIF's variant of LZW.
+fn lzw(data: &[u8], min_code_size: uint) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = BitWriter { out: vec![], acc: 0, bits: 0 };
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut width = min_code_size + 1;
+
+    out.write(clear, width);
+    if data.is_empty() {
+        out.write(end, width);
+        return out.finish();
+    }
+
+    let mut prefix = data[0] as u16;
+    for &k in data.slice_from(1).iter() {
+        match codes.find_copy(&(prefix, k)) {
+            Some(code) => prefix = code,
+            None => {
+                out.write(prefix, width);
+                if next < 1 << MAX_CODE_BITS {
+                    codes.insert((prefix, k), next);
+                    next += 1;
+                    // The decoder is a code behind us, so widen only
+                    // once it will need to.
+                    if next > 1 << width && width < MAX_CODE_BITS {
+                        width += 1;
+                    }
+                } else {
+                    out.write(clear, width);
+                    codes.clear();
+                    next = end + 1;
+                    width = min_code_size + 1;
+                }
+                prefix = k as u16;
+            }
+        }
+    }
+    out.write(prefix, width);
+    out.write(end, width);
+    out.finish()
+}
+
+/// One frame of a GIF: its size, and each pixel's index into its
+/// color table, row by row.
+pub struct Frame {
+    pub width: uint,
+    pub height: uint,
+    pub indices: Vec<u8>,
+}
+
+pub fn is_gif(data: &[u8]) -> bool {
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+/// Read the frames of a GIF. Colors aren't looked up; only the
+/// indices are kept.
+pub fn read_frames(data: &[u8]) -> Result<Vec<Frame>, String> {
+    if !is_gif(data) {
+        return Err("not a GIF".to_string());
+    }
+    if data.len() < 13 {
+        return Err("GIF data ends early".to_string());
+    }
+    let mut pos = 13;
+    if data[10] & 0x80 != 0 {
+        pos += 3 << ((data[10] & 7) + 1) as uint;
+    }
+
+    let mut frames = vec![];
+    loop {
+        if pos >= data.len() {
+            // The trailer is missing, but what came before may be fine.
+            return if frames.is_empty() {
+                Err("GIF data ends early".to_string())
+            } else {
+                Ok(frames)
+            };
+        }
+        match data[pos] {
+            0x3b => return Ok(frames),
+            0x21 => pos = try!(sub_blocks(data, pos + 2)).val1(),
+            0x2c => {
+                if pos + 11 > data.len() {
+                    return Err("GIF data ends early".to_string());
+                }
+                let word = |i: uint| data[i] as uint | data[i + 1] as uint << 8;
+                let (width, height) = (word(pos + 5), word(pos + 7));
+                let flags = data[pos + 9];
+                pos += 10;
+                if flags & 0x80 != 0 {
+                    pos += 3 << ((flags & 7) + 1) as uint;
+                }
+                if pos >= data.len() {
+                    return Err("GIF data ends early".to_string());
+                }
+                let min_code_size = data[pos] as uint;
+                if min_code_size < 2 || min_code_size > 8 {
+                    return Err(format!("bad GIF code size {}", min_code_size));
+                }
+                let (compressed, end) = try!(sub_blocks(data, pos + 1));
+                pos = end;
+
+                let mut indices = try!(unlzw(compressed.as_slice(), min_code_size));
+                if indices.len() < width * height {
+                    return Err(format!("GIF frame {} is missing pixels", frames.len() + 1));
+                }
+                indices.truncate(width * height);
+                if flags & 0x40 != 0 && width > 0 {
+                    indices = deinterlace(indices.as_slice(), width, height);
+                }
+                frames.push(Frame { width: width, height: height, indices: indices });
+            },
+            b => return Err(format!("unknown GIF block type {:02X}", b))
+        }
+    }
+}
+
+/// Join the sub-blocks starting at `pos`, returning them and where
+/// they end.
+fn sub_blocks(data: &[u8], mut pos: uint) -> Result<(Vec<u8>, uint), String> {
+    let mut out = vec![];
+    loop {
+        if pos >= data.len() {
+            return Err("GIF data ends early".to_string());
+        }
+        let len = data[pos] as uint;
+        pos += 1;
+        if len == 0 {
+            return Ok((out, pos));
+        }
+        if pos + len > data.len() {
+            return Err("GIF data ends early".to_string());
+        }
+        out.push_all(data.slice(pos, pos + len));
+        pos += len;
+    }
+}
+
+/// Put the rows of an interlaced image back in order.
+fn deinterlace(indices: &[u8], width: uint, height: uint) -> Vec<u8> {
+    let mut out = Vec::from_elem(width * height, 0u8);
+    let mut rows = indices.chunks(width);
+    for &(start, step) in [(0u, 8u), (4, 8), (2, 4), (1, 2)].iter() {
+        for y in range_step(start, height, step) {
+            out.mut_slice(y * width, (y + 1) * width).copy_from(rows.next().unwrap());
+        }
+    }
+    out
+}
+
+fn unlzw(data: &[u8], min_code_size: uint) -> Result<Vec<u8>, String> {
+    let clear = 1u << min_code_size;
+    let end = clear + 1;
+    let mut table: Vec<Vec<u8>> = vec![]; // the codes after `end`
+    let mut width = min_code_size + 1;
+    let mut prev: Option<Vec<u8>> = None;
+    let mut out = vec![];
+    let (mut acc, mut bits, mut pos) = (0u, 0u, 0u);
+
+    loop {
+        while bits < width {
+            if pos >= data.len() {
+                return Ok(out); // some encoders leave out the end code
+            }
+            acc |= (data[pos] as uint) << bits;
+            pos += 1;
+            bits += 8;
+        }
+        let code = acc & ((1 << width) - 1);
+        acc >>= width;
+        bits -= width;
+
+        if code == clear {
+            table.clear();
+            width = min_code_size + 1;
+            prev = None;
+            continue;
+        }
+        if code == end {
+            return Ok(out);
+        }
+        let next = end + 1 + table.len();
+        let entry = if code < clear {
+            vec![code as u8]
+        } else if code < next {
+            table[code - end - 1].clone()
+        } else {
+            match prev {
+                Some(ref p) if code == next => {
+                    let mut e = p.clone();
+                    e.push(p[0]);
+                    e
+                },
+                _ => return Err("bad LZW code in GIF image".to_string())
+            }
+        };
+        out.push_all(entry.as_slice());
+        match prev {
+            Some(ref p) if next < 1 << MAX_CODE_BITS => {
+                let mut e = p.clone();
+                e.push(entry[0]);
+                table.push(e);
+                if next + 1 == 1 << width && width < MAX_CODE_BITS {
+                    width += 1;
+                }
+            },
+            _ => {}
+        }
+        prev = Some(entry);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GifWriter, lzw, unlzw, read_frames};
+    use std::io::MemWriter;
+    use video::Image;
+
+    #[test]
+    fn test_lzw_roundtrip() {
+        let mut data = vec![];
+        for i in range(0u, 20000) {
+            data.push(((i * 7 / 13) % 3 + i % 2) as u8); // fits in 2 bits
+        }
+        for &size in [2u, 3, 8].iter() {
+            let encoded = lzw(data.as_slice(), size);
+            assert_eq!(unlzw(encoded.as_slice(), size).unwrap(), data);
+        }
+        assert_eq!(unlzw(lzw([], 2).as_slice(), 2).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_gif() {
+        let mut gif = GifWriter::new(MemWriter::new(), 4, 2).unwrap();
+        gif.frame(&Image::new(4, 2, [0xff, 0, 0, 0xff]), 2).unwrap();
+        let data = gif.finish().unwrap().unwrap();
+        assert_eq!(data.slice_to(6), b"GIF89a");
+        assert_eq!(data[data.len() - 1], 0x3b);
+    }
+
+    #[test]
+    fn test_read_frames() {
+        let mut img = Image::new(3, 2, [0, 0, 0, 0xff]);
+        img.set(1, 0, [0xff, 0xff, 0xff, 0xff]);
+        img.set(2, 1, [0xff, 0, 0, 0xff]);
+        let mut gif = GifWriter::new(MemWriter::new(), 3, 2).unwrap();
+        gif.frame(&img, 2).unwrap();
+        gif.frame(&Image::new(3, 2, [0xff, 0, 0, 0xff]), 2).unwrap();
+        let data = gif.finish().unwrap().unwrap();
+
+        let frames = read_frames(data.as_slice()).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].width, frames[0].height), (3, 2));
+        assert_eq!(frames[0].indices, vec![0, 1, 0, 0, 0, 2]);
+        assert_eq!(frames[1].indices, vec![0, 0, 0, 0, 0, 0]);
+        assert!(read_frames(data.slice_to(30)).is_err());
+        assert!(read_frames(b"PNG").is_err());
+    }
+}
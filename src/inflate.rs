@@ -0,0 +1,324 @@
+//! DEFLATE decompression (RFC 1951), and the gzip wrapper around it
+//! (RFC 1952).
+
+use crc32::crc32;
+
+static MAX_BITS: uint = 15;
+
+static LENGTH_BASE: [u16, ..29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                   35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA: [u8, ..29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                   3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+static DIST_BASE: [u16, ..30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                                 8193, 12289, 16385, 24577];
+static DIST_EXTRA: [u8, ..30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                                 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// The order code length code lengths come in.
+static CLEN_ORDER: [uint, ..19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                   14, 1, 15];
+
+/// Reads bits least significant first, as DEFLATE packs them.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: uint,
+    bit: uint,
+}
+
+impl<'a> Bits<'a> {
+    fn bit(&mut self) -> Result<uint, String> {
+        if self.pos >= self.data.len() {
+            return Err("compressed data ends early".to_string());
+        }
+        let b = (self.data[self.pos] >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(b as uint)
+    }
+
+    fn bits(&mut self, n: uint) -> Result<uint, String> {
+        let mut v = 0;
+        for i in range(0, n) {
+            v |= try!(self.bit()) << i;
+        }
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// A canonical Huffman code, from its code lengths.
+struct Huffman {
+    counts: [uint, ..MAX_BITS + 1], // codes of each length
+    symbols: Vec<uint>,             // in code order
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u, ..MAX_BITS + 1];
+        for &len in lengths.iter() {
+            counts[len as uint] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = vec![];
+        for len in range(1, MAX_BITS + 1) {
+            for (sym, &l) in lengths.iter().enumerate() {
+                if l as uint == len {
+                    symbols.push(sym);
+                }
+            }
+        }
+        Huffman { counts: counts, symbols: symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<uint, String> {
+        let (mut code, mut first, mut index) = (0i, 0i, 0i);
+        for len in range(1, MAX_BITS + 1) {
+            code |= try!(bits.bit()) as int;
+            let count = self.counts[len] as int;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as uint]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("bad Huffman code".to_string())
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lit = [0u8, ..288];
+    for (i, l) in lit.iter_mut().enumerate() {
+        *l = match i {
+            0..143 => 8,
+            144..255 => 9,
+            256..279 => 7,
+            _ => 8
+        };
+    }
+    (Huffman::new(lit), Huffman::new([5u8, ..30]))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let nlit = try!(bits.bits(5)) + 257;
+    let ndist = try!(bits.bits(5)) + 1;
+    let nclen = try!(bits.bits(4)) + 4;
+
+    let mut clens = [0u8, ..19];
+    for i in range(0, nclen) {
+        clens[CLEN_ORDER[i]] = try!(bits.bits(3)) as u8;
+    }
+    let clen_code = Huffman::new(clens);
+
+    let mut lengths = vec![];
+    while lengths.len() < nlit + ndist {
+        let sym = try!(clen_code.decode(bits));
+        let (len, repeat) = match sym {
+            0..15 => (sym as u8, 1),
+            16 => match lengths.last() {
+                Some(&prev) => (prev, 3 + try!(bits.bits(2))),
+                None => return Err("repeated code length with nothing before it".to_string())
+            },
+            17 => (0, 3 + try!(bits.bits(3))),
+            _ => (0, 11 + try!(bits.bits(7)))
+        };
+        if lengths.len() + repeat > nlit + ndist {
+            return Err("too many code lengths".to_string());
+        }
+        for _ in range(0, repeat) {
+            lengths.push(len);
+        }
+    }
+    Ok((Huffman::new(lengths.slice_to(nlit)), Huffman::new(lengths.slice_from(nlit))))
+}
+
+/// Decompress raw DEFLATE data. Returns the data, and how many
+/// bytes of input it took up.
+pub fn inflate(data: &[u8]) -> Result<(Vec<u8>, uint), String> {
+    let mut bits = Bits { data: data, pos: 0, bit: 0 };
+    let mut out: Vec<u8> = vec![];
+    loop {
+        let last = try!(bits.bit()) == 1;
+        match try!(bits.bits(2)) {
+            0 => {
+                bits.align();
+                let p = bits.pos;
+                if p + 4 > data.len() {
+                    return Err("compressed data ends early".to_string());
+                }
+                let len = data[p] as uint | data[p + 1] as uint << 8;
+                let nlen = data[p + 2] as uint | data[p + 3] as uint << 8;
+                if len != !nlen & 0xffff {
+                    return Err("bad stored block length".to_string());
+                }
+                if p + 4 + len > data.len() {
+                    return Err("compressed data ends early".to_string());
+                }
+                out.push_all(data.slice(p + 4, p + 4 + len));
+                bits.pos = p + 4 + len;
+            },
+            kind @ 1..2 => {
+                let (lit, dist) = if kind == 1 {
+                    fixed_codes()
+                } else {
+                    try!(dynamic_codes(&mut bits))
+                };
+                loop {
+                    let sym = try!(lit.decode(&mut bits));
+                    if sym < 256 {
+                        out.push(sym as u8);
+                    } else if sym == 256 {
+                        break;
+                    } else if sym - 257 < LENGTH_BASE.len() {
+                        let i = sym - 257;
+                        let len = LENGTH_BASE[i] as uint +
+                                  try!(bits.bits(LENGTH_EXTRA[i] as uint));
+                        let d = try!(dist.decode(&mut bits));
+                        if d >= DIST_BASE.len() {
+                            return Err("bad distance code".to_string());
+                        }
+                        let distance = DIST_BASE[d] as uint +
+                                       try!(bits.bits(DIST_EXTRA[d] as uint));
+                        if distance > out.len() {
+                            return Err("distance too far back".to_string());
+                        }
+                        let start = out.len() - distance;
+                        for i in range(0, len) {
+                            let b = out[start + i];
+                            out.push(b);
+                        }
+                    } else {
+                        return Err("bad length code".to_string());
+                    }
+                }
+            },
+            _ => return Err("bad block type".to_string())
+        }
+        if last {
+            break;
+        }
+    }
+    bits.align();
+    Ok((out, bits.pos))
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0x1f && data[1] == 0x8b
+}
+
+/// Decompress a gzip file, checking its CRC and length.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    static FHCRC: u8 = 2;
+    static FEXTRA: u8 = 4;
+    static FNAME: u8 = 8;
+    static FCOMMENT: u8 = 16;
+
+    let short = || Err("gzip data ends early".to_string());
+    if !is_gzip(data) {
+        return Err("not gzip data".to_string());
+    }
+    if data.len() < 18 {
+        return short();
+    }
+    if data[2] != 8 {
+        return Err(format!("unknown gzip compression method {}", data[2]));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        pos += 2 + (data[pos] as uint | data[pos + 1] as uint << 8);
+    }
+    for &flag in [FNAME, FCOMMENT].iter() {
+        if flags & flag != 0 {
+            while pos < data.len() && data[pos] != 0 {
+                pos += 1;
+            }
+            pos += 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    if pos >= data.len() {
+        return short();
+    }
+
+    let (out, used) = try!(inflate(data.slice_from(pos)));
+    let trailer = pos + used;
+    if trailer + 8 > data.len() {
+        return short();
+    }
+    let word = |i: uint| {
+        data[i] as u32 | data[i + 1] as u32 << 8 | data[i + 2] as u32 << 16 |
+        data[i + 3] as u32 << 24
+    };
+    if word(trailer) != crc32(out.as_slice()) {
+        return Err("gzip CRC doesn't match; the file is damaged".to_string());
+    }
+    if word(trailer + 4) != out.len() as u32 {
+        return Err("gzip length doesn't match; the file is damaged".to_string());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{gunzip, inflate};
+
+    static TEXT: &'static [u8] = b"CHIP-8 CHIP-8 CHIP-8 SUPER-CHIP XO-CHIP CHIP-8";
+
+    #[test]
+    fn test_stored() {
+        let (out, used) = inflate(b"\x01\x02\x00\xfd\xffhi").unwrap();
+        assert_eq!(out.as_slice(), b"hi");
+        assert_eq!(used, 7);
+    }
+
+    #[test]
+    fn test_fixed() {
+        // zlib.compressobj(9, zlib.DEFLATED, -15) of TEXT
+        let data = b"\x73\xf6\xf0\x0c\xd0\xb5\x50\x70\x46\xa1\x82\x43\x03\x5c\x83\x74\
+                     \x41\x1c\x85\x08\x7f\x08\x0d\x91\x01\x00";
+        let (out, used) = inflate(data).unwrap();
+        assert_eq!(out.as_slice(), TEXT);
+        assert_eq!(used, data.len());
+    }
+
+    #[test]
+    fn test_dynamic() {
+        let mut text = vec![];
+        for i in range(0u, 10) {
+            text.push_all(format!("{:03X} ", (i * 37) % 4096).as_bytes());
+        }
+        let data = b"\x05\xc1\xc1\x11\x00\x10\x0c\x00\xc1\x56\xae\x84\x0b\x61\x78\x32\
+                     \xe8\xbf\x24\xbb\x2a\x96\x86\xb9\xb0\x3f\x9c\x89\x7b\xe2\xb9\x84\
+                     \x95\x28\x83\xc8\xc3\x07";
+        assert_eq!(inflate(data).unwrap().val0(), text);
+        // Truncated data fails rather than returning garbage.
+        assert!(inflate(data.slice_to(20)).is_err());
+    }
+
+    #[test]
+    fn test_gunzip() {
+        // gzip.compress(TEXT, mtime=0)
+        let data = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x73\xf6\xf0\x0c\xd0\xb5\
+                     \x50\x70\x46\xa1\x82\x43\x03\x5c\x83\x74\x41\x1c\x85\x08\x7f\x08\
+                     \x0d\x91\x01\x00\x67\xff\xa1\x75\x2e\x00\x00\x00";
+        assert_eq!(gunzip(data).unwrap().as_slice(), TEXT);
+
+        let mut damaged = data.to_vec();
+        *damaged.get_mut(data.len() - 8) ^= 1;
+        assert!(gunzip(damaged.as_slice()).is_err());
+        assert!(gunzip(data.slice_to(20)).is_err());
+        assert!(gunzip(b"not gzip").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{Deque, RingBuf};
+
+use vm::Vm;
+
+/// Instruction batches per 60 Hz tick. Input events are applied
+/// between batches, at the point matching their timestamp.
+static SLICES: uint = 8;
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum KeyEvent {
+    KeyDown(uint), // CHIP-8 key, 0x0 to 0xf
+    KeyUp(uint),
+}
+
+impl KeyEvent {
+    fn key(&self) -> uint {
+        match *self {
+            KeyDown(k) | KeyUp(k) => k
+        }
+    }
+}
+
+/// Key events waiting to be delivered to the VM, stamped with the
+/// (real) time they were received in ns.
+pub struct InputQueue {
+    events: RingBuf<(u64, KeyEvent)>
+}
+
+impl InputQueue {
+    pub fn new() -> InputQueue {
+        InputQueue { events: RingBuf::new() }
+    }
+
+    pub fn push(&mut self, time: u64, event: KeyEvent) {
+        self.events.push_back((time, event));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Deliver the events stamped at or before `time`, in order. A
+    /// key changes state at most once per call, so a press and
+    /// release received together still reach the program as a tap:
+    /// a key's later events wait for the next call, while other
+    /// keys' events carry on being delivered.
+    pub fn apply_until(&mut self, vm: &mut Vm, time: u64) {
+        let mut changed: u16 = 0;
+        let mut deferred = vec![];
+        loop {
+            let (t, event) = match self.events.front() {
+                Some(&(t, event)) if t <= time => (t, event),
+                _ => break
+            };
+            self.events.pop_front();
+            let bit = 1 << event.key();
+            if changed & bit != 0 {
+                deferred.push((t, event));
+                continue;
+            }
+            changed |= bit;
+            match event {
+                KeyDown(k) => vm.keydown(k),
+                KeyUp(k) => vm.keyup(k),
+            }
+        }
+        for &e in deferred.iter().rev() {
+            self.events.push_front(e);
+        }
+    }
+
+    /// Run one tick's worth of `cycles` covering real time `start`
+    /// to `end`, delivering queued events between instruction
+    /// batches.
+    pub fn run_tick(&mut self, vm: &mut Vm, cycles: uint, start: u64, end: u64) {
+        let span = if end > start { end - start } else { 0 };
+        let mut done = 0;
+        for slice in range(0, SLICES) {
+            self.apply_until(vm, start + span * slice as u64 / SLICES as u64);
+            let until = cycles * (slice + 1) / SLICES;
+            vm.run_cycles(until - done);
+            done = until;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{InputQueue, KeyEvent, KeyDown, KeyUp};
+    use cpu;
+    use mem::Rom;
+    use std::io::MemReader;
+    use std::rand::StdRng;
+    use vm::Vm;
+
+    fn vm(prgm: &[u8]) -> Vm {
+        let mut r = MemReader::new(prgm.to_vec());
+        Vm::new(Rom::from_reader(&mut r).unwrap(), StdRng::new().unwrap())
+    }
+
+    #[test]
+    fn test_tap_within_tick() {
+        let prgm = [0x60, 0x05,  // V0 = 5
+                    0xe0, 0x9e,  // skip if key V0 is pressed
+                    0x12, 0x02,  // jump back to the skip
+                    0x61, 0x01,  // V1 = 1
+                    0x12, 0x08]; // loop forever
+        let mut vm = vm(prgm.as_slice());
+        let mut input = InputQueue::new();
+        input.push(0, KeyDown(5));
+        input.push(0, KeyUp(5));
+        input.run_tick(&mut vm, 100, 0, 16);
+        assert_eq!(vm.registers().get(cpu::V1), 1);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_events_wait_for_their_time() {
+        let prgm = [0x12, 0x00]; // loop forever
+        let mut vm = vm(prgm.as_slice());
+        let mut input = InputQueue::new();
+        input.push(20, KeyDown(1));
+        input.run_tick(&mut vm, 100, 0, 16);
+        assert!(!input.is_empty());
+        input.run_tick(&mut vm, 100, 16, 32);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_simultaneous_presses() {
+        let prgm = [0x12, 0x00];
+        let mut vm = vm(prgm.as_slice());
+        let mut input = InputQueue::new();
+        input.push(0, KeyDown(1));
+        input.push(0, KeyDown(2));
+        input.apply_until(&mut vm, 0);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_only_the_repeated_key_waits() {
+        let prgm = [0x12, 0x00];
+        let mut vm = vm(prgm.as_slice());
+        let mut input = InputQueue::new();
+        input.push(0, KeyDown(1));
+        input.push(0, KeyUp(1));
+        input.push(1, KeyDown(2));
+        input.push(2, KeyDown(1));
+        input.push(3, KeyDown(3));
+        input.apply_until(&mut vm, 3);
+        let left: Vec<(u64, KeyEvent)> = input.events.iter().map(|&e| e).collect();
+        assert_eq!(left, vec![(0, KeyUp(1)), (2, KeyDown(1))]);
+        input.apply_until(&mut vm, 3);
+        let left: Vec<(u64, KeyEvent)> = input.events.iter().map(|&e| e).collect();
+        assert_eq!(left, vec![(2, KeyDown(1))]);
+    }
+}
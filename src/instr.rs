@@ -0,0 +1,243 @@
+use std::fmt;
+
+/// A decoded CHIP-8 instruction. Variants are named after the
+/// mnemonics in Cowgod's technical reference, which is also the
+/// syntax `Show` prints and the assembler reads: registers are
+/// `x` and `y`, `nnn` an address, `nn` a byte and `n` a nibble.
+#[deriving(Clone, PartialEq)]
+pub enum Instruction {
+    Sys(u16),          // 0nnn  SYS nnn: call machine code
+    Cls,               // 00E0  CLS
+    Ret,               // 00EE  RET
+    Jp(u16),           // 1nnn  JP nnn
+    Call(u16),         // 2nnn  CALL nnn
+    SeByte(u8, u8),    // 3xnn  SE Vx, nn
+    SneByte(u8, u8),   // 4xnn  SNE Vx, nn
+    SeReg(u8, u8),     // 5xy0  SE Vx, Vy
+    LdByte(u8, u8),    // 6xnn  LD Vx, nn
+    AddByte(u8, u8),   // 7xnn  ADD Vx, nn
+    LdReg(u8, u8),     // 8xy0  LD Vx, Vy
+    OrReg(u8, u8),     // 8xy1  OR Vx, Vy
+    AndReg(u8, u8),    // 8xy2  AND Vx, Vy
+    XorReg(u8, u8),    // 8xy3  XOR Vx, Vy
+    AddReg(u8, u8),    // 8xy4  ADD Vx, Vy
+    SubReg(u8, u8),    // 8xy5  SUB Vx, Vy
+    ShrReg(u8, u8),    // 8xy6  SHR Vx, Vy
+    SubnReg(u8, u8),   // 8xy7  SUBN Vx, Vy
+    ShlReg(u8, u8),    // 8xyE  SHL Vx, Vy
+    SneReg(u8, u8),    // 9xy0  SNE Vx, Vy
+    LdI(u16),          // Annn  LD I, nnn
+    JpV0(u16),         // Bnnn  JP V0, nnn
+    Rnd(u8, u8),       // Cxnn  RND Vx, nn
+    Drw(u8, u8, u8),   // Dxyn  DRW Vx, Vy, n
+    Skp(u8),           // Ex9E  SKP Vx
+    Sknp(u8),          // ExA1  SKNP Vx
+    Audio,             // F002  AUDIO (XO-CHIP)
+    LdVxDt(u8),        // Fx07  LD Vx, DT
+    LdVxK(u8),         // Fx0A  LD Vx, K
+    LdDtVx(u8),        // Fx15  LD DT, Vx
+    LdStVx(u8),        // Fx18  LD ST, Vx
+    AddIVx(u8),        // Fx1E  ADD I, Vx
+    LdFVx(u8),         // Fx29  LD F, Vx
+    LdBVx(u8),         // Fx33  LD B, Vx
+    Pitch(u8),         // Fx3A  PITCH Vx (XO-CHIP)
+    LdIVx(u8),         // Fx55  LD [I], Vx
+    LdVxI(u8),         // Fx65  LD Vx, [I]
+    Unknown(u16),
+}
+
+pub fn decode(ins: u16) -> Instruction {
+    let op = (ins >> 12) as u8;
+    let x = ((ins >> 8) & 0xf) as u8;
+    let y = ((ins >> 4) & 0xf) as u8;
+    let n = (ins & 0xf) as u8;
+    let nn = (ins & 0xff) as u8;
+    let nnn = ins & 0xfff;
+
+    match (op, n) {
+        _ if ins == 0x00e0 => Cls,
+        _ if ins == 0x00ee => Ret,
+        (0x0, _) => Sys(nnn),
+        (0x1, _) => Jp(nnn),
+        (0x2, _) => Call(nnn),
+        (0x3, _) => SeByte(x, nn),
+        (0x4, _) => SneByte(x, nn),
+        (0x5, 0x0) => SeReg(x, y),
+        (0x6, _) => LdByte(x, nn),
+        (0x7, _) => AddByte(x, nn),
+        (0x8, 0x0) => LdReg(x, y),
+        (0x8, 0x1) => OrReg(x, y),
+        (0x8, 0x2) => AndReg(x, y),
+        (0x8, 0x3) => XorReg(x, y),
+        (0x8, 0x4) => AddReg(x, y),
+        (0x8, 0x5) => SubReg(x, y),
+        (0x8, 0x6) => ShrReg(x, y),
+        (0x8, 0x7) => SubnReg(x, y),
+        (0x8, 0xe) => ShlReg(x, y),
+        (0x9, 0x0) => SneReg(x, y),
+        (0xa, _) => LdI(nnn),
+        (0xb, _) => JpV0(nnn),
+        (0xc, _) => Rnd(x, nn),
+        (0xd, _) => Drw(x, y, n),
+        (0xe, _) if nn == 0x9e => Skp(x),
+        (0xe, _) if nn == 0xa1 => Sknp(x),
+        (0xf, _) => match nn {
+            0x02 if x == 0 => Audio,
+            0x07 => LdVxDt(x),
+            0x0a => LdVxK(x),
+            0x15 => LdDtVx(x),
+            0x18 => LdStVx(x),
+            0x1e => AddIVx(x),
+            0x29 => LdFVx(x),
+            0x33 => LdBVx(x),
+            0x3a => Pitch(x),
+            0x55 => LdIVx(x),
+            0x65 => LdVxI(x),
+            _ => Unknown(ins)
+        },
+        _ => Unknown(ins)
+    }
+}
+
+fn xy(base: u16, x: u8, y: u8) -> u16 {
+    base | (x as u16) << 8 | (y as u16) << 4
+}
+
+fn xnn(base: u16, x: u8, nn: u8) -> u16 {
+    base | (x as u16) << 8 | nn as u16
+}
+
+impl Instruction {
+    pub fn encode(&self) -> u16 {
+        match *self {
+            Sys(nnn) => nnn & 0xfff,
+            Cls => 0x00e0,
+            Ret => 0x00ee,
+            Jp(nnn) => 0x1000 | nnn & 0xfff,
+            Call(nnn) => 0x2000 | nnn & 0xfff,
+            SeByte(x, nn) => xnn(0x3000, x, nn),
+            SneByte(x, nn) => xnn(0x4000, x, nn),
+            SeReg(x, y) => xy(0x5000, x, y),
+            LdByte(x, nn) => xnn(0x6000, x, nn),
+            AddByte(x, nn) => xnn(0x7000, x, nn),
+            LdReg(x, y) => xy(0x8000, x, y),
+            OrReg(x, y) => xy(0x8001, x, y),
+            AndReg(x, y) => xy(0x8002, x, y),
+            XorReg(x, y) => xy(0x8003, x, y),
+            AddReg(x, y) => xy(0x8004, x, y),
+            SubReg(x, y) => xy(0x8005, x, y),
+            ShrReg(x, y) => xy(0x8006, x, y),
+            SubnReg(x, y) => xy(0x8007, x, y),
+            ShlReg(x, y) => xy(0x800e, x, y),
+            SneReg(x, y) => xy(0x9000, x, y),
+            LdI(nnn) => 0xa000 | nnn & 0xfff,
+            JpV0(nnn) => 0xb000 | nnn & 0xfff,
+            Rnd(x, nn) => xnn(0xc000, x, nn),
+            Drw(x, y, n) => xy(0xd000, x, y) | (n & 0xf) as u16,
+            Skp(x) => xnn(0xe000, x, 0x9e),
+            Sknp(x) => xnn(0xe000, x, 0xa1),
+            Audio => 0xf002,
+            LdVxDt(x) => xnn(0xf000, x, 0x07),
+            LdVxK(x) => xnn(0xf000, x, 0x0a),
+            LdDtVx(x) => xnn(0xf000, x, 0x15),
+            LdStVx(x) => xnn(0xf000, x, 0x18),
+            AddIVx(x) => xnn(0xf000, x, 0x1e),
+            LdFVx(x) => xnn(0xf000, x, 0x29),
+            LdBVx(x) => xnn(0xf000, x, 0x33),
+            Pitch(x) => xnn(0xf000, x, 0x3a),
+            LdIVx(x) => xnn(0xf000, x, 0x55),
+            LdVxI(x) => xnn(0xf000, x, 0x65),
+            Unknown(ins) => ins,
+        }
+    }
+}
+
+impl fmt::Show for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Sys(nnn) => write!(f, "SYS #{:03X}", nnn),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Jp(nnn) => write!(f, "JP #{:03X}", nnn),
+            Call(nnn) => write!(f, "CALL #{:03X}", nnn),
+            SeByte(x, nn) => write!(f, "SE V{:X}, #{:02X}", x, nn),
+            SneByte(x, nn) => write!(f, "SNE V{:X}, #{:02X}", x, nn),
+            SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            LdByte(x, nn) => write!(f, "LD V{:X}, #{:02X}", x, nn),
+            AddByte(x, nn) => write!(f, "ADD V{:X}, #{:02X}", x, nn),
+            LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            OrReg(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            AndReg(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            XorReg(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            SubReg(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShrReg(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubnReg(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShlReg(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(nnn) => write!(f, "LD I, #{:03X}", nnn),
+            JpV0(nnn) => write!(f, "JP V0, #{:03X}", nnn),
+            Rnd(x, nn) => write!(f, "RND V{:X}, #{:02X}", x, nn),
+            Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Audio => write!(f, "AUDIO"),
+            LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            Pitch(x) => write!(f, "PITCH V{:X}", x),
+            LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Unknown(ins) => write!(f, "DW #{:04X}", ins),
+        }
+    }
+}
+
+/// Disassemble a program loaded at `base`, one line per
+/// instruction word: address, encoding, and mnemonic.
+pub fn disassemble(prgm: &[u8], base: u16, out: &mut Writer) -> ::std::io::IoResult<()> {
+    for (i, word) in prgm.chunks(2).enumerate() {
+        let addr = base as uint + i * 2;
+        if word.len() < 2 {
+            try!(writeln!(out, "{:03X}  {:02X}    DB #{:02X}", addr, word[0], word[0]));
+            break;
+        }
+        let ins = (word[0] as u16) << 8 | word[1] as u16;
+        try!(writeln!(out, "{:03X}  {:04X}  {}", addr, ins, decode(ins)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, Unknown, Cls, Drw, LdIVx, SeReg};
+
+    #[test]
+    fn test_decode() {
+        assert!(decode(0x00e0) == Cls);
+        assert!(decode(0xd125) == Drw(1, 2, 5));
+        assert!(decode(0xf355) == LdIVx(3));
+        assert!(decode(0x5120) == SeReg(1, 2));
+        assert!(decode(0x5121) == Unknown(0x5121));
+        assert!(decode(0xe1ff) == Unknown(0xe1ff));
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        for ins in range(0u, 0x10000) {
+            let ins = ins as u16;
+            assert_eq!(decode(ins).encode(), ins);
+        }
+    }
+
+    #[test]
+    fn test_show() {
+        assert_eq!(format!("{}", decode(0x6a2f)).as_slice(), "LD VA, #2F");
+        assert_eq!(format!("{}", decode(0xd01f)).as_slice(), "DRW V0, V1, 15");
+        assert_eq!(format!("{}", decode(0xf265)).as_slice(), "LD V2, [I]");
+    }
+}
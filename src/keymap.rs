@@ -0,0 +1,285 @@
+use rsfml::window::keyboard;
+use rsfml::window::keyboard::Key;
+
+use std::ascii::StrAsciiExt;
+use std::collections::TreeMap;
+use std::io::File;
+use std::num;
+
+pub static DEFAULT_PRESET: &'static str = "qwerty";
+
+/// Maps physical keys to CHIP-8 keys. Several physical keys may
+/// map to the same CHIP-8 key.
+#[deriving(Clone)]
+pub struct Keymap {
+    keys: TreeMap<Key, uint>
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap { keys: TreeMap::new() }
+    }
+
+    /// Look up a built-in layout by name.
+    ///
+    /// The QWERTY, AZERTY and Dvorak presets place the COSMAC VIP
+    /// keypad on the same four-by-four block of keys:
+    ///
+    ///     1 2 3 C
+    ///     4 5 6 D
+    ///     7 8 9 E
+    ///     A 0 B F
+    ///
+    /// `numpad` puts the digits on the numeric keypad, and `cosmac`
+    /// maps the keys labelled 0-9 and A-F to the digit on the label.
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let rows: [Key, ..16] = match name.to_ascii_lower().as_slice() {
+            "qwerty" => [keyboard::Num1, keyboard::Num2, keyboard::Num3, keyboard::Num4,
+                         keyboard::Q, keyboard::W, keyboard::E, keyboard::R,
+                         keyboard::A, keyboard::S, keyboard::D, keyboard::F,
+                         keyboard::Z, keyboard::X, keyboard::C, keyboard::V],
+            "azerty" => [keyboard::Num1, keyboard::Num2, keyboard::Num3, keyboard::Num4,
+                         keyboard::A, keyboard::Z, keyboard::E, keyboard::R,
+                         keyboard::Q, keyboard::S, keyboard::D, keyboard::F,
+                         keyboard::W, keyboard::X, keyboard::C, keyboard::V],
+            "dvorak" => [keyboard::Num1, keyboard::Num2, keyboard::Num3, keyboard::Num4,
+                         keyboard::Quote, keyboard::Comma, keyboard::Period, keyboard::P,
+                         keyboard::A, keyboard::O, keyboard::E, keyboard::U,
+                         keyboard::SemiColon, keyboard::Q, keyboard::J, keyboard::K],
+            "numpad" => {
+                return Some(Keymap::from_pairs([
+                    (keyboard::Numpad0, 0x0), (keyboard::Numpad1, 0x1),
+                    (keyboard::Numpad2, 0x2), (keyboard::Numpad3, 0x3),
+                    (keyboard::Numpad4, 0x4), (keyboard::Numpad5, 0x5),
+                    (keyboard::Numpad6, 0x6), (keyboard::Numpad7, 0x7),
+                    (keyboard::Numpad8, 0x8), (keyboard::Numpad9, 0x9),
+                    (keyboard::Divide, 0xa), (keyboard::Multiply, 0xb),
+                    (keyboard::Subtract, 0xc), (keyboard::Add, 0xd),
+                    (keyboard::Return, 0xe), (keyboard::Period, 0xf),
+                ].as_slice()));
+            },
+            "cosmac" => {
+                return Some(Keymap::from_pairs([
+                    (keyboard::Num0, 0x0), (keyboard::Num1, 0x1),
+                    (keyboard::Num2, 0x2), (keyboard::Num3, 0x3),
+                    (keyboard::Num4, 0x4), (keyboard::Num5, 0x5),
+                    (keyboard::Num6, 0x6), (keyboard::Num7, 0x7),
+                    (keyboard::Num8, 0x8), (keyboard::Num9, 0x9),
+                    (keyboard::A, 0xa), (keyboard::B, 0xb),
+                    (keyboard::C, 0xc), (keyboard::D, 0xd),
+                    (keyboard::E, 0xe), (keyboard::F, 0xf),
+                ].as_slice()));
+            },
+            _ => return None
+        };
+
+        // The VIP keypad, read left to right, top to bottom.
+        static VIP_KEYPAD: [uint, ..16] = [0x1, 0x2, 0x3, 0xc,
+                                           0x4, 0x5, 0x6, 0xd,
+                                           0x7, 0x8, 0x9, 0xe,
+                                           0xa, 0x0, 0xb, 0xf];
+        let mut map = Keymap::new();
+        for (&key, &code) in rows.iter().zip(VIP_KEYPAD.iter()) {
+            map.bind(key, code);
+        }
+        Some(map)
+    }
+
+    fn from_pairs(pairs: &[(Key, uint)]) -> Keymap {
+        let mut map = Keymap::new();
+        for &(key, code) in pairs.iter() {
+            map.bind(key, code);
+        }
+        map
+    }
+
+    /// Load a preset name, or else a keymap file.
+    pub fn load(name: &str) -> Result<Keymap, String> {
+        match Keymap::preset(name) {
+            Some(map) => Ok(map),
+            None => {
+                let mut map = Keymap::preset(DEFAULT_PRESET).unwrap();
+                try!(map.apply_file(&Path::new(name)));
+                Ok(map)
+            }
+        }
+    }
+
+    pub fn bind(&mut self, key: Key, code: uint) {
+        assert!(code < 16);
+        self.keys.insert(key, code);
+    }
+
+    /// Remove every binding to the CHIP-8 key `code`.
+    pub fn unbind(&mut self, code: uint) {
+        let bound: Vec<Key> = self.keys.iter()
+            .filter(|&(_, &c)| c == code)
+            .map(|(&k, _)| k)
+            .collect();
+        for key in bound.iter() {
+            self.keys.remove(key);
+        }
+    }
+
+    pub fn find(&self, key: &Key) -> Option<uint> {
+        self.keys.find(key).map(|&code| code)
+    }
+
+    pub fn apply_file(&mut self, path: &Path) -> Result<(), String> {
+        let src = try!(File::open(path).read_to_string().map_err(|e| {
+            format!("Error reading keymap {}: {}", path.display(), e.desc)
+        }));
+        self.apply(src.as_slice()).map_err(|e| {
+            format!("Error in keymap {}: {}", path.display(), e)
+        })
+    }
+
+    /// Apply keymap source on top of this map. Each line binds a
+    /// CHIP-8 key (a hex digit) to one or more physical keys,
+    /// replacing its previous bindings (or, with `+=`, keeping
+    /// them), or starts over from a preset:
+    ///
+    ///     # comments start with a hash
+    ///     preset = azerty
+    ///     a = Z Numpad0
+    ///     5 += Up
+    pub fn apply(&mut self, src: &str) -> Result<(), String> {
+        for (i, line) in src.lines().enumerate() {
+            let line = line.splitn('#', 1).next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (lhs, rhs) = match line.find('=') {
+                Some(eq) => (line.slice_to(eq).trim(), line.slice_from(eq + 1).trim()),
+                None => return Err(format!("line {}: expected `KEY = ...`", i + 1))
+            };
+            let (lhs, adding) = if lhs.ends_with("+") {
+                (lhs.slice_to(lhs.len() - 1).trim(), true)
+            } else {
+                (lhs, false)
+            };
+            if lhs == "preset" {
+                if adding {
+                    return Err(format!("line {}: a preset can't be added to", i + 1));
+                }
+                *self = match Keymap::preset(rhs) {
+                    Some(map) => map,
+                    None => return Err(format!("line {}: unknown preset `{}`", i + 1, rhs))
+                };
+                continue;
+            }
+            let code = match num::from_str_radix::<uint>(lhs, 16) {
+                Some(code) if code < 16 && lhs.len() == 1 => code,
+                _ => return Err(format!("line {}: `{}` is not a CHIP-8 key (0-F)", i + 1, lhs))
+            };
+            let mut keys = vec![];
+            for name in rhs.words() {
+                match key_from_name(name) {
+                    Some(key) => keys.push(key),
+                    None => return Err(format!("line {}: unknown key `{}`", i + 1, name))
+                }
+            }
+            if !adding {
+                self.unbind(code);
+            }
+            for &key in keys.iter() {
+                self.bind(key, code);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse an SFML key name, ignoring case.
+pub fn key_from_name(name: &str) -> Option<Key> {
+    let key = match name.to_ascii_lower().as_slice() {
+        "a" => keyboard::A, "b" => keyboard::B, "c" => keyboard::C,
+        "d" => keyboard::D, "e" => keyboard::E, "f" => keyboard::F,
+        "g" => keyboard::G, "h" => keyboard::H, "i" => keyboard::I,
+        "j" => keyboard::J, "k" => keyboard::K, "l" => keyboard::L,
+        "m" => keyboard::M, "n" => keyboard::N, "o" => keyboard::O,
+        "p" => keyboard::P, "q" => keyboard::Q, "r" => keyboard::R,
+        "s" => keyboard::S, "t" => keyboard::T, "u" => keyboard::U,
+        "v" => keyboard::V, "w" => keyboard::W, "x" => keyboard::X,
+        "y" => keyboard::Y, "z" => keyboard::Z,
+        "num0" => keyboard::Num0, "num1" => keyboard::Num1,
+        "num2" => keyboard::Num2, "num3" => keyboard::Num3,
+        "num4" => keyboard::Num4, "num5" => keyboard::Num5,
+        "num6" => keyboard::Num6, "num7" => keyboard::Num7,
+        "num8" => keyboard::Num8, "num9" => keyboard::Num9,
+        "numpad0" => keyboard::Numpad0, "numpad1" => keyboard::Numpad1,
+        "numpad2" => keyboard::Numpad2, "numpad3" => keyboard::Numpad3,
+        "numpad4" => keyboard::Numpad4, "numpad5" => keyboard::Numpad5,
+        "numpad6" => keyboard::Numpad6, "numpad7" => keyboard::Numpad7,
+        "numpad8" => keyboard::Numpad8, "numpad9" => keyboard::Numpad9,
+        "add" => keyboard::Add, "subtract" => keyboard::Subtract,
+        "multiply" => keyboard::Multiply, "divide" => keyboard::Divide,
+        "left" => keyboard::Left, "right" => keyboard::Right,
+        "up" => keyboard::Up, "down" => keyboard::Down,
+        "space" => keyboard::Space, "return" => keyboard::Return,
+        "backspace" => keyboard::BackSpace, "tab" => keyboard::Tab,
+        "lshift" => keyboard::LShift, "rshift" => keyboard::RShift,
+        "lcontrol" => keyboard::LControl, "rcontrol" => keyboard::RControl,
+        "lalt" => keyboard::LAlt, "ralt" => keyboard::RAlt,
+        "comma" => keyboard::Comma, "period" => keyboard::Period,
+        "semicolon" => keyboard::SemiColon, "quote" => keyboard::Quote,
+        "slash" => keyboard::Slash, "backslash" => keyboard::BackSlash,
+        "lbracket" => keyboard::LBracket, "rbracket" => keyboard::RBracket,
+        "dash" => keyboard::Dash, "equal" => keyboard::Equal,
+        "tilde" => keyboard::Tilde,
+        _ => return None
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Keymap;
+    use rsfml::window::keyboard;
+
+    #[test]
+    fn test_presets() {
+        for name in ["qwerty", "azerty", "dvorak", "numpad", "cosmac"].iter() {
+            let map = Keymap::preset(*name).unwrap();
+            for code in range(0u, 16) {
+                assert!(map.keys.values().any(|&c| c == code));
+            }
+        }
+        assert!(Keymap::preset("colemak").is_none());
+    }
+
+    #[test]
+    fn test_qwerty_is_vip_layout() {
+        let map = Keymap::preset("qwerty").unwrap();
+        assert_eq!(map.find(&keyboard::X), Some(0x0));
+        assert_eq!(map.find(&keyboard::Num4), Some(0xc));
+        assert_eq!(map.find(&keyboard::V), Some(0xf));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut map = Keymap::preset("qwerty").unwrap();
+        map.apply("# arrows for movement\n\
+                   5 = W Up   # two keys\n\
+                   preset = azerty\n\
+                   a = w numpad0\n").unwrap();
+        assert_eq!(map.find(&keyboard::Up), None); // reset by the preset
+        assert_eq!(map.find(&keyboard::W), Some(0xa));
+        assert_eq!(map.find(&keyboard::Numpad0), Some(0xa));
+        assert_eq!(map.find(&keyboard::Z), Some(0x5));
+
+        map.apply("5 += Up").unwrap();
+        assert_eq!(map.find(&keyboard::Up), Some(0x5));
+        assert_eq!(map.find(&keyboard::Z), Some(0x5));
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let mut map = Keymap::new();
+        assert!(map.apply("g = A").is_err());
+        assert!(map.apply("1 = Hyper").is_err());
+        assert!(map.apply("1 A").is_err());
+        assert!(map.apply("preset = qwertz").is_err());
+        assert!(map.apply("preset += qwerty").is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! Turning ROM files into programs. Besides raw binaries, ROMs turn
+//! up as hex dumps, Intel HEX files, Octo cartridges and gzipped
+//! copies of them.
+
+use std::num;
+use std::str;
+
+use cartridge;
+use config::Settings;
+use gif;
+use inflate;
+
+/// How a ROM file holds its program.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Format {
+    Raw,           // the program bytes as they are
+    HexText,       // hex digits, like `00E0 A22A 600C`
+    IntelHex,      // Intel HEX records
+    OctoCartridge, // a GIF with Octo source and options hidden in it
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Raw => "raw binary",
+            HexText => "hex text",
+            IntelHex => "Intel HEX",
+            OctoCartridge => "Octo cartridge",
+        }
+    }
+}
+
+/// A program taken out of a ROM file.
+pub struct Loaded {
+    pub prgm: Vec<u8>,
+    pub format: Format,
+    pub gzipped: bool,
+    pub settings: Settings,   // what the file asks for, if anything
+    pub ignored: Vec<String>, // settings it asks for that fries can't honour
+}
+
+impl Loaded {
+    /// How the program was stored, like `Intel HEX, gzipped`.
+    pub fn describe(&self) -> String {
+        if self.gzipped {
+            format!("{}, gzipped", self.format.name())
+        } else {
+            self.format.name().to_string()
+        }
+    }
+}
+
+/// Work out what kind of file `data` is and take the program out.
+/// `origin` is the load address Intel HEX addresses are taken from.
+pub fn decode(data: &[u8], origin: u16) -> Result<Loaded, String> {
+    if inflate::is_gzip(data) {
+        let inner = try!(inflate::gunzip(data).map_err(|e| format!("gzip: {}", e)));
+        if inflate::is_gzip(inner.as_slice()) {
+            return Err("gzip: the file is gzipped twice".to_string());
+        }
+        let mut loaded = try!(decode(inner.as_slice(), origin));
+        loaded.gzipped = true;
+        return Ok(loaded);
+    }
+
+    if gif::is_gif(data) {
+        let cart = try!(cartridge::decode(data));
+        return Ok(Loaded {
+            prgm: cart.prgm,
+            format: OctoCartridge,
+            gzipped: false,
+            settings: cart.settings,
+            ignored: cart.ignored,
+        });
+    }
+
+    let (format, prgm) = match str::from_utf8(data) {
+        Some(text) if is_intel_hex(text) => (IntelHex, try!(intel_hex(text, origin))),
+        Some(text) if is_hex_text(text) => (HexText, try!(hex_text(text))),
+        _ => (Raw, data.to_vec())
+    };
+    if prgm.is_empty() {
+        return Err("the ROM is empty".to_string());
+    }
+    Ok(Loaded {
+        prgm: prgm,
+        format: format,
+        gzipped: false,
+        settings: Settings::new(),
+        ignored: vec![],
+    })
+}
+
+/// Check a program against `max`, the room the memory map leaves
+/// for it. If it's too big, it's an error,
+/// unless `allow_oversize`, in which case it's cut down to size and
+/// the warning is returned.
+pub fn check_size(prgm: &mut Vec<u8>, max: uint,
+                  allow_oversize: bool) -> Result<Option<String>, String> {
+    if prgm.len() <= max {
+        return Ok(None);
+    }
+    let msg = format!("the ROM is {} bytes, but only {} fit", prgm.len(), max);
+    if !allow_oversize {
+        return Err(msg);
+    }
+    prgm.truncate(max);
+    Ok(Some(format!("{}; loading the first {}", msg, max)))
+}
+
+fn strip_comment<'a>(line: &'a str) -> &'a str {
+    match line.find(|c: char| c == '#' || c == ';') {
+        Some(i) => line.slice_to(i),
+        None => line
+    }
+}
+
+fn is_intel_hex(text: &str) -> bool {
+    let mut lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+    match lines.next() {
+        Some(first) => first.starts_with(":") && first.len() >= 11,
+        None => false
+    }
+}
+
+/// Only hex digits, separators, `0x`/`$` prefixes, `ADDR:` labels
+/// and comments.
+fn is_hex_text(text: &str) -> bool {
+    let mut digits = false;
+    for line in text.lines() {
+        for c in strip_comment(line).chars() {
+            if c.is_digit_radix(16) {
+                digits = true;
+            } else if !(c.is_whitespace() || c == ',' || c == ':' || c == 'x' || c == 'X' ||
+                        c == '$') {
+                return false;
+            }
+        }
+    }
+    digits
+}
+
+fn hex_byte(s: &str) -> Option<u8> {
+    if s.len() == 2 {
+        num::from_str_radix::<u8>(s, 16)
+    } else {
+        None
+    }
+}
+
+/// Parse a hex dump. Bytes may be written singly (`00 E0`) or run
+/// together (`00E0`); `ADDR:` tokens at the start of dump lines are
+/// skipped.
+fn hex_text(text: &str) -> Result<Vec<u8>, String> {
+    let mut prgm = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let words = strip_comment(line).split(|c: char| c.is_whitespace() || c == ',');
+        for word in words.filter(|w| !w.is_empty()) {
+            if word.ends_with(":") {
+                continue;
+            }
+            let digits = if word.starts_with("0x") || word.starts_with("0X") {
+                word.slice_from(2)
+            } else if word.starts_with("$") {
+                word.slice_from(1)
+            } else {
+                word
+            };
+            if digits.is_empty() || digits.len() % 2 != 0 {
+                return Err(format!("line {}: `{}` isn't a whole number of bytes", n + 1, word));
+            }
+            for i in range(0, digits.len() / 2) {
+                match hex_byte(digits.slice(i * 2, i * 2 + 2)) {
+                    Some(b) => prgm.push(b),
+                    None => return Err(format!("line {}: `{}` isn't hex", n + 1, word))
+                }
+            }
+        }
+    }
+    Ok(prgm)
+}
+
+/// Parse Intel HEX. If every address is at or above `origin`, the
+/// load address, the addresses are taken to be where the bytes go in
+/// memory; otherwise they count from the start of the program. Gaps
+/// are filled with zeroes.
+fn intel_hex(text: &str, origin: u16) -> Result<Vec<u8>, String> {
+    let mut chunks: Vec<(uint, Vec<u8>)> = vec![];
+    let mut base = 0u;
+    let mut ended = false;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg: &str| Err(format!("line {}: {}", n + 1, msg));
+        if ended {
+            return err("data after the end-of-file record");
+        }
+        if !line.starts_with(":") {
+            return err("records must start with `:`");
+        }
+        let hex = line.slice_from(1);
+        if hex.len() % 2 != 0 || hex.len() < 10 {
+            return err("record is too short");
+        }
+        let mut bytes = vec![];
+        for i in range(0, hex.len() / 2) {
+            match hex_byte(hex.slice(i * 2, i * 2 + 2)) {
+                Some(b) => bytes.push(b),
+                None => return err("not a hex digit")
+            }
+        }
+        let len = bytes[0] as uint;
+        if bytes.len() != len + 5 {
+            return err(format!("record says it holds {} bytes, but holds {}", len,
+                               bytes.len() - 5).as_slice());
+        }
+        let sum = bytes.iter().fold(0u8, |a, &b| a + b);
+        if sum != 0 {
+            let expected = (!bytes.slice_to(len + 4).iter().fold(0u8, |a, &b| a + b)) + 1;
+            return err(format!("bad checksum {:02X}, should be {:02X}", bytes[len + 4],
+                               expected).as_slice());
+        }
+        let addr = bytes[1] as uint << 8 | bytes[2] as uint;
+        let data = bytes.slice(4, 4 + len);
+        let word = || (data[0] as uint) << 8 | data[1] as uint;
+        match bytes[3] {
+            0x00 => chunks.push((base + addr, data.to_vec())),
+            0x01 => ended = true,
+            0x02 if len == 2 => base = word() << 4,
+            0x04 if len == 2 => base = word() << 16,
+            0x03 | 0x05 => {}, // start address; CHIP-8 always starts at the load address
+            0x02 | 0x04 => return err("address record should hold 2 bytes"),
+            t => return err(format!("unknown record type {:02X}", t).as_slice())
+        }
+    }
+    if !ended {
+        return Err("no end-of-file record; the file may be cut short".to_string());
+    }
+
+    let start = chunks.iter().map(|&(a, _)| a).min().unwrap_or(0);
+    let end = chunks.iter().map(|&(a, ref d)| a + d.len()).max().unwrap_or(0);
+    let origin = if start >= origin as uint { origin as uint } else { 0 };
+    if end - origin > 0x10000 {
+        return Err(format!("addresses run to {:X}, beyond any CHIP-8 memory", end));
+    }
+    let mut prgm = Vec::from_elem(end - origin, 0u8);
+    for &(addr, ref data) in chunks.iter() {
+        prgm.mut_slice(addr - origin, addr - origin + data.len()).copy_from(data.as_slice());
+    }
+    Ok(prgm)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, check_size, Raw, HexText, IntelHex};
+    use mem::ROM_LOC;
+
+    #[test]
+    fn test_raw() {
+        let l = decode(b"\x00\xe0\x12\x00", ROM_LOC).unwrap();
+        assert_eq!(l.format, Raw);
+        assert_eq!(l.prgm, vec![0x00, 0xe0, 0x12, 0x00]);
+        assert!(decode(b"", ROM_LOC).is_err());
+    }
+
+    #[test]
+    fn test_hex_text() {
+        let l = decode(b"00E0 a22a\n0200: 60,0C ; comment\n0x12 $00\n", ROM_LOC).unwrap();
+        assert_eq!(l.format, HexText);
+        assert_eq!(l.prgm, vec![0x00, 0xe0, 0xa2, 0x2a, 0x60, 0x0c, 0x12, 0x00]);
+        assert!(decode(b"00E 0", ROM_LOC).is_err());
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let l = decode(b":0402000000E0120008\n:00000001FF\n", ROM_LOC).unwrap();
+        assert_eq!(l.format, IntelHex);
+        assert_eq!(l.prgm, vec![0x00, 0xe0, 0x12, 0x00]);
+
+        // Relative addresses, with a gap.
+        let l = decode(b":01000000609F\n:01000300AA52\n:00000001FF\n", ROM_LOC).unwrap();
+        assert_eq!(l.prgm, vec![0x60, 0, 0, 0xaa]);
+
+        let bad = decode(b":0402000000E01200FF\n:00000001FF\n", ROM_LOC);
+        assert_eq!(bad.err(), Some("line 1: bad checksum FF, should be 08".to_string()));
+        assert!(decode(b":0402000000E0120008\n", ROM_LOC).is_err());
+
+        // An ETI-660 program, at its own load address.
+        let l = decode(b":0206000000E018\n:00000001FF\n", 0x600).unwrap();
+        assert_eq!(l.prgm, vec![0x00, 0xe0]);
+    }
+
+    #[test]
+    fn test_gzip() {
+        // gzip.compress(b"\x00\xe0\x12\x00", mtime=0)
+        let l = decode(b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x63\x78\x20\xc4\x00\x00\
+                         \x6f\x7f\x52\xf0\x04\x00\x00\x00", ROM_LOC).unwrap();
+        assert!(l.gzipped);
+        assert_eq!(l.format, Raw);
+        assert_eq!(l.prgm, vec![0x00, 0xe0, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_size() {
+        let mut prgm = vec![1, 2, 3];
+        assert_eq!(check_size(&mut prgm, 3, false), Ok(None));
+        assert!(check_size(&mut prgm, 2, false).is_err());
+        assert!(check_size(&mut prgm, 2, true).unwrap().is_some());
+        assert_eq!(prgm, vec![1, 2]);
+    }
+}
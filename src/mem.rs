@@ -1,31 +1,128 @@
 use std::default::Default;
-use std::io::IoResult;
+use std::io::{IoError, IoResult, InvalidInput};
+use std::num;
 
+use font::{Font, SMALL_SIZE, MAX_BIG_SIZE, BIG_SPRITE_SIZE};
+
+// The COSMAC VIP's layout, which is the default.
 pub static MEMORY_SIZE: u16 = 4096;
 pub static ROM_LOC: u16 = 0x200;
 pub static ROM_SIZE: u16 = MEMORY_SIZE - ROM_LOC;
 pub static FONT_SPRITE_SIZE: uint = 5;
 pub static FONT_SPRITES: uint = 16;
-static FONT_LOC: uint = 0;
+static FONT_LOC: u16 = 0;
+/// The VIP keeps return addresses in memory, growing down from here.
+pub static VIP_STACK_TOP: u16 = 0xed0;
+/// The small font, then room for a big one of 16 digits after it.
+pub static FONT_AREA_SIZE: uint = SMALL_SIZE + MAX_BIG_SIZE;
+
+/// The memory sizes interpreters have: the VIP's 4K, and XO-CHIP's
+/// 64K.
+pub static MEMORY_SIZES: [uint, ..2] = [4096, 65536];
+
+/// Where things go in memory.
+#[deriving(Clone, PartialEq, Show)]
+pub struct MemoryMap {
+    pub load_address: u16, // where programs are loaded and start: 0x600 on the ETI-660
+    pub font_address: u16, // where the hex digit sprites go: often 0x50
+    pub size: uint,        // one of MEMORY_SIZES
+    pub reserved: uint,    // bytes at the top the interpreter keeps for itself
+}
+
+impl MemoryMap {
+    pub fn new() -> MemoryMap {
+        MemoryMap {
+            load_address: ROM_LOC,
+            font_address: FONT_LOC,
+            size: MEMORY_SIZE as uint,
+            reserved: 0,
+        }
+    }
+
+    /// The largest program that fits between the load address and
+    /// the reserved area.
+    pub fn max_program_size(&self) -> uint {
+        let top = self.size - self.reserved;
+        if top > self.load_address as uint { top - self.load_address as uint } else { 0 }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if !MEMORY_SIZES.contains(&self.size) {
+            return Err(format!("memory size {} isn't 4096 or 65536", self.size));
+        }
+        if self.reserved >= self.size {
+            return Err(format!("{} reserved bytes leave no memory", self.reserved));
+        }
+        let load = self.load_address as uint;
+        let top = self.size - self.reserved;
+        if load >= top {
+            return Err(format!("load address {:X} is past the usable memory, which ends at {:X}",
+                               load, top));
+        }
+        let (font, font_end) = (self.font_address as uint,
+                                self.font_address as uint + FONT_AREA_SIZE);
+        if font_end > self.size {
+            return Err(format!("the font at {:X} runs off the end of memory", font));
+        }
+        if font < top && font_end > load {
+            return Err(format!("the font at {:X} overlaps the program at {:X}", font, load));
+        }
+        Ok(())
+    }
+}
+
+impl Default for MemoryMap {
+    fn default() -> MemoryMap { MemoryMap::new() }
+}
+
+/// Parse an address or size: decimal, or hex after `0x` or `$`.
+pub fn parse_number(s: &str) -> Option<uint> {
+    let s = s.trim();
+    if s.starts_with("0x") || s.starts_with("0X") {
+        num::from_str_radix(s.slice_from(2), 16)
+    } else if s.starts_with("$") {
+        num::from_str_radix(s.slice_from(1), 16)
+    } else {
+        from_str(s)
+    }
+}
 
 pub struct Memory {
-    mem: [u8, ..MEMORY_SIZE]
+    mem: Vec<u8>,
+    map: MemoryMap,
 }
 
 impl Memory {
     pub fn new() -> Memory {
-        Memory { mem: [0, ..MEMORY_SIZE as uint] }
+        Memory::with_map(MemoryMap::new())
+    }
+
+    pub fn with_map(map: MemoryMap) -> Memory {
+        Memory { mem: Vec::from_elem(map.size, 0u8), map: map }
+    }
+
+    pub fn map<'a>(&'a self) -> &'a MemoryMap {
+        &self.map
     }
 
     pub fn load_rom(&mut self, rom: Rom) {
-        let dst = self.mem.mut_slice_from(ROM_LOC as uint);
-        dst.copy_from(rom.prgm.as_slice());
+        assert!(rom.len() <= self.map.max_program_size());
+        let load = self.map.load_address as uint;
+        let dst = self.mem.mut_slice(load, load + rom.len());
+        dst.copy_from(rom.as_slice());
     }
 
-    pub fn load_font(&mut self, sprites: &[u8]) {
-        assert!(sprites.len() == FONT_SPRITE_SIZE * FONT_SPRITES);
-        let dst = self.mem.mut_slice(FONT_LOC, FONT_SPRITE_SIZE * FONT_SPRITES);
-        dst.copy_from(sprites);
+    /// Put the small font at the font address, and the big one
+    /// straight after it, clearing what an earlier font left.
+    pub fn load_font(&mut self, font: &Font) {
+        assert!(font.small.len() == SMALL_SIZE && font.big.len() <= MAX_BIG_SIZE);
+        let start = self.map.font_address as uint;
+        let dst = self.mem.mut_slice(start, start + FONT_AREA_SIZE);
+        for b in dst.mut_iter() {
+            *b = 0;
+        }
+        dst.copy_from(font.small.as_slice());
+        dst.mut_slice_from(SMALL_SIZE).copy_from(font.big.as_slice());
     }
 
     pub fn get(&self, i: u16) -> u8 {
@@ -40,9 +137,23 @@ impl Memory {
         self.mem.mut_slice(start as uint, end as uint)
     }
 
+    /// All of memory.
+    pub fn as_slice<'a>(&'a self) -> &'a [u8] {
+        self.mem.as_slice()
+    }
+
+    pub fn as_mut_slice<'a>(&'a mut self) -> &'a mut [u8] {
+        self.mem.as_mut_slice()
+    }
+
     pub fn font_offset(&self, n: u8) -> u16 {
         let n: uint = (n & 0xf) as uint;
-        (FONT_LOC + n * FONT_SPRITE_SIZE) as u16
+        self.map.font_address + (n * FONT_SPRITE_SIZE) as u16
+    }
+
+    pub fn big_font_offset(&self, n: u8) -> u16 {
+        let n: uint = (n & 0xf) as uint;
+        self.map.font_address + (SMALL_SIZE + n * BIG_SPRITE_SIZE) as u16
     }
 }
 
@@ -50,13 +161,90 @@ impl Default for Memory {
     fn default() -> Memory { Memory::new() }
 }
 
+/// A program, with the memory map it's to be loaded into, and no
+/// larger than fits there.
 pub struct Rom {
-    prgm: [u8, ..ROM_SIZE]
+    prgm: Vec<u8>,
+    map: MemoryMap,
 }
 
 impl Rom {
+    /// A program for the default memory map.
+    pub fn new(prgm: Vec<u8>) -> Rom {
+        Rom::with_map(prgm, MemoryMap::new())
+    }
+
+    pub fn with_map(prgm: Vec<u8>, map: MemoryMap) -> Rom {
+        assert!(prgm.len() <= map.max_program_size());
+        Rom { prgm: prgm, map: map }
+    }
+
+    /// Read a raw program to the end of `r`.
     pub fn from_reader(r: &mut Reader) -> IoResult<Rom> {
-        let mut buf = [0u8, ..ROM_SIZE as uint];
-        r.read(buf.as_mut_slice()).map(|_| Rom { prgm: buf  })
+        let prgm = try!(r.read_to_end());
+        if prgm.len() > ROM_SIZE as uint {
+            return Err(IoError {
+                kind: InvalidInput,
+                desc: "ROM too large",
+                detail: Some(format!("{} bytes, but only {} fit in memory", prgm.len(), ROM_SIZE)),
+            });
+        }
+        Ok(Rom::new(prgm))
+    }
+
+    pub fn len(&self) -> uint {
+        self.prgm.len()
+    }
+
+    pub fn as_slice<'a>(&'a self) -> &'a [u8] {
+        self.prgm.as_slice()
+    }
+
+    pub fn map<'a>(&'a self) -> &'a MemoryMap {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Memory, MemoryMap, Rom, parse_number};
+    use font::Font;
+
+    #[test]
+    fn test_map() {
+        let mut map = MemoryMap::new();
+        assert_eq!(map.max_program_size(), 0xe00);
+        assert!(map.check().is_ok());
+
+        // An ETI-660 program, with the font where many interpreters
+        // put it.
+        map.load_address = 0x600;
+        map.font_address = 0x50;
+        assert_eq!(map.max_program_size(), 0xa00);
+        let mut mem = Memory::with_map(map.clone());
+        mem.load_rom(Rom::with_map(vec![0x12, 0x34], map.clone()));
+        assert_eq!(mem.slice(0x600, 0x602), [0x12, 0x34].as_slice());
+        assert_eq!(mem.font_offset(2), 0x5a);
+        assert_eq!(mem.big_font_offset(2), 0xb4);
+        mem.load_font(&Font::preset("schip").unwrap());
+        assert_eq!(mem.slice(0xa0, 0xa2), [0x3c, 0x7e].as_slice());
+        assert_eq!(mem.get(0x104), 0);
+
+        map.font_address = 0x5f0;
+        assert!(map.check().is_err());
+        map.font_address = 0x50;
+        map.size = 8192;
+        assert!(map.check().is_err());
+        map.size = 65536;
+        map.reserved = 0xfa00;
+        assert!(map.check().is_err());
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("0x600"), Some(0x600));
+        assert_eq!(parse_number("$50"), Some(0x50));
+        assert_eq!(parse_number("4096"), Some(4096));
+        assert_eq!(parse_number("0x"), None);
     }
 }
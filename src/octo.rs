@@ -0,0 +1,683 @@
+//! A compiler for Octo, the CHIP-8 assembly language Octo cartridges
+//! are written in. It covers the statements, registers, labels,
+//! `:const`, `:alias`, `:unpack`, `:next`, `:org` and structured
+//! control flow; macros, `:calc` and string modes aren't supported.
+//!
+//! As in Octo, execution starts with a jump to the `main` label.
+
+use std::ascii::StrAsciiExt;
+use std::collections::HashMap;
+use std::num;
+
+/// Where an address that wasn't known yet has to go.
+#[deriving(Clone, PartialEq, Show)]
+enum Fixup {
+    Addr12,         // the low 12 bits of an instruction
+    Addr16,         // the word after `i := long`
+    UnpackHi(u8),   // `v0 := NNNN >> 8`, with a nibble on top
+    UnpackLo,       // `v1 := NNNN & 0xFF`
+}
+
+/// An open `if ... begin`, `else` or `loop`, waiting for its end.
+enum Block {
+    If(uint),             // the jump over the `begin` part
+    Else(uint),           // the jump over the `else` part
+    Loop(uint, Vec<uint>), // the start, and the jumps out of `while`s
+}
+
+struct Compiler {
+    tokens: Vec<(String, uint)>, // with line numbers
+    pos: uint,
+    line: uint,
+    base: uint,
+    here: uint,
+    rom: Vec<u8>,
+    names: HashMap<String, uint>,  // labels and constants
+    aliases: HashMap<String, u8>,
+    fixups: Vec<(uint, String, Fixup, uint)>, // address, name, kind, line
+    blocks: Vec<Block>,
+}
+
+static RESERVED: &'static [&'static str] = &[
+    ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=",
+    "then", "begin", "else", "end", "loop", "again", "while", "if", "key", "-key", "hex",
+    "bighex", "long", "random", "delay", "buzzer", "pitch", "i", "return", "clear",
+];
+
+/// Octo directives this compiler doesn't support.
+static UNSUPPORTED: &'static [&'static str] = &[
+    ":macro", ":calc", ":stringmode", ":assert", ":pointer",
+];
+
+/// Split source into words, with their line numbers, leaving out comments.
+fn tokenize(src: &str) -> Vec<(String, uint)> {
+    let mut tokens = vec![];
+    for (n, line) in src.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(i) => line.slice_to(i),
+            None => line
+        };
+        for word in code.words() {
+            tokens.push((word.to_string(), n + 1));
+        }
+    }
+    tokens
+}
+
+/// The first directive in `src` that this compiler doesn't support,
+/// like `:macro`, and the line it's on.
+pub fn unsupported(src: &str) -> Option<(String, uint)> {
+    tokenize(src).iter()
+        .find(|t| UNSUPPORTED.contains(&t.ref0().as_slice()))
+        .map(|t| t.clone())
+}
+
+/// Compile Octo source to a program loaded at `base`.
+pub fn compile(src: &str, base: u16) -> Result<Vec<u8>, String> {
+    let mut c = Compiler {
+        tokens: tokenize(src),
+        pos: 0,
+        line: 0,
+        base: base as uint,
+        here: base as uint,
+        rom: vec![],
+        names: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: vec![],
+        blocks: vec![],
+    };
+    // Start with `jump main`.
+    c.fixups.push((c.here, "main".to_string(), Addr12, 0));
+    c.inst(0x10, 0x00);
+    while c.pos < c.tokens.len() {
+        let tok = try!(c.next());
+        try!(c.statement(tok.as_slice()).map_err(|e| format!("line {}: {}", c.line, e)));
+    }
+    match c.blocks.last() {
+        Some(&Loop(..)) => return Err("`loop` without `again`".to_string()),
+        Some(_) => return Err("`if ... begin` without `end`".to_string()),
+        None => {}
+    }
+    if !c.names.contains_key(&"main".to_string()) {
+        return Err("the program has no `main` label".to_string());
+    }
+    try!(c.resolve());
+    Ok(c.rom)
+}
+
+fn parse_number(s: &str) -> Option<int> {
+    let (neg, s) = if s.starts_with("-") { (true, s.slice_from(1)) } else { (false, s) };
+    let n = if s.starts_with("0x") || s.starts_with("0X") {
+        num::from_str_radix::<int>(s.slice_from(2), 16)
+    } else if s.starts_with("0b") || s.starts_with("0B") {
+        num::from_str_radix::<int>(s.slice_from(2), 2)
+    } else {
+        from_str::<int>(s)
+    };
+    n.map(|n| if neg { -n } else { n })
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty() && !s.char_at(0).is_digit() && !s.starts_with(":") && !s.starts_with("-") &&
+        s.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.') &&
+        !RESERVED.contains(&s)
+}
+
+impl Compiler {
+    fn next(&mut self) -> Result<String, String> {
+        if self.pos >= self.tokens.len() {
+            return Err("the program ends in the middle of a statement".to_string());
+        }
+        let (tok, line) = self.tokens[self.pos].clone();
+        self.pos += 1;
+        self.line = line;
+        Ok(tok)
+    }
+
+    fn peek<'a>(&'a self) -> Option<&'a str> {
+        if self.pos < self.tokens.len() {
+            Some(self.tokens[self.pos].ref0().as_slice())
+        } else {
+            None
+        }
+    }
+
+    fn expect(&mut self, what: &str) -> Result<(), String> {
+        let tok = try!(self.next());
+        if tok.as_slice() != what {
+            return Err(format!("expected `{}`, found `{}`", what, tok));
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, b: u8) {
+        let i = self.here - self.base;
+        while self.rom.len() <= i {
+            self.rom.push(0);
+        }
+        *self.rom.get_mut(i) = b;
+        self.here += 1;
+    }
+
+    fn inst(&mut self, hi: u8, lo: u8) {
+        self.byte(hi);
+        self.byte(lo);
+    }
+
+    fn define(&mut self, name: String, value: uint) -> Result<(), String> {
+        if !is_name(name.as_slice()) {
+            return Err(format!("`{}` can't be used as a name", name));
+        }
+        if self.names.contains_key(&name) || self.aliases.contains_key(&name) {
+            return Err(format!("`{}` is defined twice", name));
+        }
+        self.names.insert(name, value);
+        Ok(())
+    }
+
+    fn register_name(&self, tok: &str) -> Option<u8> {
+        let lower = tok.to_ascii_lower();
+        if lower.len() == 2 && lower.as_slice().starts_with("v") {
+            match num::from_str_radix::<u8>(lower.as_slice().slice_from(1), 16) {
+                Some(r) => return Some(r),
+                None => {}
+            }
+        }
+        self.aliases.find_copy(&tok.to_string())
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let tok = try!(self.next());
+        match self.register_name(tok.as_slice()) {
+            Some(r) => Ok(r),
+            None => Err(format!("expected a register, found `{}`", tok))
+        }
+    }
+
+    fn peek_register(&self) -> Option<u8> {
+        self.peek().and_then(|tok| self.register_name(tok))
+    }
+
+    /// A number or constant that must fit in `bits` bits. Negative
+    /// numbers count down from the top, so -1 is 0xFF as a byte.
+    fn value(&mut self, bits: uint) -> Result<uint, String> {
+        let tok = try!(self.next());
+        let n = match parse_number(tok.as_slice()) {
+            Some(n) => n,
+            None => match self.names.find_copy(&tok) {
+                Some(n) => n as int,
+                None => return Err(format!("`{}` isn't a number or a defined constant", tok))
+            }
+        };
+        let max = (1 << bits) as int;
+        if n >= max || n < -max / 2 {
+            return Err(format!("{} doesn't fit in {} bits", tok, bits));
+        }
+        Ok((n & (max - 1)) as uint)
+    }
+
+    /// An address, which may be a label defined further on.
+    fn address(&mut self, at: uint, kind: Fixup) -> Result<uint, String> {
+        let tok = try!(self.next());
+        match parse_number(tok.as_slice()) {
+            Some(n) if n >= 0 => return Ok(n as uint),
+            Some(_) => return Err(format!("expected an address, found `{}`", tok)),
+            None => {}
+        }
+        match self.names.find_copy(&tok) {
+            Some(n) => Ok(n),
+            None if is_name(tok.as_slice()) => {
+                self.fixups.push((at, tok, kind, self.line));
+                Ok(0)
+            },
+            None => Err(format!("expected an address, found `{}`", tok))
+        }
+    }
+
+    fn addr12(&mut self, op: u8) -> Result<(), String> {
+        let at = self.here;
+        let addr = try!(self.address(at, Addr12));
+        if addr > 0xfff {
+            return Err(format!("address {:X} is out of reach; `i := long` reaches it", addr));
+        }
+        self.inst(op | (addr >> 8) as u8, addr as u8);
+        Ok(())
+    }
+
+    fn patch(&mut self, at: uint, target: uint) {
+        let i = at - self.base;
+        *self.rom.get_mut(i) = self.rom[i] & 0xf0 | ((target >> 8) & 0xf) as u8;
+        *self.rom.get_mut(i + 1) = target as u8;
+    }
+
+    fn resolve(&mut self) -> Result<(), String> {
+        for &(at, ref name, ref kind, line) in self.fixups.iter() {
+            let addr = match self.names.find_copy(name) {
+                Some(addr) => addr,
+                None => return Err(format!("line {}: `{}` is never defined", line, name))
+            };
+            let i = at - self.base;
+            match *kind {
+                Addr12 => {
+                    if addr > 0xfff {
+                        return Err(format!("line {}: `{}` is out of reach at {:X}; \
+                                            `i := long` reaches it", line, name, addr));
+                    }
+                    *self.rom.get_mut(i) = self.rom[i] & 0xf0 | (addr >> 8) as u8;
+                    *self.rom.get_mut(i + 1) = addr as u8;
+                },
+                Addr16 => {
+                    *self.rom.get_mut(i + 2) = (addr >> 8) as u8;
+                    *self.rom.get_mut(i + 3) = addr as u8;
+                },
+                UnpackHi(nibble) => *self.rom.get_mut(i + 1) = nibble << 4 | (addr >> 8) as u8,
+                UnpackLo => *self.rom.get_mut(i + 1) = addr as u8,
+            }
+        }
+        Ok(())
+    }
+
+    /// Compile a condition, as the instruction(s) that skip the next
+    /// one if it's false, or if `skip_if_true`, if it's true.
+    fn condition(&mut self, skip_if_true: bool) -> Result<(), String> {
+        let x = try!(self.register());
+        let op = try!(self.next());
+        let pick = |then_form: u8, skip_form: u8| if skip_if_true { skip_form } else { then_form };
+        match op.as_slice() {
+            "key" => self.inst(0xe0 | x, pick(0xa1, 0x9e)),
+            "-key" => self.inst(0xe0 | x, pick(0x9e, 0xa1)),
+            "==" | "!=" => {
+                // `then` needs the opposite skip: `if v0 == 1 then`
+                // skips when v0 != 1.
+                let skip_unequal = (op.as_slice() == "==") != skip_if_true;
+                match self.peek_register() {
+                    Some(y) => {
+                        self.pos += 1;
+                        self.inst(if skip_unequal { 0x90 } else { 0x50 } | x, y << 4);
+                    },
+                    None => {
+                        let n = try!(self.value(8)) as u8;
+                        self.inst(if skip_unequal { 0x40 } else { 0x30 } | x, n);
+                    }
+                }
+            },
+            "<" | ">" | "<=" | ">=" => {
+                // vf := the other side, then subtract so the flag says
+                // whether the condition holds.
+                match self.peek_register() {
+                    Some(y) => {
+                        self.pos += 1;
+                        self.inst(0x8f, y << 4);
+                    },
+                    None => {
+                        let n = try!(self.value(8)) as u8;
+                        self.inst(0x6f, n);
+                    }
+                }
+                let (sub, flag) = match op.as_slice() {
+                    ">" => (0x5, 0),  // vf = other - vx; no borrow unless vx > other
+                    "<=" => (0x5, 1),
+                    "<" => (0x7, 0),  // vf = vx - other; no borrow unless vx < other
+                    _ => (0x7, 1),
+                };
+                self.inst(0x8f, x << 4 | sub);
+                self.inst(pick(0x4f, 0x3f), flag);
+            },
+            _ => return Err(format!("expected a comparison, found `{}`", op))
+        }
+        Ok(())
+    }
+
+    fn assignment(&mut self, x: u8) -> Result<(), String> {
+        let op = try!(self.next());
+        let y = self.peek_register();
+        let math = |n: u8| -> Result<(u8, u8), String> {
+            match y {
+                Some(y) => Ok((0x80 | x, y << 4 | n)),
+                None => Err(format!("`{}` needs a register on the right", op))
+            }
+        };
+        let word = self.peek().unwrap_or("").to_string();
+        let (hi, lo) = match op.as_slice() {
+            ":=" => match (y, word.as_slice()) {
+                (Some(y), _) => (0x80 | x, y << 4),
+                (None, "random") => {
+                    self.pos += 1;
+                    (0xc0 | x, try!(self.value(8)) as u8)
+                },
+                (None, "delay") => { self.pos += 1; (0xf0 | x, 0x07) },
+                (None, "key") => { self.pos += 1; (0xf0 | x, 0x0a) },
+                _ => (0x60 | x, try!(self.value(8)) as u8)
+            },
+            "+=" => match y {
+                Some(y) => (0x80 | x, y << 4 | 0x4),
+                None => (0x70 | x, try!(self.value(8)) as u8)
+            },
+            "-=" => match y {
+                Some(y) => (0x80 | x, y << 4 | 0x5),
+                None => (0x70 | x, (0x100 - try!(self.value(8))) as u8)
+            },
+            "=-" => try!(math(0x7)),
+            "|=" => try!(math(0x1)),
+            "&=" => try!(math(0x2)),
+            "^=" => try!(math(0x3)),
+            ">>=" => try!(math(0x6)),
+            "<<=" => try!(math(0xe)),
+            _ => return Err(format!("expected an assignment, found `{}`", op))
+        };
+        if y.is_some() {
+            self.pos += 1;
+        }
+        self.inst(hi, lo);
+        Ok(())
+    }
+
+    fn statement(&mut self, tok: &str) -> Result<(), String> {
+        match self.register_name(tok) {
+            Some(x) => return self.assignment(x),
+            None => {}
+        }
+        match tok {
+            ":" => {
+                let name = try!(self.next());
+                let here = self.here;
+                try!(self.define(name, here));
+            },
+            ":const" => {
+                let name = try!(self.next());
+                let value = try!(self.value(16));
+                try!(self.define(name, value));
+            },
+            ":alias" => {
+                let name = try!(self.next());
+                let r = try!(self.register());
+                if !is_name(name.as_slice()) || self.names.contains_key(&name) {
+                    return Err(format!("`{}` can't be used as an alias", name));
+                }
+                self.aliases.insert(name, r);
+            },
+            ":org" => {
+                let addr = try!(self.value(16));
+                if addr < self.base {
+                    return Err(format!("`:org {:X}` is below the start of the program", addr));
+                }
+                self.here = addr;
+            },
+            ":next" => {
+                let name = try!(self.next());
+                let here = self.here;
+                try!(self.define(name, here + 1));
+            },
+            ":unpack" => {
+                let nibble = if self.peek() == Some("long") {
+                    self.pos += 1;
+                    0
+                } else {
+                    try!(self.value(4)) as u8
+                };
+                let (at, fixups) = (self.here, self.fixups.len());
+                let addr = try!(self.address(at, UnpackHi(nibble)));
+                if self.fixups.len() > fixups {
+                    let (_, name, _, line) = self.fixups[fixups].clone();
+                    self.fixups.push((at + 2, name, UnpackLo, line));
+                }
+                self.inst(0x60, nibble << 4 | (addr >> 8) as u8);
+                self.inst(0x61, addr as u8);
+            },
+            ":byte" => {
+                let b = try!(self.value(8)) as u8;
+                self.byte(b);
+            },
+            ":call" => try!(self.addr12(0x20)),
+            ":breakpoint" => { try!(self.next()); },
+            ":monitor" => { try!(self.next()); try!(self.next()); },
+            _ if UNSUPPORTED.contains(&tok) => {
+                return Err(format!("`{}` isn't supported", tok));
+            },
+            ";" | "return" => self.inst(0x00, 0xee),
+            "clear" => self.inst(0x00, 0xe0),
+            "hires" => self.inst(0x00, 0xff),
+            "lores" => self.inst(0x00, 0xfe),
+            "scroll-right" => self.inst(0x00, 0xfb),
+            "scroll-left" => self.inst(0x00, 0xfc),
+            "exit" => self.inst(0x00, 0xfd),
+            "scroll-down" => {
+                let n = try!(self.value(4)) as u8;
+                self.inst(0x00, 0xc0 | n);
+            },
+            "scroll-up" => {
+                let n = try!(self.value(4)) as u8;
+                self.inst(0x00, 0xd0 | n);
+            },
+            "audio" => self.inst(0xf0, 0x02),
+            "plane" => {
+                let n = try!(self.value(4)) as u8;
+                self.inst(0xf0 | n, 0x01);
+            },
+            "bcd" => {
+                let x = try!(self.register());
+                self.inst(0xf0 | x, 0x33);
+            },
+            "save" | "load" => {
+                let x = try!(self.register());
+                if self.peek() == Some("-") {
+                    self.pos += 1;
+                    let y = try!(self.register());
+                    self.inst(0x50 | x, y << 4 | if tok == "save" { 2 } else { 3 });
+                } else {
+                    self.inst(0xf0 | x, if tok == "save" { 0x55 } else { 0x65 });
+                }
+            },
+            "saveflags" => {
+                let x = try!(self.register());
+                self.inst(0xf0 | x, 0x75);
+            },
+            "loadflags" => {
+                let x = try!(self.register());
+                self.inst(0xf0 | x, 0x85);
+            },
+            "sprite" => {
+                let x = try!(self.register());
+                let y = try!(self.register());
+                let n = try!(self.value(4)) as u8;
+                self.inst(0xd0 | x, y << 4 | n);
+            },
+            "jump" => try!(self.addr12(0x10)),
+            "jump0" => try!(self.addr12(0xb0)),
+            "native" => try!(self.addr12(0x00)),
+            "delay" | "buzzer" | "pitch" => {
+                try!(self.expect(":="));
+                let x = try!(self.register());
+                self.inst(0xf0 | x, match tok { "delay" => 0x15, "buzzer" => 0x18, _ => 0x3a });
+            },
+            "i" => {
+                let op = try!(self.next());
+                let word = self.peek().unwrap_or("").to_string();
+                match (op.as_slice(), word.as_slice()) {
+                    ("+=", _) => {
+                        let x = try!(self.register());
+                        self.inst(0xf0 | x, 0x1e);
+                    },
+                    (":=", "hex") | (":=", "bighex") => {
+                        self.pos += 1;
+                        let x = try!(self.register());
+                        self.inst(0xf0 | x, if word.as_slice() == "hex" { 0x29 } else { 0x30 });
+                    },
+                    (":=", "long") => {
+                        self.pos += 1;
+                        let at = self.here;
+                        let addr = try!(self.address(at, Addr16));
+                        if addr > 0xffff {
+                            return Err(format!("address {:X} is out of reach", addr));
+                        }
+                        self.inst(0xf0, 0x00);
+                        self.inst((addr >> 8) as u8, addr as u8);
+                    },
+                    (":=", _) => try!(self.addr12(0xa0)),
+                    _ => return Err(format!("expected `:=` or `+=` after `i`, found `{}`", op))
+                }
+            },
+            "if" => {
+                let then = {
+                    // Look past the condition for `then` or `begin`.
+                    let rest = self.tokens.slice_from(self.pos);
+                    match rest.iter().position(|t| {
+                        t.ref0().as_slice() == "then" || t.ref0().as_slice() == "begin"
+                    }) {
+                        Some(i) => rest[i].ref0().as_slice() == "then",
+                        None => return Err("`if` without `then` or `begin`".to_string())
+                    }
+                };
+                if then {
+                    try!(self.condition(false));
+                    try!(self.expect("then"));
+                } else {
+                    try!(self.condition(true));
+                    try!(self.expect("begin"));
+                    let here = self.here;
+                    self.blocks.push(If(here));
+                    self.inst(0x10, 0x00);
+                }
+            },
+            "else" => match self.blocks.pop() {
+                Some(If(jump)) => {
+                    let here = self.here;
+                    self.blocks.push(Else(here));
+                    self.inst(0x10, 0x00);
+                    let target = self.here;
+                    self.patch(jump, target);
+                },
+                _ => return Err("`else` without `if ... begin`".to_string())
+            },
+            "end" => match self.blocks.pop() {
+                Some(If(jump)) | Some(Else(jump)) => {
+                    let here = self.here;
+                    self.patch(jump, here);
+                },
+                _ => return Err("`end` without `if ... begin`".to_string())
+            },
+            "loop" => {
+                let here = self.here;
+                self.blocks.push(Loop(here, vec![]));
+            },
+            "while" => {
+                try!(self.condition(true));
+                let here = self.here;
+                let mut inside = false;
+                for block in self.blocks.mut_iter().rev() {
+                    match *block {
+                        Loop(_, ref mut exits) => {
+                            exits.push(here);
+                            inside = true;
+                            break;
+                        },
+                        _ => {}
+                    }
+                }
+                if !inside {
+                    return Err("`while` outside a `loop`".to_string());
+                }
+                self.inst(0x10, 0x00);
+            },
+            "again" => match self.blocks.pop() {
+                Some(Loop(start, exits)) => {
+                    self.inst(0x10 | (start >> 8) as u8, start as u8);
+                    let here = self.here;
+                    for &exit in exits.iter() {
+                        self.patch(exit, here);
+                    }
+                },
+                _ => return Err("`again` without `loop`".to_string())
+            },
+            _ => match parse_number(tok) {
+                Some(n) if n >= -128 && n < 256 => self.byte(n as u8),
+                Some(_) => return Err(format!("{} doesn't fit in a byte", tok)),
+                None if is_name(tok) => {
+                    // A bare name calls a subroutine.
+                    self.pos -= 1;
+                    try!(self.addr12(0x20));
+                },
+                None => return Err(format!("unexpected `{}`", tok))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile, unsupported};
+
+    #[test]
+    fn test_compile() {
+        let prgm = compile("
+            :alias x v1
+            :const speed 2
+            : main
+                clear
+                x := 0
+                loop
+                    i := dot
+                    sprite x v2 1   # draw it
+                    x += speed
+                    if x == 60 then x := 0
+                    while v3 != 5
+                    draw
+                again
+            : draw  ;
+            : dot   0x80
+        ", 0x200).unwrap();
+        assert_eq!(prgm, vec![0x12, 0x02,  // jump main
+                              0x00, 0xe0,  // clear
+                              0x61, 0x00,
+                              0xa2, 0x1a,  // i := dot
+                              0xd1, 0x21,
+                              0x71, 0x02,
+                              0x41, 0x3c,  // skip unless x == 60
+                              0x61, 0x00,
+                              0x43, 0x05,  // leave the loop unless v3 != 5
+                              0x12, 0x18,
+                              0x22, 0x18,  // draw
+                              0x12, 0x06,
+                              0x00, 0xee,
+                              0x80]);
+    }
+
+    #[test]
+    fn test_begin_else() {
+        let prgm = compile(": main if v0 > 3 begin v1 := 1 else v1 := 2 end", 0x200).unwrap();
+        assert_eq!(prgm, vec![0x12, 0x02,
+                              0x6f, 0x03,  // vf := 3
+                              0x8f, 0x05,  // vf -= v0
+                              0x3f, 0x00,  // v0 > 3 if there was a borrow
+                              0x12, 0x0e,
+                              0x61, 0x01,
+                              0x12, 0x10,
+                              0x61, 0x02]);
+    }
+
+    #[test]
+    fn test_unpack_and_long() {
+        let prgm = compile(": main :unpack 0xA data i := long data : data", 0x200).unwrap();
+        assert_eq!(prgm, vec![0x12, 0x02, 0x60, 0xa2, 0x61, 0x0a, 0xf0, 0x00, 0x02, 0x0a]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(compile("clear", 0x200).is_err()); // no main
+        assert!(compile(": main jump nowhere", 0x200).is_err());
+        assert!(compile(": main v0 := 256", 0x200).is_err());
+        assert!(compile(": main loop v0 += 1", 0x200).is_err());
+        assert!(compile(": main : main", 0x200).is_err());
+        assert!(compile(": main :macro m { }", 0x200).is_err());
+        assert_eq!(compile(": main\nv0 := 1\nv0 |= 2", 0x200).err(),
+                   Some("line 3: `|=` needs a register on the right".to_string()));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(unsupported(": main\n# :macro in a comment\n:calc two { 1 + 1 }"),
+                   Some((":calc".to_string(), 3)));
+        assert_eq!(unsupported(": main v0 := 1"), None);
+    }
+}
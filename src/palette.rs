@@ -0,0 +1,154 @@
+use std::num;
+
+/// An RGBA color.
+pub type Color = [u8, ..4];
+
+pub static MAX_COLORS: uint = 16;
+pub static DEFAULT_PALETTE: &'static str = "solarized";
+
+/// Colors for each pixel value. Index 0 is the background and 1 is
+/// an ordinary lit pixel; with more than one bitplane, index `n` is
+/// the pixel lit in the planes set in `n` (so 2 is the second plane
+/// alone and 3 is both).
+#[deriving(Clone, PartialEq, Show)]
+pub struct Palette {
+    colors: Vec<Color>,
+}
+
+// off, plane 1, plane 2, both planes
+static PRESETS: [(&'static str, [Color, ..4]), ..6] = [
+    ("solarized",     [[0x00, 0x2b, 0x36, 0xff], [0x6c, 0x71, 0xc4, 0xff],
+                       [0x2a, 0xa1, 0x98, 0xff], [0xfd, 0xf6, 0xe3, 0xff]]),
+    ("green",         [[0x0a, 0x1a, 0x0a, 0xff], [0x33, 0xff, 0x33, 0xff],
+                       [0x1a, 0x80, 0x1a, 0xff], [0x99, 0xff, 0x99, 0xff]]),
+    ("amber",         [[0x1a, 0x10, 0x00, 0xff], [0xff, 0xb0, 0x00, 0xff],
+                       [0x80, 0x58, 0x00, 0xff], [0xff, 0xd7, 0x80, 0xff]]),
+    ("octo",          [[0x99, 0x66, 0x00, 0xff], [0xff, 0xcc, 0x00, 0xff],
+                       [0xff, 0x66, 0x00, 0xff], [0x66, 0x22, 0x00, 0xff]]),
+    ("high-contrast", [[0x00, 0x00, 0x00, 0xff], [0xff, 0xff, 0xff, 0xff],
+                       [0xff, 0xff, 0x00, 0xff], [0x00, 0xff, 0xff, 0xff]]),
+    // Okabe-Ito colors, distinguishable with any kind of color blindness.
+    ("colorblind",    [[0x00, 0x00, 0x00, 0xff], [0xe6, 0x9f, 0x00, 0xff],
+                       [0x56, 0xb4, 0xe9, 0xff], [0xf0, 0xe4, 0x42, 0xff]]),
+];
+
+impl Palette {
+    pub fn new(colors: &[Color]) -> Palette {
+        assert!(colors.len() >= 2 && colors.len() <= MAX_COLORS);
+        Palette { colors: colors.to_vec() }
+    }
+
+    /// Look up a named palette: `solarized` (the default), `green`,
+    /// `amber`, `octo`, `high-contrast` or `colorblind`.
+    pub fn preset(name: &str) -> Option<Palette> {
+        PRESETS.iter().find(|&&(n, _)| n == name).map(|&(_, ref colors)| {
+            Palette::new(colors.as_slice())
+        })
+    }
+
+    pub fn preset_names() -> Vec<&'static str> {
+        PRESETS.iter().map(|&(n, _)| n).collect()
+    }
+
+    /// Parse a palette name, or a comma-separated list of colors
+    /// starting from index 0, like `#000000,#ffffff`.
+    pub fn parse(spec: &str) -> Result<Palette, String> {
+        let spec = spec.trim();
+        if !spec.starts_with("#") {
+            return match Palette::preset(spec) {
+                Some(p) => Ok(p),
+                None => Err(format!("unknown palette `{}` (try one of: {})", spec,
+                                    Palette::preset_names().connect(", ")))
+            };
+        }
+        let mut colors = vec![];
+        for c in spec.split(',') {
+            match parse_color(c.trim()) {
+                Some(color) => colors.push(color),
+                None => return Err(format!("bad color `{}`, expected one like #6c71c4",
+                                           c.trim()))
+            }
+        }
+        if colors.len() < 2 || colors.len() > MAX_COLORS {
+            return Err(format!("a palette needs 2 to {} colors, not {}",
+                               MAX_COLORS, colors.len()));
+        }
+        Ok(Palette { colors: colors })
+    }
+
+    pub fn len(&self) -> uint {
+        self.colors.len()
+    }
+
+    /// The color for pixel value `index`. Values past the end of the
+    /// palette show as an ordinary lit pixel, so a two-color palette
+    /// still shows every plane.
+    pub fn color(&self, index: uint) -> Color {
+        if index < self.colors.len() {
+            self.colors[index]
+        } else {
+            self.colors[1]
+        }
+    }
+
+    /// Change one color, growing the palette if needed. New entries
+    /// in between start out as the lit color.
+    pub fn set(&mut self, index: uint, color: Color) {
+        assert!(index < MAX_COLORS);
+        while self.colors.len() <= index {
+            let lit = self.colors[1];
+            self.colors.push(lit);
+        }
+        *self.colors.get_mut(index) = color;
+    }
+}
+
+/// Parse a `#rrggbb` color into RGBA.
+pub fn parse_color(s: &str) -> Option<Color> {
+    if s.len() != 7 || !s.starts_with("#") {
+        return None;
+    }
+    let channel = |i: uint| num::from_str_radix::<u8>(s.slice(i, i + 2), 16);
+    match (channel(1), channel(3), channel(5)) {
+        (Some(r), Some(g), Some(b)) => Some([r, g, b, 0xff]),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Palette, DEFAULT_PALETTE, parse_color};
+
+    #[test]
+    fn test_parse() {
+        for name in Palette::preset_names().iter() {
+            assert_eq!(Palette::parse(*name).unwrap().len(), 4);
+        }
+        assert!(Palette::preset(DEFAULT_PALETTE).is_some());
+
+        let p = Palette::parse("#000000, #ffb000").unwrap();
+        assert_eq!(p.color(0), [0x00, 0x00, 0x00, 0xff]);
+        assert_eq!(p.color(1), [0xff, 0xb0, 0x00, 0xff]);
+
+        assert!(Palette::parse("mauve").is_err());
+        assert!(Palette::parse("#000000").is_err());
+        assert!(Palette::parse("#000000,white").is_err());
+    }
+
+    #[test]
+    fn test_extra_indices() {
+        let mut p = Palette::parse("#000000,#ffffff").unwrap();
+        assert_eq!(p.color(3), [0xff, 0xff, 0xff, 0xff]);
+        p.set(3, [0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(p.len(), 4);
+        assert_eq!(p.color(2), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(p.color(3), [0xff, 0x00, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#6c71c4"), Some([0x6c, 0x71, 0xc4, 0xff]));
+        assert_eq!(parse_color("6c71c4"), None);
+        assert_eq!(parse_color("#6c71cz"), None);
+    }
+}
@@ -0,0 +1,66 @@
+use mem::MemoryMap;
+
+/// The machines and interpreters CHIP-8 programs are written for.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Platform {
+    Chip8,     // the original, on the COSMAC VIP
+    Chip8X,    // the VIP's colour extension
+    SuperChip, // SCHIP 1.1, on HP48 calculators
+    XoChip,    // Octo's extension
+}
+
+impl Platform {
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name {
+            "chip8" | "chip-8" | "vip" => Some(Chip8),
+            "chip8x" | "chip-8x" => Some(Chip8X),
+            "schip" | "superchip" => Some(SuperChip),
+            "xochip" | "xo-chip" => Some(XoChip),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Chip8 => "chip8",
+            Chip8X => "chip8x",
+            SuperChip => "schip",
+            XoChip => "xochip",
+        }
+    }
+
+    /// The quirks preset that matches this platform.
+    pub fn quirks_preset(&self) -> &'static str {
+        match *self {
+            Chip8 | Chip8X => "vip",
+            SuperChip => "schip",
+            XoChip => "octo",
+        }
+    }
+
+    /// How this platform lays out memory. The VIP keeps its stack
+    /// and display at the top of its 4K, from 0xEA0; XO-CHIP has 64K.
+    pub fn memory_map(&self) -> MemoryMap {
+        let mut map = MemoryMap::new();
+        match *self {
+            Chip8 | Chip8X => map.reserved = 0x160,
+            SuperChip => {},
+            XoChip => map.size = 0x10000,
+        }
+        map
+    }
+
+    /// The font set its interpreters draw digits with.
+    pub fn font_preset(&self) -> &'static str {
+        match *self {
+            Chip8 | Chip8X => "vip",
+            SuperChip => "schip",
+            XoChip => "octo",
+        }
+    }
+
+    /// The largest program this platform can load.
+    pub fn max_rom_size(&self) -> uint {
+        self.memory_map().max_program_size()
+    }
+}
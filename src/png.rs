@@ -0,0 +1,111 @@
+use std::io::{IoResult, MemWriter};
+
+use crc32::Crc32;
+use video::Image;
+
+static SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+static MAX_STORED_BLOCK: uint = 0xffff;
+
+/// Write `img` as a PNG, 8-bit RGBA. The image data isn't
+/// compressed (it goes in stored deflate blocks), which keeps this
+/// simple; CHIP-8 screens are small anyway.
+pub fn write_png(w: &mut Writer, img: &Image) -> IoResult<()> {
+    try!(w.write(SIGNATURE));
+
+    let mut ihdr = MemWriter::new();
+    try!(ihdr.write_be_u32(img.width as u32));
+    try!(ihdr.write_be_u32(img.height as u32));
+    try!(ihdr.write([8,    // bits per channel
+                     6,    // RGBA
+                     0,    // deflate
+                     0,    // standard filtering
+                     0])); // not interlaced
+    try!(write_chunk(w, b"IHDR", ihdr.get_ref()));
+
+    // Each row starts with its filter type, which is always none.
+    let stride = img.width * 4;
+    let mut raw = Vec::with_capacity((stride + 1) * img.height);
+    for row in img.data.as_slice().chunks(stride) {
+        raw.push(0);
+        raw.push_all(row);
+    }
+    try!(write_chunk(w, b"IDAT", zlib_stored(raw.as_slice()).as_slice()));
+
+    write_chunk(w, b"IEND", b"")
+}
+
+fn write_chunk(w: &mut Writer, kind: &[u8], data: &[u8]) -> IoResult<()> {
+    try!(w.write_be_u32(data.len() as u32));
+    try!(w.write(kind));
+    try!(w.write(data));
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    w.write_be_u32(crc.finish())
+}
+
+/// `data` in a zlib stream, without compression.
+pub fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if data.is_empty() {
+        out.push_all([1, 0, 0, 0xff, 0xff]);
+    }
+    loop {
+        let block = match blocks.next() {
+            Some(block) => block,
+            None => break
+        };
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(if last { 1 } else { 0 });
+        out.push_all([len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        out.push_all(block);
+    }
+    let adler = adler32(data);
+    out.push_all([(adler >> 24) as u8, (adler >> 16) as u8, (adler >> 8) as u8, adler as u8]);
+    out
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod test {
+    use super::{adler32, write_png, zlib_stored};
+    use std::io::MemWriter;
+    use video::Image;
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        let z = zlib_stored(b"hi");
+        assert_eq!(z, vec![0x78, 0x01, 1, 2, 0, 0xfd, 0xff, b'h', b'i', 0x01, 0x3b, 0x00, 0xd2]);
+        // Too long for one block.
+        let big = Vec::from_elem(70000, 7u8);
+        assert_eq!(zlib_stored(big.as_slice()).len(), 2 + 5 + 65535 + 5 + (70000 - 65535) + 4);
+    }
+
+    #[test]
+    fn test_write_png() {
+        let img = Image::new(2, 1, [0xff, 0, 0, 0xff]);
+        let mut w = MemWriter::new();
+        write_png(&mut w, &img).unwrap();
+        let png = w.unwrap();
+        assert_eq!(png.slice_to(8), b"\x89PNG\r\n\x1a\n");
+        assert_eq!(png.slice(12, 16), b"IHDR");
+        assert_eq!(png.slice(16, 24), [0, 0, 0, 2, 0, 0, 0, 1].as_slice());
+        assert_eq!(png.slice_from(png.len() - 12),
+                   [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82].as_slice());
+    }
+}
@@ -0,0 +1,219 @@
+use std::default::Default;
+
+/// When `FX0A` (wait for a key) completes.
+#[deriving(Clone, PartialEq, Show)]
+pub enum KeyWaitMode {
+    CompleteOnRelease, // COSMAC VIP: once the pressed key is let go
+    CompleteOnPress,   // as soon as a key goes down
+}
+
+/// Which register `8XY6` and `8XYE` shift.
+#[deriving(Clone, PartialEq, Show)]
+pub enum ShiftMode {
+    ShiftVy, // COSMAC VIP: VX = VY shifted
+    ShiftVx, // SCHIP: VX shifted in place, VY ignored
+}
+
+/// What `FX55` and `FX65` do to I.
+#[deriving(Clone, PartialEq, Show)]
+pub enum LoadStoreMode {
+    IncrementIndex, // COSMAC VIP: I ends up past the last register
+    KeepIndex,      // SCHIP: I is left alone
+}
+
+/// What happens when an instruction reaches past the end of memory.
+#[deriving(Clone, PartialEq, Show)]
+pub enum BoundsMode {
+    WrapAddress,  // COSMAC VIP: addresses wrap around at the memory size
+    MaskAddress,  // I itself is kept to 12 bits (16 with 64K), as some interpreters do
+    FaultAddress, // stop with an error, to catch bugs
+}
+
+/// Where `2NNN` and `00EE` keep return addresses.
+#[deriving(Clone, PartialEq, Show)]
+pub enum StackMode {
+    SeparateStack, // out of the program's reach
+    MemoryStack,   // COSMAC VIP: in memory, growing down from 0xED0
+}
+
+/// What happens on an instruction fries can't run: one no
+/// interpreter defines, or one from an extension it doesn't emulate.
+#[deriving(Clone, PartialEq, Show)]
+pub enum IllegalMode {
+    HaltOnIllegal, // stop with an error
+    IgnoreIllegal, // carry on as if it were a NOP
+    TrapIllegal,   // pause, so it can be looked at, then skip it
+    VipIllegal,    // as the VIP: `5XYN` and `9XYN` ignore N, the rest are NOPs
+}
+
+/// Whether `FX1E` reports I running past 0xFFF.
+#[deriving(Clone, PartialEq, Show)]
+pub enum IndexOverflowMode {
+    IgnoreIndexOverflow, // COSMAC VIP: VF is left alone
+    FlagIndexOverflow,   // Amiga interpreter: VF = 1 when I passes 0xFFF, 0 otherwise
+}
+
+/// Behaviours that differ between CHIP-8 interpreters.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Quirks {
+    pub key_wait: KeyWaitMode,
+    pub shift: ShiftMode,
+    pub load_store: LoadStoreMode,
+    pub memory: BoundsMode,
+    pub stack_depth: Option<uint>, // calls deep before overflow: 12 on the VIP, or no limit
+    pub stack: StackMode,
+    pub illegal: IllegalMode,
+    pub index_overflow: IndexOverflowMode,
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            key_wait: CompleteOnRelease,
+            shift: ShiftVy,
+            load_store: IncrementIndex,
+            memory: WrapAddress,
+            stack_depth: None,
+            stack: SeparateStack,
+            illegal: HaltOnIllegal,
+            index_overflow: IgnoreIndexOverflow,
+        }
+    }
+}
+
+impl Quirks {
+    /// Look up the quirks of a well-known interpreter: `vip`,
+    /// `schip` or `octo`.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        let quirks = match name {
+            "vip" => Quirks { stack_depth: Some(12), ..Default::default() },
+            "octo" => Default::default(),
+            "schip" => Quirks {
+                key_wait: CompleteOnPress,
+                shift: ShiftVx,
+                load_store: KeepIndex,
+                memory: WrapAddress,
+                stack_depth: Some(16),
+                stack: SeparateStack,
+                illegal: HaltOnIllegal,
+                index_overflow: IgnoreIndexOverflow,
+            },
+            _ => return None
+        };
+        Some(quirks)
+    }
+
+    /// Apply one quirk setting: a preset name, which replaces every
+    /// quirk, or a single `quirk=value`.
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        let spec = spec.trim();
+        let (name, value) = match spec.find('=') {
+            Some(eq) => (spec.slice_to(eq).trim(), spec.slice_from(eq + 1).trim()),
+            None => {
+                *self = match Quirks::preset(spec) {
+                    Some(q) => q,
+                    None => return Err(format!("unknown quirks preset `{}`", spec))
+                };
+                return Ok(());
+            }
+        };
+        let bad_value = |expected: &str| {
+            Err(format!("quirk `{}`: expected {}, found `{}`", name, expected, value))
+        };
+        match name {
+            "key_wait" => self.key_wait = match value {
+                "release" => CompleteOnRelease,
+                "press" => CompleteOnPress,
+                _ => return bad_value("`release` or `press`")
+            },
+            "shift" => self.shift = match value {
+                "vy" => ShiftVy,
+                "vx" => ShiftVx,
+                _ => return bad_value("`vy` or `vx`")
+            },
+            "load_store" => self.load_store = match value {
+                "increment" => IncrementIndex,
+                "keep" => KeepIndex,
+                _ => return bad_value("`increment` or `keep`")
+            },
+            "memory" => self.memory = match value {
+                "wrap" => WrapAddress,
+                "mask" => MaskAddress,
+                "error" => FaultAddress,
+                _ => return bad_value("`wrap`, `mask` or `error`")
+            },
+            "stack_depth" => self.stack_depth = match value {
+                "unlimited" => None,
+                _ => match from_str::<uint>(value) {
+                    Some(n) if n > 0 => Some(n),
+                    _ => return bad_value("a number of calls or `unlimited`")
+                }
+            },
+            "stack" => self.stack = match value {
+                "separate" => SeparateStack,
+                "memory" => MemoryStack,
+                _ => return bad_value("`separate` or `memory`")
+            },
+            "illegal" => self.illegal = match value {
+                "error" => HaltOnIllegal,
+                "nop" => IgnoreIllegal,
+                "trap" => TrapIllegal,
+                "vip" => VipIllegal,
+                _ => return bad_value("`error`, `nop`, `trap` or `vip`")
+            },
+            "index_overflow" => self.index_overflow = match value {
+                "ignore" => IgnoreIndexOverflow,
+                "vf" => FlagIndexOverflow,
+                _ => return bad_value("`ignore` or `vf`")
+            },
+            _ => return Err(format!("unknown quirk `{}`", name))
+        }
+        Ok(())
+    }
+
+    /// Apply a comma-separated list of quirk settings, in order.
+    pub fn apply_all(&mut self, specs: &str) -> Result<(), String> {
+        for spec in specs.split(',') {
+            try!(self.apply(spec));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Quirks, CompleteOnPress, CompleteOnRelease, ShiftVx, ShiftVy, KeepIndex,
+                FaultAddress, MemoryStack, VipIllegal, IgnoreIndexOverflow, FlagIndexOverflow};
+    use std::default::Default;
+
+    #[test]
+    fn test_apply() {
+        let mut q: Quirks = Default::default();
+        q.apply_all("schip").unwrap();
+        assert_eq!(q.key_wait, CompleteOnPress);
+        assert_eq!(q.load_store, KeepIndex);
+        q.apply_all("schip, key_wait=release, shift=vy").unwrap();
+        assert_eq!(q.key_wait, CompleteOnRelease);
+        assert_eq!(q.shift, ShiftVy);
+        q.apply("shift=vx").unwrap();
+        assert_eq!(q.shift, ShiftVx);
+        q.apply("memory=error").unwrap();
+        assert_eq!(q.memory, FaultAddress);
+        assert_eq!(q.stack_depth, Some(16));
+        q.apply_all("stack_depth=unlimited, stack=memory").unwrap();
+        assert_eq!((q.stack_depth, q.stack), (None, MemoryStack));
+        assert_eq!(Quirks::preset("vip").unwrap().stack_depth, Some(12));
+        assert!(q.apply("stack_depth=0").is_err());
+        q.apply("illegal=vip").unwrap();
+        assert_eq!(q.illegal, VipIllegal);
+        for name in ["vip", "octo", "schip"].iter() {
+            assert_eq!(Quirks::preset(*name).unwrap().index_overflow, IgnoreIndexOverflow);
+        }
+        q.apply("index_overflow=vf").unwrap();
+        assert_eq!(q.index_overflow, FlagIndexOverflow);
+        assert!(q.apply("index_overflow=maybe").is_err());
+        assert!(q.apply("key_wait=sometimes").is_err());
+        assert!(q.apply("turbo=on").is_err());
+        assert!(q.apply("chip48").is_err());
+    }
+}
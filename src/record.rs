@@ -0,0 +1,154 @@
+use std::io::IoResult;
+
+use gif::GifWriter;
+use video::Image;
+
+static FPS: uint = 60;
+
+/// Video file formats for recording.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Format {
+    Gif,     // animated GIF
+    Y4m,     // YUV4MPEG2, 4:4:4, which most encoders read
+    RawRgba, // bare RGBA frames, one after another
+}
+
+impl Format {
+    /// The format for a file name: `.gif`, `.y4m`, or `.rgba`.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension_str() {
+            Some("gif") => Some(Gif),
+            Some("y4m") => Some(Y4m),
+            Some("rgba") | Some("raw") => Some(RawRgba),
+            _ => None
+        }
+    }
+}
+
+enum Output<W> {
+    GifOut(GifWriter<W>),
+    Stream(W),
+}
+
+/// Records frames at 60 fps.
+pub struct VideoRecorder<W> {
+    out: Output<W>,
+    format: Format,
+    width: uint,
+    height: uint,
+    frames: uint,
+}
+
+impl<W: Writer> VideoRecorder<W> {
+    pub fn new(mut w: W, format: Format, width: uint,
+               height: uint) -> IoResult<VideoRecorder<W>> {
+        let out = match format {
+            Gif => GifOut(try!(GifWriter::new(w, width, height))),
+            Y4m => {
+                try!(write!(w, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n", width, height, FPS));
+                Stream(w)
+            },
+            RawRgba => Stream(w)
+        };
+        Ok(VideoRecorder {
+            out: out,
+            format: format,
+            width: width,
+            height: height,
+            frames: 0,
+        })
+    }
+
+    pub fn frame(&mut self, img: &Image) -> IoResult<()> {
+        assert!(img.width == self.width && img.height == self.height);
+        let delay = gif_delay(self.frames);
+        self.frames += 1;
+
+        match (&mut self.out, &self.format) {
+            (&GifOut(ref mut gif), _) => gif.frame(img, delay),
+            (&Stream(ref mut w), &Y4m) => {
+                try!(w.write(b"FRAME\n"));
+                let planes = yuv444(img);
+                for plane in planes.iter() {
+                    try!(w.write(plane.as_slice()));
+                }
+                Ok(())
+            },
+            (&Stream(ref mut w), _) => w.write(img.data.as_slice()),
+        }
+    }
+
+    pub fn frames(&self) -> uint {
+        self.frames
+    }
+
+    pub fn finish(self) -> IoResult<W> {
+        match self.out {
+            GifOut(gif) => gif.finish(),
+            Stream(w) => Ok(w)
+        }
+    }
+}
+
+/// How long GIF frame `n` is shown. GIF delays are in hundredths of
+/// a second, so 60 fps comes out as 1, 2, 2, 1, 2, 2, ... Some viewers
+/// slow down 1s.
+fn gif_delay(n: uint) -> u16 {
+    ((n + 1) * 100 / FPS - n * 100 / FPS) as u16
+}
+
+/// The Y, Cb and Cr planes of `img`, in BT.601 studio range.
+fn yuv444(img: &Image) -> [Vec<u8>, ..3] {
+    let n = img.width * img.height;
+    let (mut y, mut u, mut v) = (Vec::with_capacity(n), Vec::with_capacity(n),
+                                 Vec::with_capacity(n));
+    for px in img.data.as_slice().chunks(4) {
+        let (r, g, b) = (px[0] as f64, px[1] as f64, px[2] as f64);
+        y.push((16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0).round() as u8);
+        u.push((128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0).round() as u8);
+        v.push((128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0).round() as u8);
+    }
+    [y, u, v]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{VideoRecorder, Format, Gif, Y4m, RawRgba, gif_delay};
+    use std::io::MemWriter;
+    use video::Image;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Format::from_path(&Path::new("a/b.gif")), Some(Gif));
+        assert_eq!(Format::from_path(&Path::new("b.y4m")), Some(Y4m));
+        assert_eq!(Format::from_path(&Path::new("b.rgba")), Some(RawRgba));
+        assert_eq!(Format::from_path(&Path::new("b.mp4")), None);
+    }
+
+    #[test]
+    fn test_gif_delay() {
+        assert_eq!([gif_delay(0), gif_delay(1), gif_delay(2)], [1, 2, 2]);
+        // Each second adds up to exactly 100.
+        assert_eq!(range(0u, 60).map(|n| gif_delay(n)).fold(0, |a, b| a + b), 100);
+    }
+
+    #[test]
+    fn test_y4m() {
+        let mut rec = VideoRecorder::new(MemWriter::new(), Y4m, 2, 1).unwrap();
+        rec.frame(&Image::new(2, 1, [0xff, 0xff, 0xff, 0xff])).unwrap();
+        rec.frame(&Image::new(2, 1, [0, 0, 0, 0xff])).unwrap();
+        assert_eq!(rec.frames(), 2);
+        let data = rec.finish().unwrap().unwrap();
+        let header = b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C444\n";
+        assert_eq!(data.slice_to(header.len()), header);
+        let frames = data.slice_from(header.len());
+        assert_eq!(frames, b"FRAME\n\xeb\xeb\x80\x80\x80\x80FRAME\n\x10\x10\x80\x80\x80\x80");
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut rec = VideoRecorder::new(MemWriter::new(), RawRgba, 1, 1).unwrap();
+        rec.frame(&Image::new(1, 1, [1, 2, 3, 4])).unwrap();
+        assert_eq!(rec.finish().unwrap().unwrap(), vec![1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,287 @@
+//! A database of known ROMs, keyed by SHA-1, in the format of the
+//! community CHIP-8 database's `programs.json`: a list of programs,
+//! each with the ROMs that make it up.
+//!
+//!     [{"title": "Tetris", "authors": ["Fran Dachille"],
+//!       "roms": {"<sha1>": {"platforms": ["originalChip8"], "tickrate": 15,
+//!                           "quirkyPlatforms": {"originalChip8": {"shift": true}},
+//!                           "keys": {"left": 5, "right": 6, "a": 4},
+//!                           "colors": {"pixels": ["#000000", "#ffffff"]}}}}]
+//!
+//! Anything the database says that fries can't use is noted rather
+//! than treated as an error, so newer copies of it keep working.
+
+use std::ascii::StrAsciiExt;
+use std::collections::HashMap;
+use std::io::File;
+use std::os;
+
+use serialize::json;
+use serialize::json::Json;
+
+use config::Settings;
+use palette;
+use platform::{Platform, Chip8, Chip8X, SuperChip, XoChip};
+use timing::PerFrame;
+
+/// What the database knows about one ROM.
+#[deriving(Clone, Show)]
+pub struct Entry {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub platform: Option<String>, // the database's name for it, like `superchip`
+    pub settings: Settings,       // platform, quirks, speed, keys, colors, load address
+    pub ignored: Vec<String>,     // what it asks for that fries can't honour
+}
+
+impl Entry {
+    /// `Title by Author, Author (year)`.
+    pub fn describe(&self) -> String {
+        let mut s = self.title.clone();
+        if !self.authors.is_empty() {
+            s.push_str(format!(" by {}", self.authors.connect(", ")).as_slice());
+        }
+        match self.release {
+            Some(ref release) => s.push_str(format!(" ({})", release).as_slice()),
+            None => {}
+        }
+        s
+    }
+}
+
+pub struct Database {
+    roms: HashMap<String, Entry>,
+}
+
+impl Database {
+    /// Where the database lives if not given: `programs.json` in
+    /// `$XDG_DATA_HOME/fries`, by default under `~/.local/share`.
+    pub fn default_path() -> Option<Path> {
+        let base = match os::getenv("XDG_DATA_HOME") {
+            Some(dir) => Path::new(dir),
+            None => match os::getenv("HOME") {
+                Some(home) => Path::new(home).join(".local").join("share"),
+                None => return None
+            }
+        };
+        Some(base.join("fries").join("programs.json"))
+    }
+
+    pub fn load(path: &Path) -> Result<Database, String> {
+        let src = try!(File::open(path).read_to_string().map_err(|e| {
+            format!("Error reading ROM database {}: {}", path.display(), e.desc)
+        }));
+        Database::parse(src.as_slice()).map_err(|e| {
+            format!("Error in ROM database {}: {}", path.display(), e)
+        })
+    }
+
+    pub fn parse(src: &str) -> Result<Database, String> {
+        let root = try!(json::from_str(src).map_err(|e| format!("{}", e)));
+        let programs = match root.as_list() {
+            Some(programs) => programs,
+            None => return Err("expected a list of programs".to_string())
+        };
+        let mut db = Database { roms: HashMap::new() };
+        for (i, program) in programs.iter().enumerate() {
+            let title = match field(program, "title").and_then(|t| t.as_string()) {
+                Some(title) => title.to_string(),
+                None => return Err(format!("program {} has no title", i))
+            };
+            let authors = match field(program, "authors").and_then(|a| a.as_list()) {
+                Some(a) => a.iter().filter_map(|a| a.as_string()).map(|a| a.to_string()).collect(),
+                None => vec![]
+            };
+            let release = field(program, "release").and_then(|r| r.as_string())
+                                                   .map(|r| r.to_string());
+            let roms = match field(program, "roms").and_then(|r| r.as_object()) {
+                Some(roms) => roms,
+                None => continue
+            };
+            for (hash, rom) in roms.iter() {
+                let mut entry = Entry {
+                    title: title.clone(),
+                    authors: authors.clone(),
+                    release: release.clone(),
+                    platform: None,
+                    settings: Settings::new(),
+                    ignored: vec![],
+                };
+                read_rom(rom, &mut entry);
+                db.roms.insert(hash.as_slice().to_ascii_lower(), entry);
+            }
+        }
+        Ok(db)
+    }
+
+    /// The entry for a ROM with the given SHA-1 (in hex).
+    pub fn find<'a>(&'a self, hash: &str) -> Option<&'a Entry> {
+        self.roms.find(&hash.to_ascii_lower())
+    }
+
+    pub fn len(&self) -> uint {
+        self.roms.len()
+    }
+}
+
+fn field<'a>(obj: &'a Json, key: &str) -> Option<&'a Json> {
+    obj.find(&key.to_string())
+}
+
+/// The database's platforms that fries emulates. The first a ROM
+/// lists is the one it's best played on.
+fn platform(id: &str) -> Option<Platform> {
+    match id {
+        "originalChip8" | "hybridVIP" | "modernChip8" => Some(Chip8),
+        "chip8x" => Some(Chip8X),
+        "chip48" | "superchip1" | "superchip" => Some(SuperChip),
+        "xochip" => Some(XoChip),
+        _ => None
+    }
+}
+
+/// Host keys for the database's actions, added to whatever the
+/// keymap already binds.
+fn action_key(action: &str) -> Option<&'static str> {
+    match action {
+        "up" => Some("Up"),
+        "down" => Some("Down"),
+        "left" => Some("Left"),
+        "right" => Some("Right"),
+        "a" => Some("Space"),
+        "b" => Some("Return"),
+        _ => None
+    }
+}
+
+fn read_rom(rom: &Json, entry: &mut Entry) {
+    let platforms: Vec<&str> = match field(rom, "platforms").and_then(|p| p.as_list()) {
+        Some(p) => p.iter().filter_map(|p| p.as_string()).collect(),
+        None => vec![]
+    };
+    match platforms.iter().map(|&id| id).find(|&id| platform(id).is_some()) {
+        Some(id) => {
+            entry.platform = Some(id.to_string());
+            entry.settings.platform = platform(id);
+            let quirky = field(rom, "quirkyPlatforms").and_then(|q| field(q, id))
+                                                      .and_then(|q| q.as_object());
+            match quirky {
+                Some(quirks) => {
+                    for (name, val) in quirks.iter() {
+                        let on = val.as_boolean().unwrap_or(false);
+                        match name.as_slice() {
+                            "shift" => {
+                                let spec = if on { "shift=vx" } else { "shift=vy" };
+                                entry.settings.quirks.push(spec.to_string());
+                            },
+                            "memoryLeaveIUnchanged" => {
+                                let spec = if on {
+                                    "load_store=keep"
+                                } else {
+                                    "load_store=increment"
+                                };
+                                entry.settings.quirks.push(spec.to_string());
+                            },
+                            _ if on => entry.ignored.push(format!("{} quirk", name)),
+                            _ => {}
+                        }
+                    }
+                },
+                None => {}
+            }
+        },
+        None => match platforms.as_slice().head() {
+            Some(id) => entry.ignored.push(format!("platform {}", id)),
+            None => {}
+        }
+    }
+
+    match field(rom, "tickrate").and_then(|t| t.as_f64()) {
+        Some(n) if n >= 1.0 => entry.settings.speed = Some(PerFrame(n as uint)),
+        _ => {}
+    }
+    match field(rom, "startAddress").and_then(|a| a.as_f64()) {
+        Some(addr) if addr >= 0.0 && addr < 0x10000 as f64 => {
+            entry.settings.load_address = Some(addr as u16);
+        },
+        _ => {}
+    }
+
+    match field(rom, "keys").and_then(|k| k.as_object()) {
+        Some(keys) => {
+            for (action, code) in keys.iter() {
+                match (action_key(action.as_slice()), code.as_f64()) {
+                    (Some(key), Some(code)) if code >= 0.0 && code < 16.0 => {
+                        entry.settings.keys.push(format!("{:X} += {}", code as uint, key));
+                    },
+                    _ => {} // second player's keys and the like
+                }
+            }
+        },
+        None => {}
+    }
+
+    match field(rom, "colors").and_then(|c| field(c, "pixels")).and_then(|p| p.as_list()) {
+        Some(pixels) => {
+            let colors: Vec<&str> = pixels.iter().filter_map(|c| c.as_string()).collect();
+            if !colors.is_empty() && colors.len() <= palette::MAX_COLORS &&
+               colors.iter().all(|c| palette::parse_color(*c).is_some()) {
+                entry.settings.palette = Some(colors.connect(","));
+            }
+        },
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Database;
+    use platform::SuperChip;
+    use quirks::{ShiftVx, KeepIndex};
+    use timing::PerFrame;
+
+    static PROGRAMS: &'static str = r#"[
+        {"title": "Blinky", "authors": ["Hans Christian Egeberg"], "release": "1991",
+         "roms": {"ABCDEF0123456789ABCDEF0123456789ABCDEF01": {
+            "platforms": ["megachip8", "superchip", "originalChip8"],
+            "quirkyPlatforms": {"superchip": {"shift": true, "memoryLeaveIUnchanged": true,
+                                              "wrap": false, "jump": true}},
+            "tickrate": 20,
+            "keys": {"up": 3, "down": 6, "a": 15, "player2Up": 1},
+            "colors": {"pixels": ["#000000", "#ff00ff"], "buzzer": "#990099"}}}},
+        {"title": "Untitled", "roms": {"0000000000000000000000000000000000000000": {
+            "platforms": ["megachip8"], "startAddress": 1536}}}
+    ]"#;
+
+    #[test]
+    fn test_parse() {
+        let db = Database::parse(PROGRAMS).unwrap();
+        assert_eq!(db.len(), 2);
+        let e = db.find("abcdef0123456789abcdef0123456789abcdef01").unwrap();
+        assert_eq!(e.describe(), "Blinky by Hans Christian Egeberg (1991)".to_string());
+        assert_eq!(e.platform, Some("superchip".to_string()));
+        assert_eq!(e.settings.platform, Some(SuperChip));
+        assert_eq!(e.settings.speed, Some(PerFrame(20)));
+        assert_eq!(e.settings.palette, Some("#000000,#ff00ff".to_string()));
+        assert_eq!(e.settings.keys, vec!["F += Space".to_string(), "6 += Down".to_string(),
+                                         "3 += Up".to_string()]);
+        let quirks = e.settings.quirks().unwrap();
+        assert_eq!(quirks.shift, ShiftVx);
+        assert_eq!(quirks.load_store, KeepIndex);
+        assert_eq!(e.ignored, vec!["jump quirk".to_string()]);
+
+        let e = db.find("0000000000000000000000000000000000000000").unwrap();
+        assert_eq!(e.settings.platform, None);
+        assert_eq!(e.settings.load_address, Some(0x600));
+        assert_eq!(e.ignored, vec!["platform megachip8".to_string()]);
+        assert!(db.find("1111111111111111111111111111111111111111").is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Database::parse("{}").is_err());
+        assert!(Database::parse("[{\"roms\": {}}]").is_err());
+        assert!(Database::parse("[").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+pub static DIGEST_SIZE: uint = 20;
+
+pub fn sha1(data: &[u8]) -> [u8, ..DIGEST_SIZE] {
+    let mut h: [u32, ..5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut msg = data.to_vec();
+    let bit_len = data.len() as u64 * 8;
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    for i in range(0u, 8) {
+        msg.push((bit_len >> (56 - i * 8)) as u8);
+    }
+
+    for chunk in msg.as_slice().chunks(64) {
+        let mut w = [0u32, ..80];
+        for i in range(0u, 16) {
+            w[i] = chunk[i * 4] as u32 << 24 | chunk[i * 4 + 1] as u32 << 16 |
+                   chunk[i * 4 + 2] as u32 << 8 | chunk[i * 4 + 3] as u32;
+        }
+        for i in range(16u, 80) {
+            w[i] = rotl(w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16], 1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for i in range(0u, 80) {
+            let (f, k) = match i {
+                0..19  => ((b & c) | (!b & d), 0x5a827999),
+                20..39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _      => (b ^ c ^ d, 0xca62c1d6)
+            };
+            let t = rotl(a, 5) + f + e + k + w[i];
+            e = d;
+            d = c;
+            c = rotl(b, 30);
+            b = a;
+            a = t;
+        }
+        h[0] += a;
+        h[1] += b;
+        h[2] += c;
+        h[3] += d;
+        h[4] += e;
+    }
+
+    let mut out = [0u8, ..DIGEST_SIZE];
+    for (i, word) in h.iter().enumerate() {
+        for j in range(0u, 4) {
+            out[i * 4 + j] = (*word >> (24 - j * 8)) as u8;
+        }
+    }
+    out
+}
+
+#[inline]
+fn rotl(x: u32, n: uint) -> u32 {
+    (x << n) | (x >> (32 - n))
+}
+
+/// Format bytes as lowercase hex.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes.iter() {
+        s.push_str(format!("{:02x}", *b).as_slice());
+    }
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sha1, to_hex};
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(to_hex(sha1(b"").as_slice()).as_slice(),
+                   "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(to_hex(sha1(b"abc").as_slice()).as_slice(),
+                   "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(to_hex(sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")
+                              .as_slice()).as_slice(),
+                   "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+}
@@ -0,0 +1,190 @@
+pub static TIMER_HZ: uint = 60;
+static NS_PER_SEC: f64 = 1000000000.0;
+static MAX_CATCH_UP: uint = 6; // ticks of real time per update before giving up
+
+/// How fast the CPU runs relative to the 60 Hz timers.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Speed {
+    PerFrame(uint),  // instructions per 60 Hz tick
+    PerSecond(uint), // instructions per emulated second
+}
+
+/// Paces emulation against real time. The 60 Hz timer tick is the
+/// unit of emulated time: callers feed in the real time, get back
+/// how many ticks are due, and run `cycles_for_tick` instructions
+/// for each. Leftover time carries over between updates, so ticks
+/// don't drift however irregularly `update` is called.
+pub struct Timing {
+    speed: Speed,
+    rate: f64,         // emulated seconds per real second
+    paused: bool,
+    advance: uint,     // ticks requested by frame advance while paused
+    last: Option<u64>, // real time of the previous update, in ns
+    acc: f64,          // emulated ns not yet used up by a tick
+    cycle_acc: uint,   // PerSecond instructions owed to the next tick, times 60
+}
+
+impl Timing {
+    pub fn new(speed: Speed) -> Timing {
+        Timing {
+            speed: speed,
+            rate: 1.0,
+            paused: false,
+            advance: 0,
+            last: None,
+            acc: 0.0,
+            cycle_acc: 0,
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.cycle_acc = 0;
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Set the emulation speed relative to real time: 1.0 is
+    /// normal, above is fast-forward and below is slow motion.
+    pub fn set_rate(&mut self, rate: f64) {
+        assert!(rate > 0.0);
+        self.rate = rate;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.advance = 0;
+    }
+
+    /// Pause, and run exactly one more tick on the next update.
+    pub fn frame_advance(&mut self) {
+        self.paused = true;
+        self.advance += 1;
+    }
+
+    /// Return the number of ticks due at real time `now` (in ns).
+    pub fn update(&mut self, now: u64) -> uint {
+        let elapsed = match self.last {
+            Some(last) if now > last => now - last,
+            _ => 0
+        };
+        self.last = Some(now);
+
+        if self.paused {
+            let ticks = self.advance;
+            self.advance = 0;
+            return ticks;
+        }
+
+        let period = NS_PER_SEC / TIMER_HZ as f64;
+        if elapsed as f64 > MAX_CATCH_UP as f64 * period {
+            // We fell far behind (the host stalled, or can't keep
+            // up); drop the backlog instead of racing to catch up.
+            // The limit is on real time, so fast-forward still runs
+            // faster after a stall.
+            self.acc = 0.0;
+            return (MAX_CATCH_UP as f64 * self.rate) as uint;
+        }
+        self.acc += elapsed as f64 * self.rate;
+        let ticks = (self.acc / period) as uint;
+        self.acc -= ticks as f64 * period;
+        ticks
+    }
+
+    /// Return how many instructions to run during the next tick.
+    pub fn cycles_for_tick(&mut self) -> uint {
+        match self.speed {
+            PerFrame(n) => n,
+            PerSecond(n) => {
+                self.cycle_acc += n;
+                let cycles = self.cycle_acc / TIMER_HZ;
+                self.cycle_acc %= TIMER_HZ;
+                cycles
+            }
+        }
+    }
+
+    /// Return how much real time (in ns) one tick takes at the
+    /// current rate.
+    pub fn tick_duration(&self) -> u64 {
+        (NS_PER_SEC / TIMER_HZ as f64 / self.rate) as u64
+    }
+
+    /// Return the real time (in ns) until the next tick is due.
+    pub fn until_next_tick(&self) -> u64 {
+        let period = NS_PER_SEC / TIMER_HZ as f64;
+        if self.paused {
+            return period as u64;
+        }
+        // Round up, so sleeping this long always makes a tick due.
+        ((period - self.acc) / self.rate).ceil() as u64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Timing, PerFrame, PerSecond};
+
+    static MS: u64 = 1000000;
+
+    #[test]
+    fn test_no_drift() {
+        let mut t = Timing::new(PerFrame(10));
+        let mut now = 0;
+        let mut ticks = t.update(now);
+        // a second (and change) of irregular 7 ms updates
+        while now + 7 * MS <= 1010 * MS {
+            now += 7 * MS;
+            ticks += t.update(now);
+        }
+        ticks += t.update(1010 * MS);
+        assert_eq!(ticks, 60);
+    }
+
+    #[test]
+    fn test_catch_up_limit() {
+        let mut t = Timing::new(PerFrame(10));
+        t.update(0);
+        assert_eq!(t.update(10000 * MS), super::MAX_CATCH_UP);
+        assert_eq!(t.update(10001 * MS), 0);
+    }
+
+    #[test]
+    fn test_rate() {
+        let mut t = Timing::new(PerFrame(10));
+        t.set_rate(4.0);
+        t.update(0);
+        assert_eq!(t.update(51 * MS), 12);
+        assert_eq!(t.update(10051 * MS), 24);
+    }
+
+    #[test]
+    fn test_pause_and_advance() {
+        let mut t = Timing::new(PerFrame(10));
+        t.update(0);
+        t.set_paused(true);
+        assert_eq!(t.update(100 * MS), 0);
+        t.frame_advance();
+        assert_eq!(t.update(101 * MS), 1);
+        assert_eq!(t.update(200 * MS), 0);
+        t.set_paused(false);
+        assert_eq!(t.update(217 * MS), 1);
+    }
+
+    #[test]
+    fn test_instructions_per_second() {
+        let mut t = Timing::new(PerSecond(500));
+        let total = range(0u, 60).fold(0, |acc, _| acc + t.cycles_for_tick());
+        assert_eq!(total, 500);
+    }
+}
@@ -0,0 +1,451 @@
+use std::cmp::{max, min};
+
+use display;
+use display::Display;
+use palette::{Color, Palette};
+
+/// An RGBA image, row by row.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Image {
+    pub width: uint,
+    pub height: uint,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: uint, height: uint, fill: Color) -> Image {
+        let mut data = Vec::with_capacity(width * height * 4);
+        for _ in range(0, width * height) {
+            data.push_all(fill.as_slice());
+        }
+        Image { width: width, height: height, data: data }
+    }
+
+    /// The display at one image pixel per display pixel.
+    pub fn from_display(display: &Display, palette: &Palette) -> Image {
+        let (off, on) = (palette.color(0), palette.color(1));
+        let mut data = Vec::with_capacity(display::COLS * display::ROWS * 4);
+        for &row in display.rows().iter() {
+            for bit in range(0, display::COLS).rev() {
+                let color = if (row >> bit) & 1 == 1 { on } else { off };
+                data.push_all(color.as_slice());
+            }
+        }
+        Image { width: display::COLS, height: display::ROWS, data: data }
+    }
+
+    /// The display after flicker reduction, shading each pixel
+    /// between the off and on colors by its level.
+    pub fn from_levels(levels: &[f32], palette: &Palette) -> Image {
+        assert_eq!(levels.len(), display::COLS * display::ROWS);
+        let (off, on) = (palette.color(0), palette.color(1));
+        let mut data = Vec::with_capacity(levels.len() * 4);
+        for &level in levels.iter() {
+            for c in range(0, 4) {
+                let v = off[c] as f32 + (on[c] as f32 - off[c] as f32) * level;
+                data.push(v.round() as u8);
+            }
+        }
+        Image { width: display::COLS, height: display::ROWS, data: data }
+    }
+
+    /// The pixel at (x, y), with coordinates outside the image
+    /// clamped to the edge.
+    pub fn get(&self, x: int, y: int) -> Color {
+        let x = min(max(x, 0), self.width as int - 1) as uint;
+        let y = min(max(y, 0), self.height as int - 1) as uint;
+        let i = (y * self.width + x) * 4;
+        [self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]]
+    }
+
+    pub fn set(&mut self, x: uint, y: uint, color: Color) {
+        let i = (y * self.width + x) * 4;
+        for c in range(0, 4) {
+            *self.data.get_mut(i + c) = color[c];
+        }
+    }
+
+    /// This image resized to `width` by `height`, by nearest
+    /// neighbour.
+    pub fn resize(&self, width: uint, height: uint) -> Image {
+        let mut out = Image::new(width, height, [0, 0, 0, 0xff]);
+        for y in range(0, height) {
+            for x in range(0, width) {
+                let c = self.get((x * self.width / width) as int,
+                                 (y * self.height / height) as int);
+                out.set(x, y, c);
+            }
+        }
+        out
+    }
+
+    /// Copy `src` into this image with its top left corner at (x, y).
+    pub fn blit(&mut self, src: &Image, x: uint, y: uint) {
+        for sy in range(0, min(src.height, self.height - y)) {
+            for sx in range(0, min(src.width, self.width - x)) {
+                self.set(x + sx, y + sy, src.get(sx as int, sy as int));
+            }
+        }
+    }
+
+    fn darken(&mut self, x: uint, y: uint) {
+        let c = self.get(x as int, y as int);
+        self.set(x, y, [c[0] / 2, c[1] / 2, c[2] / 2, c[3]]);
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Filter {
+    Nearest,
+    Scale2x,
+    Scale3x,
+    Epx,
+    Scanlines,
+    PixelGrid,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        let filter = match name {
+            "nearest" => Nearest,
+            "scale2x" => Scale2x,
+            "scale3x" => Scale3x,
+            "epx" => Epx,
+            "scanlines" => Scanlines,
+            "grid" => PixelGrid,
+            _ => return None
+        };
+        Some(filter)
+    }
+
+    /// Upscale `img` by this filter's own factor; the filters that
+    /// work at the final size leave it as it is.
+    fn upscale(&self, img: &Image) -> Image {
+        match *self {
+            Scale2x => scale2x(img),
+            Scale3x => scale3x(img),
+            Epx => epx(img),
+            Nearest | Scanlines | PixelGrid => img.clone()
+        }
+    }
+}
+
+/// How the display is fitted to an output size.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Scaling {
+    Integer, // whole multiples only, so every pixel is the same size
+    Aspect,  // as large as fits, keeping the 2:1 shape
+    Stretch, // fill the output
+}
+
+impl Scaling {
+    pub fn from_name(name: &str) -> Option<Scaling> {
+        match name {
+            "integer" => Some(Integer),
+            "aspect" => Some(Aspect),
+            "stretch" => Some(Stretch),
+            _ => None
+        }
+    }
+
+    /// Where a `cols` by `rows` image goes in a `width` by `height`
+    /// output: (x, y, width, height).
+    pub fn fit(&self, cols: uint, rows: uint, width: uint,
+               height: uint) -> (uint, uint, uint, uint) {
+        let (w, h) = match *self {
+            Integer => {
+                let s = max(min(width / cols, height / rows), 1);
+                (cols * s, rows * s)
+            },
+            Aspect => {
+                let s = (width as f64 / cols as f64).min(height as f64 / rows as f64);
+                (max((cols as f64 * s) as uint, 1), max((rows as f64 * s) as uint, 1))
+            },
+            Stretch => (width, height)
+        };
+        let (w, h) = (min(w, width), min(h, height));
+        ((width - w) / 2, (height - h) / 2, w, h)
+    }
+}
+
+/// Turns the display into RGBA images, for the window, screenshots
+/// and recordings alike.
+#[deriving(Clone)]
+pub struct Pipeline {
+    pub palette: Palette,
+    pub filter: Filter,
+    pub scaling: Scaling,
+}
+
+impl Pipeline {
+    pub fn new(palette: Palette) -> Pipeline {
+        Pipeline { palette: palette, filter: Nearest, scaling: Integer }
+    }
+
+    /// Render at `scale` output pixels per display pixel.
+    pub fn render_scaled(&self, display: &Display, scale: uint) -> Image {
+        self.render(display, display::COLS * scale, display::ROWS * scale)
+    }
+
+    /// Render to a `width` by `height` image, fitted according to
+    /// the scaling mode, with black borders around it if needed.
+    pub fn render(&self, display: &Display, width: uint, height: uint) -> Image {
+        self.finish(Image::from_display(display, &self.palette), width, height)
+    }
+
+    /// Render pixel levels from flicker reduction, like `render`.
+    pub fn render_levels(&self, levels: &[f32], width: uint, height: uint) -> Image {
+        self.finish(Image::from_levels(levels, &self.palette), width, height)
+    }
+
+    /// The rows of a `width` by `height` render that display rows
+    /// `first` up to `end` can reach, as a range. A row either side
+    /// is taken in too, since filters look at the neighbours.
+    pub fn output_rows(&self, first: uint, end: uint, width: uint,
+                       height: uint) -> (uint, uint) {
+        let rows = display::ROWS;
+        let (_, y, _, h) = self.scaling.fit(display::COLS, rows, width, height);
+        let (first, end) = (if first > 0 { first - 1 } else { 0 }, min(end + 1, rows));
+        (y + (first * h + rows - 1) / rows, y + (end * h + rows - 1) / rows)
+    }
+
+    fn finish(&self, src: Image, width: uint, height: uint) -> Image {
+        let (x, y, w, h) = self.scaling.fit(src.width, src.height, width, height);
+        let scaled = self.filter.upscale(&src).resize(w, h);
+
+        let mut out = if (w, h) == (width, height) {
+            scaled
+        } else {
+            let mut out = Image::new(width, height, [0, 0, 0, 0xff]);
+            out.blit(&scaled, x, y);
+            out
+        };
+
+        // Darken the last line of each display pixel, leaving it be
+        // if display pixels are too small to spare one.
+        let rows = src.height;
+        let cols = src.width;
+        let edge = |i: uint, n: uint, len: uint| (i + 1) * n / len != i * n / len;
+        match self.filter {
+            Scanlines if h >= rows * 2 => {
+                for oy in range(0, h) {
+                    if edge(oy, rows, h) {
+                        for ox in range(0, w) {
+                            out.darken(x + ox, y + oy);
+                        }
+                    }
+                }
+            },
+            PixelGrid if h >= rows * 2 && w >= cols * 2 => {
+                for oy in range(0, h) {
+                    for ox in range(0, w) {
+                        if edge(oy, rows, h) || edge(ox, cols, w) {
+                            out.darken(x + ox, y + oy);
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+        out
+    }
+}
+
+// The neighbourhood of a pixel:
+//
+//     A B C
+//     D E F
+//     G H I
+struct Neighbours {
+    a: Color, b: Color, c: Color,
+    d: Color, e: Color, f: Color,
+    g: Color, h: Color, i: Color,
+}
+
+fn neighbours(img: &Image, x: uint, y: uint) -> Neighbours {
+    let (x, y) = (x as int, y as int);
+    Neighbours {
+        a: img.get(x - 1, y - 1), b: img.get(x, y - 1), c: img.get(x + 1, y - 1),
+        d: img.get(x - 1, y),     e: img.get(x, y),     f: img.get(x + 1, y),
+        g: img.get(x - 1, y + 1), h: img.get(x, y + 1), i: img.get(x + 1, y + 1),
+    }
+}
+
+/// Apply `f`, which gives the `n` by `n` block for one source pixel
+/// in row order, to every pixel of `img`.
+fn scale_by(img: &Image, n: uint, f: |&Neighbours| -> Vec<Color>) -> Image {
+    let mut out = Image::new(img.width * n, img.height * n, [0, 0, 0, 0xff]);
+    for y in range(0, img.height) {
+        for x in range(0, img.width) {
+            let block = f(&neighbours(img, x, y));
+            for (i, &c) in block.iter().enumerate() {
+                out.set(x * n + i % n, y * n + i / n, c);
+            }
+        }
+    }
+    out
+}
+
+/// AdvMAME2x/Scale2x.
+fn scale2x(img: &Image) -> Image {
+    scale_by(img, 2, |p| {
+        let e = p.e;
+        if p.b != p.h && p.d != p.f {
+            vec![if p.d == p.b { p.d } else { e },
+                 if p.b == p.f { p.f } else { e },
+                 if p.d == p.h { p.d } else { e },
+                 if p.h == p.f { p.f } else { e }]
+        } else {
+            vec![e, e, e, e]
+        }
+    })
+}
+
+/// AdvMAME3x/Scale3x.
+fn scale3x(img: &Image) -> Image {
+    scale_by(img, 3, |p| {
+        let e = p.e;
+        if p.b != p.h && p.d != p.f {
+            vec![if p.d == p.b { p.d } else { e },
+                 if (p.d == p.b && e != p.c) || (p.b == p.f && e != p.a) { p.b } else { e },
+                 if p.b == p.f { p.f } else { e },
+                 if (p.d == p.b && e != p.g) || (p.d == p.h && e != p.a) { p.d } else { e },
+                 e,
+                 if (p.b == p.f && e != p.i) || (p.h == p.f && e != p.c) { p.f } else { e },
+                 if p.d == p.h { p.d } else { e },
+                 if (p.d == p.h && e != p.i) || (p.h == p.f && e != p.g) { p.h } else { e },
+                 if p.h == p.f { p.f } else { e }]
+        } else {
+            vec![e, e, e, e, e, e, e, e, e]
+        }
+    })
+}
+
+/// Eric's Pixel Expansion, as first written: like Scale2x, except
+/// that a pixel with three or more matching neighbours stays whole.
+fn epx(img: &Image) -> Image {
+    scale_by(img, 2, |p| {
+        let e = p.e;
+        let (b, d, f, h) = (p.b, p.d, p.f, p.h);
+        let mut out = vec![e, e, e, e];
+        if d == b && d != f && d != h { *out.get_mut(0) = d }
+        if b == f && b != d && b != h { *out.get_mut(1) = b }
+        if d == h && d != b && d != f { *out.get_mut(2) = d }
+        if h == f && h != b && h != d { *out.get_mut(3) = f }
+        let same = [b == d, b == f, b == h, d == f, d == h, f == h];
+        if same.iter().filter(|&&s| s).count() >= 3 {
+            out = vec![e, e, e, e];
+        }
+        out
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Image, Pipeline, Nearest, Scale2x, Scale3x, Epx, Scanlines, PixelGrid,
+                Integer, Aspect, Stretch};
+    use display::Display;
+    use palette::Palette;
+
+    static OFF: [u8, ..4] = [0x00, 0x00, 0x00, 0xff];
+    static ON: [u8, ..4] = [0xff, 0xff, 0xff, 0xff];
+
+    fn pipeline() -> Pipeline {
+        Pipeline::new(Palette::new([OFF, ON]))
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(Integer.fit(64, 32, 640, 320), (0, 0, 640, 320));
+        assert_eq!(Integer.fit(64, 32, 700, 400), (30, 40, 640, 320));
+        assert_eq!(Aspect.fit(64, 32, 700, 400), (0, 25, 700, 350));
+        assert_eq!(Stretch.fit(64, 32, 700, 400), (0, 0, 700, 400));
+    }
+
+    #[test]
+    fn test_output_rows() {
+        let p = pipeline();
+        assert_eq!(p.output_rows(5, 6, 640, 320), (40, 70));
+        assert_eq!(p.output_rows(0, 32, 640, 320), (0, 320));
+        assert_eq!(p.output_rows(31, 32, 700, 400), (340, 360)); // below a border
+    }
+
+    #[test]
+    fn test_nearest() {
+        let mut d = Display::new();
+        d.draw([0x80], 0, 0);
+        let img = pipeline().render_scaled(&d, 2);
+        assert_eq!((img.width, img.height), (128, 64));
+        assert_eq!(img.get(1, 1), ON);
+        assert_eq!(img.get(2, 0), OFF);
+        assert_eq!(img.get(0, 2), OFF);
+    }
+
+    #[test]
+    fn test_scale2x_rounds_diagonals() {
+        // The steps of a diagonal line fill in.
+        let mut d = Display::new();
+        d.draw([0x80, 0x40], 0, 0);
+        for filter in [Scale2x, Epx].iter() {
+            let p = Pipeline { filter: filter.clone(), ..pipeline() };
+            let img = p.render_scaled(&d, 2);
+            assert_eq!(img.get(0, 0), ON);
+            assert_eq!(img.get(2, 1), ON);
+            assert_eq!(img.get(1, 2), ON);
+            assert_eq!(img.get(3, 3), ON);
+            assert_eq!(img.get(0, 3), OFF);
+            assert_eq!(img.get(3, 0), OFF);
+        }
+    }
+
+    #[test]
+    fn test_scale3x() {
+        // Off pixels in the corner of a diagonal step take its color
+        // in their own corner.
+        let mut d = Display::new();
+        d.draw([0x40, 0x20], 0, 1);
+        let p = Pipeline { filter: Scale3x, ..pipeline() };
+        let img = p.render_scaled(&d, 3);
+        assert_eq!(img.get(4, 4), ON);
+        assert_eq!(img.get(6, 5), ON);
+        assert_eq!(img.get(7, 5), OFF);
+        assert_eq!(img.get(5, 6), ON);
+        assert_eq!(img.get(5, 7), OFF);
+    }
+
+    #[test]
+    fn test_pixel_grid() {
+        let mut d = Display::new();
+        d.draw([0x80], 0, 0);
+        let p = Pipeline { filter: PixelGrid, ..pipeline() };
+        let img = p.render_scaled(&d, 3);
+        assert_eq!(img.get(1, 1), ON);
+        assert_eq!(img.get(2, 0), [0x7f, 0x7f, 0x7f, 0xff]);
+        assert_eq!(img.get(0, 2), [0x7f, 0x7f, 0x7f, 0xff]);
+        assert_eq!(img.get(3, 0), OFF);
+    }
+
+    #[test]
+    fn test_scanlines() {
+        let mut d = Display::new();
+        d.draw([0x80], 0, 0);
+        let p = Pipeline { filter: Scanlines, ..pipeline() };
+        let img = p.render_scaled(&d, 3);
+        assert_eq!(img.get(0, 1), ON);
+        assert_eq!(img.get(0, 2), [0x7f, 0x7f, 0x7f, 0xff]);
+    }
+
+    #[test]
+    fn test_letterbox() {
+        let mut d = Display::new();
+        d.draw([0xff], 0, 0);
+        let img = Pipeline { filter: Nearest, scaling: Aspect, ..pipeline() }.render(&d, 64, 64);
+        assert_eq!(img, {
+            let mut want = Image::new(64, 64, OFF);
+            for x in range(0, 8) {
+                want.set(x, 16, ON);
+            }
+            want
+        });
+    }
+}
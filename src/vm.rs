@@ -0,0 +1,994 @@
+use std::collections::HashSet;
+use std::default::Default;
+use std::fmt;
+use std::io::{IoError, IoResult, InvalidInput};
+use std::rand::{Rng, StdRng};
+
+use audio;
+use audio::Voice;
+use cpu::Registers;
+use display;
+use display::Display;
+use font;
+use font::Font;
+use mem;
+use mem::{Memory, Rom};
+use quirks::{Quirks, CompleteOnPress, CompleteOnRelease, ShiftVx, KeepIndex, MaskAddress,
+             FaultAddress, SeparateStack, MemoryStack, HaltOnIllegal, IgnoreIllegal, TrapIllegal,
+             VipIllegal, FlagIndexOverflow};
+
+/// Progress of an `FX0A` wait for a key.
+#[deriving(Clone, PartialEq, Show)]
+enum KeyWait {
+    NotWaiting,
+    WaitingForPress(u8),          // destination register
+    WaitingForRelease(u8, uint),  // destination register, pressed key
+}
+
+/// Why the VM stopped. The PC is left at the instruction that failed.
+#[deriving(Clone, PartialEq)]
+pub enum VmError {
+    OutOfBounds(uint),    // address past the end of memory
+    StackOverflow(uint),  // calls deep
+    StackUnderflow,
+    IllegalInstruction(u16),
+    Trapped(u16),         // on an illegal instruction; `resume` skips it
+}
+
+impl fmt::Show for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OutOfBounds(addr) => write!(f, "access to {:X}, past the end of memory", addr),
+            StackOverflow(depth) => write!(f, "stack overflow, {} calls deep", depth),
+            StackUnderflow => write!(f, "return with an empty stack"),
+            IllegalInstruction(ins) => write!(f, "illegal instruction {:04X}", ins),
+            Trapped(ins) => write!(f, "trapped on illegal instruction {:04X}", ins),
+        }
+    }
+}
+
+static STATE_MAGIC: &'static [u8] = b"FRIESST2";
+
+pub struct Vm {
+    mem: Memory,
+    reg: Registers,
+    pc: u16,
+    dt: u8, // delay timer
+    st: u8, // sound timer
+    i: u16, // index register
+    ret_stack: Vec<u16>, // return stack; with the stack in memory, only its depth counts
+    display: Display,
+    rng: StdRng,
+    key_wait: KeyWait,
+    keys: u16,
+    voice: Voice,
+    quirks: Quirks,
+    cycles: u64, // instructions executed
+    error: Option<VmError>,
+    illegal: Vec<(u16, u16)>,          // address and instruction, in the order first run
+    illegal_seen: HashSet<(u16, u16)>,
+}
+
+impl Vm {
+    pub fn new(r: Rom, rng: StdRng) -> Vm {
+        let map = r.map().clone();
+        let mut mem = Memory::with_map(map.clone());
+        mem.load_rom(r);
+        mem.load_font(&Font::preset(font::DEFAULT_PRESET).unwrap());
+
+        Vm {
+            mem: mem,
+            reg: Default::default(),
+            pc: map.load_address,
+            dt: 0,
+            st: 0,
+            i: 0,
+            ret_stack: vec![],
+            display: Display::new(),
+            rng: rng,
+            key_wait: NotWaiting,
+            keys: 0,
+            voice: Voice::new(),
+            quirks: Default::default(),
+            cycles: 0,
+            error: None,
+            illegal: vec![],
+            illegal_seen: HashSet::new(),
+        }
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Replace the hex digit sprites in memory.
+    pub fn set_font(&mut self, font: &Font) {
+        self.mem.load_font(font);
+    }
+
+    fn math_op(&mut self, x: u8, y: u8, op: u8) {
+        let vx = self.reg.get(x);
+        let vy = self.reg.get(y);
+        match op {
+            0x0 => { // VX = VY
+                let dst = self.reg.get_mut(x);
+                *dst = vy;
+            },
+            0x1 => { // VX |= VY
+                let dst = self.reg.get_mut(x);
+                *dst |= vy;
+            },
+            0x2 => { // VX &= VY
+                let dst = self.reg.get_mut(x);
+                *dst &= vy;
+            },
+            0x3 => { // VX ^= VY
+                let dst = self.reg.get_mut(x);
+                *dst ^= vy;
+            },
+            0x4 => { // VX += VY, carry -> VF
+                let res: u8 = {
+                    let dst = self.reg.get_mut(x);
+                    *dst += vy;
+                    *dst
+                };
+                self.reg.set_flag((res < vy) as u8);
+            },
+            0x5 => { // VX -= VY, borrow -> VF
+                self.reg.set_flag((vy > vx) as u8);
+                let dst = self.reg.get_mut(x);
+                *dst -= vy;
+            },
+            0x6 => { // VX = VY >> 1, VF = LSB(VY)
+                // The documentation + implementations of the shift
+                // instructions for CHIP-8 are inconsistent and
+                // contradictory to say the least. We follow Octo
+                // here, unless the shift quirk says to shift VX.
+                let src = if self.quirks.shift == ShiftVx { vx } else { vy };
+                let res = src >> 1;
+                self.reg.set_flag(src & 0x1);
+                *self.reg.get_mut(x) = res;
+            },
+            0x7 => { // VX = VY - VX, borrow -> VF
+                self.reg.set_flag((vx > vy) as u8);
+                let dst = self.reg.get_mut(x);
+                *dst = vy - *dst;
+            },
+            0xe => { // VX = VY << 1, VF = MSB(VY)
+                // The documentation + implementations of the shift
+                // instructions for CHIP-8 are inconsistent and
+                // contradictory to say the least. We follow Octo
+                // here, unless the shift quirk says to shift VX.
+                let src = if self.quirks.shift == ShiftVx { vx } else { vy };
+                let res = src << 1;
+                self.reg.set_flag((src >> 7) & 0x1);
+                *self.reg.get_mut(x) = res;
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// Where the byte at `addr` is, according to the memory quirk.
+    fn address(&self, addr: uint) -> Result<uint, VmError> {
+        let size = self.mem.map().size;
+        match self.quirks.memory {
+            FaultAddress if addr >= size => Err(OutOfBounds(addr)),
+            _ => Ok(addr % size)
+        }
+    }
+
+    fn read(&self, start: u16, len: uint) -> Result<Vec<u8>, VmError> {
+        let mut bytes = Vec::with_capacity(len);
+        for addr in range(start as uint, start as uint + len) {
+            bytes.push(self.mem.as_slice()[try!(self.address(addr))]);
+        }
+        Ok(bytes)
+    }
+
+    /// Write `bytes` from `start`, or nothing if any of it is out
+    /// of bounds.
+    fn write(&mut self, start: u16, bytes: &[u8]) -> Result<(), VmError> {
+        let mut addrs = Vec::with_capacity(bytes.len());
+        for addr in range(start as uint, start as uint + bytes.len()) {
+            addrs.push(try!(self.address(addr)));
+        }
+        let mem = self.mem.as_mut_slice();
+        for (&addr, &b) in addrs.iter().zip(bytes.iter()) {
+            mem[addr] = b;
+        }
+        Ok(())
+    }
+
+    fn push_return(&mut self, addr: u16) -> Result<(), VmError> {
+        let depth = self.ret_stack.len();
+        match self.quirks.stack_depth {
+            Some(max) if depth >= max => return Err(StackOverflow(depth)),
+            _ => {}
+        }
+        if self.quirks.stack == MemoryStack {
+            // Below the VIP's stack area is the program's own memory,
+            // which a deep enough stack overwrites, as on the VIP.
+            let offset = 2 * (depth + 1);
+            if offset > mem::VIP_STACK_TOP as uint {
+                return Err(StackOverflow(depth));
+            }
+            let bytes = [(addr >> 8) as u8, addr as u8];
+            try!(self.write(mem::VIP_STACK_TOP - offset as u16, bytes.as_slice()));
+        }
+        self.ret_stack.push(addr);
+        Ok(())
+    }
+
+    /// The address to return to. With the stack in memory, that's
+    /// whatever is there now, even if the program changed it.
+    fn pop_return(&mut self) -> Result<u16, VmError> {
+        let depth = self.ret_stack.len();
+        if depth == 0 {
+            return Err(StackUnderflow);
+        }
+        let addr = match self.quirks.stack {
+            MemoryStack => {
+                let bytes = try!(self.read(mem::VIP_STACK_TOP - 2 * depth as u16, 2));
+                (bytes[0] as u16) << 8 | bytes[1] as u16
+            },
+            SeparateStack => self.ret_stack[depth - 1]
+        };
+        self.ret_stack.pop();
+        Ok(addr)
+    }
+
+    fn set_index(&mut self, i: uint) {
+        self.i = match self.quirks.memory {
+            MaskAddress => (i & (self.mem.map().size - 1)) as u16,
+            _ => i as u16
+        };
+    }
+
+    fn misc(&mut self, x: u8, nn: u8) -> Result<(), VmError> {
+        match nn {
+            0x07 => { // set register from delay timer
+                *self.reg.get_mut(x) = self.dt;
+            },
+            0x02 if x == 0 => { // XO-CHIP: load audio pattern from [I]
+                let pattern = try!(self.read(self.i, audio::PATTERN_SIZE));
+                self.voice.load_pattern(pattern.as_slice());
+            },
+            0x0a => { // wait for keypress
+                // Only a key pressed after this point counts; one
+                // already held down must be released and pressed again.
+                self.key_wait = WaitingForPress(x);
+            },
+            0x15 => {
+                self.dt = self.reg.get(x);
+            },
+            0x18 => {
+                self.st = self.reg.get(x);
+            },
+            0x3a => { // XO-CHIP: set audio pitch
+                self.voice.pitch = self.reg.get(x);
+            },
+            0x29 => {
+                self.i = self.mem.font_offset(self.reg.get(x));
+            },
+            0x30 => { // SCHIP: point I at a big digit
+                self.i = self.mem.big_font_offset(self.reg.get(x));
+            },
+            0x33 => { // set [I, I+1, I+2] to BCD repr of VX
+                let val = self.reg.get(x);
+                try!(self.write(self.i, [val / 100, (val / 10) % 10, val % 10].as_slice()));
+            },
+            0x1e => { // I += VX
+                let i = self.i as uint + self.reg.get(x) as uint;
+                if self.quirks.index_overflow == FlagIndexOverflow {
+                    // The Amiga interpreter flags I passing 0xFFF, whatever the memory size
+                    self.reg.set_flag(if i > 0xfff { 1 } else { 0 });
+                }
+                self.set_index(i);
+            },
+            0x55 => { // store registers to memory
+                let regs = self.reg.slice(0, x + 1).to_vec();
+                try!(self.write(self.i, regs.as_slice()));
+                if self.quirks.load_store != KeepIndex {
+                    let new_i = self.i as uint + x as uint + 1;
+                    self.set_index(new_i);
+                }
+            },
+            0x65 => { // load registers from memory
+                let src = try!(self.read(self.i, x as uint + 1));
+                self.reg.mut_slice(0, x + 1).copy_from(src.as_slice());
+                if self.quirks.load_store != KeepIndex {
+                    let new_i = self.i as uint + x as uint + 1;
+                    self.set_index(new_i);
+                }
+            },
+            _ => {
+                try!(self.illegal(0xf000 | (x as u16) << 8 | nn as u16));
+            }
+        }
+        Ok(())
+    }
+
+    /// Log an instruction fries can't run, and stop if the illegal
+    /// instruction quirk says to. The PC is already past it.
+    fn illegal(&mut self, ins: u16) -> Result<(), VmError> {
+        let entry = (self.pc - 2, ins);
+        if self.illegal_seen.insert(entry) {
+            self.illegal.push(entry);
+        }
+        match self.quirks.illegal {
+            HaltOnIllegal => Err(IllegalInstruction(ins)),
+            TrapIllegal => Err(Trapped(ins)),
+            IgnoreIllegal | VipIllegal => Ok(())
+        }
+    }
+
+    /// Whether to run `5XYN` or `9XYN`: always with N = 0, and
+    /// otherwise only as the VIP does, ignoring N.
+    fn run_skip_reg(&mut self, ins: u16) -> Result<bool, VmError> {
+        if ins & 0xf == 0 {
+            return Ok(true);
+        }
+        try!(self.illegal(ins));
+        Ok(self.quirks.illegal == VipIllegal)
+    }
+
+    fn tick(&mut self) -> Result<(), VmError> {
+        let bytes = try!(self.read(self.pc, 2));
+        let (lo, hi) = (bytes[0], bytes[1]);
+        let ins: u16 = (lo as u16) << 8 | hi as u16;
+        let op = (lo >> 4) & 0xf;
+        let x = lo & 0xf;
+        let y = (hi >> 4) & 0xf;
+        let n = hi & 0xf;
+        let nn = hi & 0xff;
+        let nnn = ins & 0xfff;
+
+        debug!("{:04x}", ins);
+        debug!("{}", self.reg);
+
+        self.pc += 2;
+
+        if ins == 0x00e0 { // clear screen
+            self.display.clear();
+            return Ok(());
+        }
+
+        if ins == 0x00ee { // return
+            self.pc = try!(self.pop_return());
+            return Ok(());
+        }
+
+        // match_hex! macro ??
+        match op {
+            0x1 => { // jump
+                self.pc = nnn;
+            },
+            0x2 => { // call
+                let ret = self.pc;
+                try!(self.push_return(ret));
+                self.pc = nnn;
+            },
+            0x3 => { // skip if VX eq NN
+                if self.reg.get(x) == nn {
+                    self.pc += 2;
+                }
+            },
+            0x4 => { // skip if VX ne NN
+                if self.reg.get(x) != nn {
+                    self.pc += 2;
+                }
+            },
+            0x5 => { // skip if VX == VY
+                if try!(self.run_skip_reg(ins)) && self.reg.get(x) == self.reg.get(y) {
+                    self.pc += 2;
+                }
+            },
+            0x6 => { // store
+                *self.reg.get_mut(x) = nn;
+            },
+            0x7 => { // add
+                let r = self.reg.get_mut(x);
+                *r = *r + nn;
+            },
+            0x8 => match n { // math
+                0x0..0x7 | 0xe => self.math_op(x, y, n),
+                _ => try!(self.illegal(ins))
+            },
+            0x9 => { // skip if VX != VY
+                if try!(self.run_skip_reg(ins)) && self.reg.get(x) != self.reg.get(y) {
+                    self.pc += 2
+                }
+            },
+            0xa => { // set index register
+                self.i = nnn;
+            },
+            0xb => { // jump to nnn + v0
+                self.pc = nnn + self.reg.get(0) as u16;
+            },
+            0xc => { // random number
+                *self.reg.get_mut(x) = self.rng.gen::<u8>() & nn;
+            }
+            0xd => { // draw sprite
+                let sprite = try!(self.read(self.i, n as uint));
+                let (vx, vy) = (self.reg.get(x), self.reg.get(y));
+                let flag = if self.display.draw(sprite.as_slice(), vx, vy) { 0x1 } else { 0x0 };
+                self.reg.set_flag(flag);
+            },
+            0xe if nn == 0x9e => { // skip if key in VX is pressed
+                if self.is_key_pressed(self.reg.get(x) as uint) {
+                    self.pc += 2;
+                }
+            },
+            0xe if nn == 0xa1 => { // skip if key in VX is not pressed
+                if !self.is_key_pressed(self.reg.get(x) as uint) {
+                    self.pc += 2;
+                }
+            },
+            0xf => {
+                try!(self.misc(x, nn));
+            },
+            _ => try!(self.illegal(ins))
+        }
+        Ok(())
+    }
+
+    /// Run up to `cycles` instructions, stopping early if the VM
+    /// blocks waiting for a key, or fails.
+    pub fn run_cycles(&mut self, cycles: uint) {
+        for _ in range(0, cycles) {
+            if self.is_blocked() || self.error.is_some() {
+                break;
+            }
+            let pc = self.pc;
+            match self.tick() {
+                Ok(()) => self.cycles += 1,
+                Err(e) => {
+                    self.pc = pc;
+                    self.error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Why the VM stopped, if it has. It runs no further, unless
+    /// it trapped and is resumed.
+    pub fn error<'a>(&'a self) -> Option<&'a VmError> {
+        self.error.as_ref()
+    }
+
+    /// Skip the instruction the VM trapped on, and carry on.
+    pub fn resume(&mut self) {
+        match self.error {
+            Some(Trapped(_)) => {
+                self.error = None;
+                self.pc += 2;
+            },
+            _ => {}
+        }
+    }
+
+    /// Every instruction that couldn't be run, with its address,
+    /// once each in the order they were first reached.
+    pub fn illegal_instructions<'a>(&'a self) -> &'a [(u16, u16)] {
+        self.illegal.as_slice()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn index(&self) -> u16 {
+        self.i
+    }
+
+    /// The instruction that will run next, or 0 if it's out of
+    /// bounds.
+    pub fn next_opcode(&self) -> u16 {
+        match self.read(self.pc, 2) {
+            Ok(ins) => (ins[0] as u16) << 8 | ins[1] as u16,
+            Err(_) => 0
+        }
+    }
+
+    /// Decrement the delay and sound timers. Call at 60 Hz.
+    pub fn tick_timers(&mut self) {
+        if self.dt > 0 { self.dt -= 1 }
+        if self.st > 0 { self.st -= 1 }
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.st > 0
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    pub fn voice<'a>(&'a self) -> &'a Voice {
+        &self.voice
+    }
+
+    pub fn display<'a>(&'a self) -> &'a Display {
+        &self.display
+    }
+
+    pub fn registers<'a>(&'a self) -> &'a Registers {
+        &self.reg
+    }
+
+    fn is_key_pressed(&self, key: uint) -> bool {
+        assert!(key < 16);
+        (self.keys & 1 << key) >> key == 1
+    }
+
+    /// Write the machine state (but not the quirks) to `w`.
+    pub fn save_state(&self, w: &mut Writer) -> IoResult<()> {
+        try!(w.write(STATE_MAGIC));
+        let map = self.mem.map();
+        try!(w.write_be_u32(map.size as u32));
+        try!(w.write_be_u16(map.load_address));
+        try!(w.write_be_u16(map.font_address));
+        try!(w.write_be_u16(self.pc));
+        try!(w.write_be_u16(self.i));
+        try!(w.write_u8(self.dt));
+        try!(w.write_u8(self.st));
+        try!(w.write_be_u16(self.keys));
+        try!(w.write(self.reg.slice(0, 16)));
+        try!(w.write_be_u16(self.ret_stack.len() as u16));
+        for addr in self.ret_stack.iter() {
+            try!(w.write_be_u16(*addr));
+        }
+        try!(w.write(self.mem.as_slice()));
+        for row in self.display.rows().iter() {
+            try!(w.write_be_u64(*row));
+        }
+        let (wait, reg, key) = match self.key_wait {
+            NotWaiting => (0u8, 0u8, 0u8),
+            WaitingForPress(reg) => (1, reg, 0),
+            WaitingForRelease(reg, key) => (2, reg, key as u8),
+        };
+        try!(w.write([wait, reg, key].as_slice()));
+        match self.voice.pattern {
+            Some(ref pattern) => {
+                try!(w.write_u8(1));
+                try!(w.write(pattern.as_slice()));
+            },
+            None => try!(w.write_u8(0))
+        }
+        try!(w.write_u8(self.voice.pitch));
+        Ok(())
+    }
+
+    /// Restore machine state written by `save_state`.
+    pub fn load_state(&mut self, r: &mut Reader) -> IoResult<()> {
+        let bad_state = |detail: &str| IoError {
+            kind: InvalidInput,
+            desc: "invalid save state",
+            detail: Some(detail.to_string()),
+        };
+
+        if try!(r.read_exact(STATE_MAGIC.len())).as_slice() != STATE_MAGIC {
+            return Err(bad_state("not a fries save state"));
+        }
+        let size = try!(r.read_be_u32()) as uint;
+        let (load, font) = (try!(r.read_be_u16()), try!(r.read_be_u16()));
+        {
+            let map = self.mem.map();
+            if size != map.size || load != map.load_address || font != map.font_address {
+                return Err(bad_state("saved with a different memory map"));
+            }
+        }
+        self.pc = try!(r.read_be_u16());
+        self.i = try!(r.read_be_u16());
+        self.dt = try!(r.read_u8());
+        self.st = try!(r.read_u8());
+        self.keys = try!(r.read_be_u16());
+        self.reg.mut_slice(0, 16).copy_from(try!(r.read_exact(16)).as_slice());
+        let depth = try!(r.read_be_u16());
+        self.ret_stack.truncate(0);
+        for _ in range(0, depth) {
+            self.ret_stack.push(try!(r.read_be_u16()));
+        }
+        let mem = try!(r.read_exact(self.mem.as_slice().len()));
+        self.mem.as_mut_slice().copy_from(mem.as_slice());
+        let mut rows = vec![];
+        for _ in range(0, display::ROWS) {
+            rows.push(try!(r.read_be_u64()));
+        }
+        self.display.set_rows(rows.as_slice());
+        let wait = try!(r.read_exact(3));
+        self.key_wait = match wait.as_slice() {
+            [0, _, _] => NotWaiting,
+            [1, reg, _] if reg < 16 => WaitingForPress(reg),
+            [2, reg, key] if reg < 16 && key < 16 => WaitingForRelease(reg, key as uint),
+            _ => return Err(bad_state("bad key wait state"))
+        };
+        self.voice.pattern = match try!(r.read_u8()) {
+            0 => None,
+            _ => {
+                let mut pattern = [0u8, ..audio::PATTERN_SIZE];
+                pattern.copy_from(try!(r.read_exact(audio::PATTERN_SIZE)).as_slice());
+                Some(pattern)
+            }
+        };
+        self.voice.pitch = try!(r.read_u8());
+        Ok(())
+    }
+
+    /// Whether the VM is stopped in an `FX0A` wait for a key.
+    /// Timers keep running while it waits.
+    pub fn is_blocked(&self) -> bool {
+        self.key_wait != NotWaiting
+    }
+
+    fn finish_key_wait(&mut self, reg: u8, key: uint) {
+        *self.reg.get_mut(reg) = key as u8;
+        self.key_wait = NotWaiting;
+    }
+
+    pub fn keydown(&mut self, key: uint) {
+        assert!(key < 16);
+        self.keys |= 1 << key;
+        match self.key_wait {
+            WaitingForPress(reg) => match self.quirks.key_wait {
+                CompleteOnPress => self.finish_key_wait(reg, key),
+                CompleteOnRelease => self.key_wait = WaitingForRelease(reg, key),
+            },
+            _ => {}
+        }
+    }
+
+    pub fn keyup(&mut self, key: uint) {
+        assert!(key < 16);
+        self.keys &= !(1 << key);
+        match self.key_wait {
+            WaitingForRelease(reg, pressed) if pressed == key => {
+                self.finish_key_wait(reg, key);
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Vm, OutOfBounds, StackOverflow, StackUnderflow, IllegalInstruction, Trapped};
+    use clock::{Clock, VirtualClock};
+    use cpu;
+    use font::Font;
+    use mem::{MemoryMap, Rom};
+    use quirks::{Quirks, CompleteOnPress, MaskAddress, FaultAddress, MemoryStack, IgnoreIllegal,
+                 TrapIllegal, VipIllegal, FlagIndexOverflow};
+    use std::cmp::min;
+    use std::default::Default;
+    use std::io::{MemReader, MemWriter};
+    use std::rand::StdRng;
+    use timing::{Timing, PerFrame};
+
+    static MS: u64 = 1000000;
+
+    fn vm(prgm: &[u8]) -> Vm {
+        let mut r = MemReader::new(prgm.to_vec());
+        Vm::new(Rom::from_reader(&mut r).unwrap(), StdRng::new().unwrap())
+    }
+
+    /// Run for `ns` of emulated time, a tick at a time, as a frontend
+    /// keeping up would.
+    fn run_for(vm: &mut Vm, timing: &mut Timing, clock: &mut VirtualClock, ns: u64) {
+        let end = clock.now() + ns;
+        while clock.now() < end {
+            clock.advance(min(end - clock.now(), timing.tick_duration()));
+            for _ in range(0, timing.update(clock.now())) {
+                vm.run_cycles(timing.cycles_for_tick());
+                vm.tick_timers();
+            }
+        }
+    }
+
+    #[test]
+    fn test_timers() {
+        let prgm = [0x60, 0x3c,  // V0 = 60
+                    0xf0, 0x15,  // DT = V0
+                    0xf0, 0x18,  // ST = V0
+                    0xf1, 0x07,  // V1 = DT
+                    0x12, 0x06]; // jump back to V1 = DT
+        let mut vm = vm(prgm.as_slice());
+        let mut clock = VirtualClock::new();
+        let mut timing = Timing::new(PerFrame(10));
+        timing.update(clock.now());
+
+        run_for(&mut vm, &mut timing, &mut clock, 505 * MS); // 30 ticks
+        assert_eq!(vm.delay_timer(), 30);
+        assert_eq!(vm.sound_timer(), 30);
+        assert_eq!(vm.reg.get(cpu::V1), 31);
+        assert!(vm.sound_active());
+
+        run_for(&mut vm, &mut timing, &mut clock, 1000 * MS);
+        assert_eq!(vm.delay_timer(), 0);
+        assert_eq!(vm.reg.get(cpu::V1), 0);
+        assert!(!vm.sound_active());
+    }
+
+    static WAIT_FOR_KEY: [u8, ..6] = [0x60, 0x3c,  // V0 = 60
+                                      0xf0, 0x15,  // DT = V0
+                                      0xf1, 0x0a]; // V1 = wait for key
+
+    #[test]
+    fn test_key_wait_press_then_release() {
+        let mut vm = vm(WAIT_FOR_KEY.as_slice());
+        vm.run_cycles(3);
+        assert!(vm.is_blocked());
+        vm.keydown(0x7);
+        assert!(vm.is_blocked());
+        vm.keydown(0x8);
+        vm.keyup(0x8); // not the key that ended the wait
+        assert!(vm.is_blocked());
+        vm.keyup(0x7);
+        assert!(!vm.is_blocked());
+        assert_eq!(vm.reg.get(cpu::V1), 0x7);
+    }
+
+    #[test]
+    fn test_key_wait_ignores_held_key() {
+        let mut vm = vm(WAIT_FOR_KEY.as_slice());
+        vm.keydown(0x3);
+        vm.run_cycles(3);
+        vm.keyup(0x3);
+        assert!(vm.is_blocked());
+        vm.keydown(0x3);
+        vm.keyup(0x3);
+        assert!(!vm.is_blocked());
+        assert_eq!(vm.reg.get(cpu::V1), 0x3);
+    }
+
+    #[test]
+    fn test_key_wait_complete_on_press() {
+        let mut vm = vm(WAIT_FOR_KEY.as_slice());
+        vm.set_quirks(Quirks { key_wait: CompleteOnPress, ..Default::default() });
+        vm.run_cycles(3);
+        vm.keydown(0xa);
+        assert!(!vm.is_blocked());
+        assert_eq!(vm.reg.get(cpu::V1), 0xa);
+    }
+
+    #[test]
+    fn test_schip_shift_and_load_store() {
+        let prgm = [0x60, 0x81,  // V0 = 0x81
+                    0x61, 0x02,  // V1 = 2
+                    0x80, 0x16,  // V0 >>= V1
+                    0xa3, 0x00,  // I = 0x300
+                    0xf1, 0x55]; // save V0-V1
+        let mut v = vm(prgm.as_slice());
+        v.run_cycles(5);
+        assert_eq!(v.reg.get(cpu::V0), 0x01);
+        assert_eq!(v.index(), 0x302);
+
+        let mut v = vm(prgm.as_slice());
+        v.set_quirks(Quirks::preset("schip").unwrap());
+        v.run_cycles(5);
+        assert_eq!(v.reg.get(cpu::V0), 0x40);
+        assert_eq!(v.reg.get(cpu::VF), 1);
+        assert_eq!(v.index(), 0x300);
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let mut map = MemoryMap::new();
+        map.load_address = 0x600;
+        map.font_address = 0x50;
+        let prgm = vec![0x60, 0x03,  // V0 = 3
+                        0xf0, 0x29,  // I = sprite for V0
+                        0x16, 0x04]; // loop
+        let mut v = Vm::new(Rom::with_map(prgm, map), StdRng::new().unwrap());
+        assert_eq!(v.pc(), 0x600);
+        v.run_cycles(2);
+        assert_eq!(v.index(), 0x5f);
+        assert_eq!(v.mem.slice(0x5f, 0x64), [0xf0, 0x10, 0xf0, 0x10, 0xf0].as_slice());
+    }
+
+    #[test]
+    fn test_fonts() {
+        let prgm = [0x60, 0x07,  // V0 = 7
+                    0xf0, 0x29,  // I = small sprite for V0
+                    0xf0, 0x30,  // I = big sprite for V0
+                    0x12, 0x06]; // loop
+        let mut v = vm(prgm.as_slice());
+        v.set_font(&Font::preset("vip").unwrap());
+        v.run_cycles(2);
+        assert_eq!(v.index(), 35);
+        assert_eq!(v.mem.slice(35, 40), [0xf0, 0x10, 0x10, 0x10, 0x10].as_slice());
+        v.run_cycles(1);
+        assert_eq!(v.index(), 150);
+        assert_eq!(v.mem.slice(150, 152), [0xff, 0xff].as_slice());
+    }
+
+    static BCD_AT_END: [u8, ..8] = [0xaf, 0xfe,  // I = FFE
+                                    0x60, 0x7b,  // V0 = 123
+                                    0xf0, 0x33,  // BCD of V0 to [I]
+                                    0x12, 0x06]; // loop
+
+    #[test]
+    fn test_memory_bounds() {
+        let mut v = vm(BCD_AT_END.as_slice());
+        v.run_cycles(3);
+        assert_eq!(v.mem.slice(0xffe, 0x1000), [1, 2].as_slice());
+        assert_eq!(v.mem.get(0), 3);
+
+        let mut v = vm(BCD_AT_END.as_slice());
+        v.set_quirks(Quirks { memory: FaultAddress, ..Default::default() });
+        v.run_cycles(10);
+        assert_eq!(v.error(), Some(&OutOfBounds(0x1000)));
+        assert_eq!(v.pc(), 0x204);
+        assert_eq!(v.cycles(), 2);
+        assert_eq!(v.mem.get(0xffe), 0);
+
+        let prgm = [0xaf, 0xfe,  // I = FFE
+                    0x61, 0x04,  // V1 = 4
+                    0xf1, 0x1e,  // I += V1
+                    0x12, 0x06]; // loop
+        let mut v = vm(prgm.as_slice());
+        v.set_quirks(Quirks { memory: MaskAddress, ..Default::default() });
+        v.run_cycles(3);
+        assert_eq!(v.index(), 0x002);
+        assert_eq!(v.reg.get(cpu::VF), 0);
+    }
+
+    #[test]
+    fn test_index_overflow() {
+        let prgm = [0x6f, 0x02,  // VF = 2
+                    0xaf, 0xfe,  // I = FFE
+                    0x61, 0x04,  // V1 = 4
+                    0xf1, 0x1e,  // I += V1
+                    0xf1, 0x1e,  // I += V1
+                    0x12, 0x0a]; // loop
+        let mut v = vm(prgm.as_slice());
+        v.run_cycles(4);
+        assert_eq!(v.index(), 0x1002);
+        assert_eq!(v.reg.get(cpu::VF), 2);
+
+        let mut v = vm(prgm.as_slice());
+        v.set_quirks(Quirks { index_overflow: FlagIndexOverflow, ..Default::default() });
+        v.run_cycles(4);
+        assert_eq!(v.reg.get(cpu::VF), 1);
+
+        let mut v = vm(prgm.as_slice());
+        v.set_quirks(Quirks { index_overflow: FlagIndexOverflow, memory: MaskAddress,
+                              ..Default::default() });
+        v.run_cycles(4);
+        assert_eq!(v.index(), 0x002);
+        assert_eq!(v.reg.get(cpu::VF), 1);
+        v.run_cycles(1);
+        assert_eq!(v.index(), 0x006);
+        assert_eq!(v.reg.get(cpu::VF), 0);
+    }
+
+    static RECURSE: [u8, ..4] = [0x70, 0x01,  // V0 += 1
+                                 0x22, 0x00]; // call 200
+
+    #[test]
+    fn test_stack_depth() {
+        let mut v = vm(RECURSE.as_slice());
+        v.set_quirks(Quirks::preset("vip").unwrap());
+        v.run_cycles(100);
+        assert_eq!(v.error(), Some(&StackOverflow(12)));
+        assert_eq!(v.reg.get(cpu::V0), 13);
+        assert_eq!(v.pc(), 0x202);
+
+        let mut v = vm(RECURSE.as_slice());
+        v.run_cycles(100);
+        assert!(v.error().is_none());
+
+        let mut v = vm([0x00, 0xee].as_slice());
+        v.run_cycles(1);
+        assert_eq!(v.error(), Some(&StackUnderflow));
+    }
+
+    #[test]
+    fn test_memory_stack() {
+        let prgm = [0x22, 0x04,  // call 204
+                    0x12, 0x02,  // loop
+                    0xae, 0xcf,  // I = ECF, the return address's low byte
+                    0x60, 0x06,  // V0 = 6
+                    0xf0, 0x55,  // store V0 over its low byte
+                    0x00, 0xee]; // return, to 206
+        let mut v = vm(prgm.as_slice());
+        v.set_quirks(Quirks { stack: MemoryStack, ..Default::default() });
+        v.run_cycles(1);
+        assert_eq!(v.mem.slice(0xece, 0xed0), [0x02, 0x02].as_slice());
+        v.run_cycles(4);
+        assert_eq!(v.pc(), 0x206);
+    }
+
+    static UNDEFINED: [u8, ..10] = [0x60, 0x00,  // V0 = 0
+                                    0x50, 0x11,  // 5XY1: skip if V0 == V1, as the VIP does
+                                    0x61, 0x01,  // V1 = 1
+                                    0x80, 0x18,  // 8XY8
+                                    0x12, 0x08]; // loop
+
+    #[test]
+    fn test_illegal_instructions() {
+        let mut v = vm(UNDEFINED.as_slice());
+        v.run_cycles(10);
+        assert_eq!(v.error(), Some(&IllegalInstruction(0x5011)));
+        assert_eq!(v.pc(), 0x202);
+
+        let mut v = vm(UNDEFINED.as_slice());
+        v.set_quirks(Quirks { illegal: IgnoreIllegal, ..Default::default() });
+        v.run_cycles(10);
+        assert!(v.error().is_none());
+        assert_eq!(v.reg.get(cpu::V1), 1);
+        assert_eq!(v.illegal_instructions(), [(0x202, 0x5011), (0x206, 0x8018)].as_slice());
+
+        let mut v = vm(UNDEFINED.as_slice());
+        v.set_quirks(Quirks { illegal: VipIllegal, ..Default::default() });
+        v.run_cycles(10);
+        assert_eq!(v.reg.get(cpu::V1), 0);
+        assert_eq!(v.illegal_instructions().len(), 2);
+
+        let mut v = vm(UNDEFINED.as_slice());
+        v.set_quirks(Quirks { illegal: TrapIllegal, ..Default::default() });
+        v.run_cycles(10);
+        assert_eq!(v.error(), Some(&Trapped(0x5011)));
+        v.resume();
+        v.run_cycles(2);
+        assert_eq!(v.error(), Some(&Trapped(0x8018)));
+        assert_eq!(v.pc(), 0x206);
+    }
+
+    #[test]
+    fn test_save_state_roundtrip() {
+        let mut vm1 = vm(WAIT_FOR_KEY.as_slice());
+        vm1.run_cycles(3);
+        vm1.keydown(0x4);
+        let mut w = MemWriter::new();
+        vm1.save_state(&mut w).unwrap();
+
+        let mut vm2 = vm(b"");
+        vm2.load_state(&mut MemReader::new(w.unwrap())).unwrap();
+        assert_eq!(vm2.pc(), vm1.pc());
+        assert_eq!(vm2.delay_timer(), 60);
+        assert!(vm2.is_blocked());
+        vm2.keyup(0x4);
+        assert_eq!(vm2.reg.get(cpu::V1), 0x4);
+    }
+
+    #[test]
+    fn test_load_state_rejects_garbage() {
+        let mut vm = vm(b"");
+        assert!(vm.load_state(&mut MemReader::new(b"not a state".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_load_state_checks_memory_map() {
+        let mut map = MemoryMap::new();
+        map.size = 0x10000;
+        let big = Vm::new(Rom::with_map(vec![0x12, 0x00], map), StdRng::new().unwrap());
+        let mut w = MemWriter::new();
+        big.save_state(&mut w).unwrap();
+
+        let mut small = vm(b"");
+        assert!(small.load_state(&mut MemReader::new(w.unwrap())).is_err());
+    }
+
+    #[test]
+    fn test_timers_run_during_key_wait() {
+        let mut vm = vm(WAIT_FOR_KEY.as_slice());
+        let mut clock = VirtualClock::new();
+        let mut timing = Timing::new(PerFrame(10));
+        timing.update(clock.now());
+        run_for(&mut vm, &mut timing, &mut clock, 505 * MS); // 30 ticks
+        assert!(vm.is_blocked());
+        assert_eq!(vm.delay_timer(), 30);
+        run_for(&mut vm, &mut timing, &mut clock, 1000 * MS);
+        assert!(vm.is_blocked());
+        assert_eq!(vm.delay_timer(), 0);
+    }
+}